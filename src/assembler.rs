@@ -0,0 +1,774 @@
+use crate::{
+    cpu::{Cpu, SEGMENT_NAMES},
+    decoder::{REG16_NAMES, REG8_NAMES},
+    error::{Error, Position},
+    object::{self, FixupKind, Module, Target},
+    parser::{parse_num, Parser},
+    token::Token,
+};
+
+/// Segment assembled programs are loaded into. DS stays 0 so `[addr]`
+/// operands reach the same bytes as in the interpreter.
+pub const LOAD_SEGMENT: u16 = 0x1000;
+
+/// Offset of the first instruction within `LOAD_SEGMENT`.
+pub const LOAD_OFFSET: u16 = 0x100;
+
+/// Bytes shown on each row of a `.LST` listing.
+const LST_BYTES_PER_ROW: usize = 6;
+
+/// The machine code produced by one source line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Line number, counting from 1.
+    pub number: usize,
+    /// Index into `Program::segments`, 0 outside of any segment.
+    pub segment: usize,
+    pub offset: u16,
+    pub bytes: Vec<u8>,
+    pub source: String,
+}
+
+/// A `name SEGMENT 'class'` block. Programs without any get an implicit
+/// `_TEXT` segment starting at the origin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentDef {
+    pub name: String,
+    pub class: String,
+    /// Offset of the segment's first byte, moved by an ORG before any code.
+    pub start: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    pub segment: usize,
+    pub offset: u16,
+}
+
+/// A 16-bit field in `lines[line].bytes[at..]` that refers to a symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub line: usize,
+    pub at: usize,
+    pub kind: FixupKind,
+    pub symbol: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub origin: u16,
+    pub lines: Vec<Line>,
+    pub segments: Vec<SegmentDef>,
+    pub labels: Vec<Label>,
+    pub publics: Vec<String>,
+    pub externs: Vec<String>,
+    pub references: Vec<Reference>,
+}
+
+impl Program {
+    /// The flat image starting at `origin`, gaps left by ORG filled with 0.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+
+        for l in self.lines.iter().filter(|l| !l.bytes.is_empty()) {
+            let at = l.offset.wrapping_sub(self.origin) as usize;
+
+            out.resize(out.len().max(at), 0);
+            out.extend(&l.bytes);
+        }
+
+        out
+    }
+
+    /// Offset, bytes and source of every line that produced code.
+    pub fn listing(&self) -> String {
+        self.lines
+            .iter()
+            .filter(|l| !l.bytes.is_empty())
+            .map(|l| {
+                let hex: Vec<String> = l.bytes.iter().map(|b| format!("{b:02X}")).collect();
+                format!("{:04X}  {:<12}{}", l.offset, hex.join(" "), l.source.trim())
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// A `.LST` listing: every source line with its number, offset and
+    /// bytes, six to a row, then the segments and symbols. Fields a linker
+    /// fills in are marked with `R`.
+    pub fn lst(&self) -> String {
+        let mut out = format!("{:>5}  {:<4}  {:<18}  Source\n", "Line", "Addr", "Code");
+
+        for (i, l) in self.lines.iter().enumerate() {
+            let relocated = self.references.iter().any(|r| r.line == i && !self.is_local(r));
+            let hex: Vec<String> = l.bytes.iter().map(|b| format!("{b:02X}")).collect();
+            let mut rows = hex.chunks(LST_BYTES_PER_ROW).map(|c| c.join(" "));
+
+            let addr = if l.bytes.is_empty() { String::new() } else { format!("{:04X}", l.offset) };
+            let first = rows.next().unwrap_or_default() + if relocated { " R" } else { "" };
+            out += &format!("{:>5}  {addr:<4}  {first:<18}  {}\n", l.number, l.source.trim_end());
+
+            for (r, row) in rows.enumerate() {
+                let at = l.offset.wrapping_add(((r + 1) * LST_BYTES_PER_ROW) as u16);
+                out += &format!("{:>5}  {at:04X}  {row}\n", "");
+            }
+        }
+
+        out += "\nSegments:\n";
+        for (i, s) in self.segments.iter().enumerate() {
+            let end = self.lines.iter().filter(|l| l.segment == i).map(|l| l.offset.wrapping_add(l.bytes.len() as u16)).max();
+            let len = end.unwrap_or(s.start).wrapping_sub(s.start);
+            out += &format!("  {:<16}{:04X} bytes  {}\n", s.name, len, s.class);
+        }
+
+        out += "\nSymbols:\n";
+        for l in &self.labels {
+            let public = if self.publics.contains(&l.name) { "  public" } else { "" };
+            out += &format!("  {:<16}{}:{:04X}{public}\n", l.name, self.segments[l.segment].name, l.offset);
+        }
+        for e in &self.externs {
+            out += &format!("  {e:<16}external\n");
+        }
+
+        out
+    }
+
+    /// Number of the source line whose code covers `offset`.
+    pub fn line_at(&self, offset: u16) -> Option<usize> {
+        self.lines
+            .iter()
+            .find(|l| offset.wrapping_sub(l.offset) < l.bytes.len() as u16)
+            .map(|l| l.number)
+    }
+
+    pub fn label(&self, name: &str) -> Option<&Label> {
+        self.labels.iter().find(|l| l.name == name)
+    }
+
+    /// True if the assembler could fill in `r` itself: a near jump or an
+    /// offset within the segment it is in.
+    fn is_local(&self, r: &Reference) -> bool {
+        let segment = self.lines[r.line].segment;
+
+        match (r.kind, self.label(&r.symbol)) {
+            (FixupKind::Relative | FixupKind::Offset, Some(l)) => l.segment == segment,
+            _ => false,
+        }
+    }
+
+    /// Checks that `bytes` is the whole program: one segment and no symbol
+    /// left for a linker.
+    pub fn check_flat(&self) -> Result<(), String> {
+        if self.segments.len() > 1 {
+            return Err("a flat image needs a single segment, link an .OBJ instead".to_string());
+        }
+
+        match self.references.iter().find(|r| !self.is_local(r)) {
+            Some(r) if r.kind == FixupKind::Base => Err(format!("SEG {} needs an .EXE", r.symbol)),
+            Some(r) => Err(format!("unresolved external {}", r.symbol)),
+            None => Ok(()),
+        }
+    }
+
+    /// The program as an object module. Offsets count from the start of
+    /// each segment, and every reference the assembler could not settle
+    /// becomes a fixup with its field zeroed.
+    pub fn module(&self, name: &str) -> Result<Module, String> {
+        let mut segments: Vec<object::Segment> = self
+            .segments
+            .iter()
+            .map(|s| object::Segment {
+                name: s.name.clone(),
+                class: s.class.clone(),
+                data: vec![],
+            })
+            .collect();
+
+        for l in self.lines.iter().filter(|l| !l.bytes.is_empty()) {
+            let data = &mut segments[l.segment].data;
+            let at = l.offset.wrapping_sub(self.segments[l.segment].start) as usize;
+
+            data.resize(data.len().max(at), 0);
+            data.extend(&l.bytes);
+        }
+
+        let relative = |l: &Label| l.offset.wrapping_sub(self.segments[l.segment].start);
+
+        let publics = self
+            .publics
+            .iter()
+            .map(|p| {
+                let l = self.label(p).ok_or_else(|| format!("public {p} is not defined"))?;
+                Ok(object::Public {
+                    name: p.clone(),
+                    segment: l.segment,
+                    offset: relative(l),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut fixups = vec![];
+
+        for r in &self.references {
+            let line = &self.lines[r.line];
+
+            if r.kind == FixupKind::Relative && self.is_local(r) {
+                continue;
+            }
+
+            let target = match (self.label(&r.symbol), self.externs.iter().position(|e| *e == r.symbol)) {
+                (Some(l), _) => Target::Segment(l.segment, relative(l)),
+                (None, Some(e)) => Target::External(e),
+                (None, None) => return Err(format!("line {}: undefined symbol {}", line.number, r.symbol)),
+            };
+
+            let offset = line.offset.wrapping_sub(self.segments[line.segment].start) + r.at as u16;
+            segments[line.segment].data[offset as usize..offset as usize + 2].fill(0);
+
+            fixups.push(object::Fixup {
+                segment: line.segment,
+                offset,
+                kind: r.kind,
+                target,
+            });
+        }
+
+        Ok(Module {
+            name: name.to_string(),
+            segments,
+            publics,
+            externs: self.externs.clone(),
+            fixups,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    Reg8(u8),
+    Reg16(u8),
+    Seg(u8),
+    Imm(u16),
+    Mem(u16),
+}
+
+fn arg(t: &Token) -> Result<Arg, Error> {
+    let position = |names: &[&str], n: &str| names.iter().position(|r| *r == n).map(|i| i as u8);
+
+    match t {
+        Token::Reg(n) => position(&REG8_NAMES, n)
+            .map(Arg::Reg8)
+            .or_else(|| position(&REG16_NAMES, n).map(Arg::Reg16))
+            .or_else(|| position(&SEGMENT_NAMES, n).map(Arg::Seg))
+            .ok_or_else(|| Error::operands(format!("unknown register {n}"))),
+        Token::Num(n) => u16::try_from(*n)
+            .map(Arg::Imm)
+            .map_err(|_| Error::operands(format!("{n:X}h does not fit in a word"))),
+        Token::Mem(a) => u16::try_from(*a)
+            .map(Arg::Mem)
+            .map_err(|_| Error::operands(format!("memory operand {a:X}h is outside the 64K data segment"))),
+        t => Err(Error::operands(format!("unexpected operand {t:?}"))),
+    }
+}
+
+/// ModR/M byte, and displacement for memory, of `rm` with `reg` in the reg
+/// field.
+fn modrm(reg: u8, rm: Arg) -> Result<Vec<u8>, Error> {
+    match rm {
+        Arg::Reg8(r) | Arg::Reg16(r) => Ok(vec![0xC0 | reg << 3 | r]),
+        Arg::Mem(a) => {
+            let [lo, hi] = a.to_le_bytes();
+            Ok(vec![reg << 3 | 6, lo, hi])
+        }
+        rm => Err(Error::operands(format!("{rm:?} cannot be addressed by ModR/M"))),
+    }
+}
+
+/// An immediate for a byte operand.
+fn byte(i: u16) -> Result<u8, Error> {
+    u8::try_from(i).map_err(|_| Error::operands(format!("{i:X}h does not fit in a byte")))
+}
+
+fn with(opcode: u8, rest: Vec<u8>) -> Vec<u8> {
+    let mut v = vec![opcode];
+    v.extend(rest);
+    v
+}
+
+/// Index of an ALU mnemonic in the 00h-3Fh opcode block and the 80h group.
+fn alu_index(name: &str) -> Option<u8> {
+    ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"]
+        .iter()
+        .position(|n| *n == name)
+        .map(|i| i as u8)
+}
+
+/// Encodes one parsed instruction. The two operand `MUL` and `DIV` forms need
+/// the accumulator on the left, as the 8086 leaves their result in AX.
+pub fn encode(token: &Token) -> Result<Vec<u8>, Error> {
+    use Arg::*;
+
+    let Token::Instruction { name, left, right } = token else {
+        return Err(Error::syntax(format!("expected an instruction, found {token:?}")));
+    };
+
+    let name = name.to_lowercase();
+    let l = arg(left)?;
+    let r = right.as_deref().map(arg).transpose()?;
+
+    let bytes = match (name.as_str(), l, r) {
+        (n, _, Some(r)) if alu_index(n).is_some() => {
+            let op = alu_index(n).unwrap();
+            let base = op << 3;
+
+            match (l, r) {
+                (Reg8(0), Imm(i)) => vec![base | 4, byte(i)?],
+                (Reg8(_) | Mem(_), Imm(i)) => with(0x80, [modrm(op, l)?, vec![byte(i)?]].concat()),
+                // 83h sign-extends a byte
+                (Reg16(_), Imm(i)) if !(0x80..0xFF80).contains(&i) => {
+                    with(0x83, [modrm(op, l)?, vec![i as u8]].concat())
+                }
+                (Reg16(_), Imm(i)) => with(0x81, [modrm(op, l)?, i.to_le_bytes().to_vec()].concat()),
+                (Reg8(_) | Mem(_), Reg8(s)) => with(base, modrm(s, l)?),
+                (Reg8(d), Mem(_)) => with(base | 2, modrm(d, r)?),
+                (Reg16(_), Reg16(s)) => with(base | 1, modrm(s, l)?),
+                _ => return Err(Error::operands(format!("invalid operands for {}", name.to_uppercase()))),
+            }
+        }
+        ("mov", Reg8(d), Some(Imm(i))) => vec![0xB0 | d, byte(i)?],
+        ("mov", Reg16(d), Some(Imm(i))) => with(0xB8 | d, i.to_le_bytes().to_vec()),
+        ("mov", Mem(_), Some(Imm(i))) => with(0xC6, [modrm(0, l)?, vec![byte(i)?]].concat()),
+        ("mov", Reg8(0), Some(Mem(a))) => with(0xA0, a.to_le_bytes().to_vec()),
+        ("mov", Mem(a), Some(Reg8(0))) => with(0xA2, a.to_le_bytes().to_vec()),
+        ("mov", Reg8(_) | Mem(_), Some(Reg8(s))) => with(0x88, modrm(s, l)?),
+        ("mov", Reg8(d), Some(Mem(_))) => with(0x8A, modrm(d, r.unwrap())?),
+        ("mov", Reg16(_), Some(Reg16(s))) => with(0x89, modrm(s, l)?),
+        ("mov", Seg(1), _) => return Err(Error::operands("CS cannot be the target of MOV")),
+        ("mov", Seg(s), Some(src @ Reg16(_))) => with(0x8E, modrm(s, src)?),
+        ("mov", Reg16(_), Some(Seg(s))) => with(0x8C, modrm(s, l)?),
+        ("xchg", Reg8(_) | Mem(_), Some(Reg8(s))) => with(0x86, modrm(s, l)?),
+        ("xchg", Reg16(0), Some(Reg16(s))) => vec![0x90 | s],
+        ("xchg", Reg16(_), Some(Reg16(s))) => with(0x87, modrm(s, l)?),
+        ("mul" | "div", Reg8(0), Some(src @ (Reg8(_) | Mem(_)))) => {
+            with(0xF6, modrm(if name == "mul" { 4 } else { 6 }, src)?)
+        }
+        ("mul" | "div", Reg16(0), Some(src @ Reg16(_))) => {
+            with(0xF7, modrm(if name == "mul" { 4 } else { 6 }, src)?)
+        }
+        ("mul" | "div", Reg8(_) | Mem(_), None) => with(0xF6, modrm(if name == "mul" { 4 } else { 6 }, l)?),
+        ("mul" | "div", Reg16(_), None) => with(0xF7, modrm(if name == "mul" { 4 } else { 6 }, l)?),
+        ("mul" | "div", _, _) => {
+            return Err(Error::operands(format!("{} needs AL or AX on the left", name.to_uppercase())))
+        }
+        ("inc", Reg16(d), None) => vec![0x40 | d],
+        ("dec", Reg16(d), None) => vec![0x48 | d],
+        ("inc", Reg8(_) | Mem(_), None) => with(0xFE, modrm(0, l)?),
+        ("dec", Reg8(_) | Mem(_), None) => with(0xFE, modrm(1, l)?),
+        ("not", Reg8(_) | Mem(_), None) => with(0xF6, modrm(2, l)?),
+        ("not", Reg16(_), None) => with(0xF7, modrm(2, l)?),
+        ("int", Imm(n), None) => vec![0xCD, byte(n)?],
+        ("in", Reg8(0), Some(Imm(p))) => vec![0xE4, byte(p)?],
+        ("out", Imm(p), Some(Reg8(0))) => vec![0xE6, byte(p)?],
+        _ => return Err(Error::operands(format!("invalid operands for {}", name.to_uppercase()))),
+    };
+
+    Ok(bytes)
+}
+
+/// Address given to an `ORG` directive on this line, if it has one.
+fn org(source: &str) -> Option<Result<u16, String>> {
+    let mut words = source.split_whitespace();
+
+    if !words.next()?.eq_ignore_ascii_case("org") {
+        return None;
+    }
+
+    let addr = words.next().and_then(parse_num).filter(|a| *a <= 0xFFFF);
+    Some(addr.map(|a| a as u16).ok_or_else(|| "ORG needs an address below 10000h".to_string()))
+}
+
+/// A symbol name in upper case, if `s` is one and not a register.
+fn symbol(s: &str) -> Option<String> {
+    let name = s.to_uppercase();
+    let mut chars = name.chars();
+    let first = chars.next()?;
+
+    let valid = (first.is_ascii_alphabetic() || "_@$".contains(first))
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_@$".contains(c));
+    let register = [&REG8_NAMES[..], &REG16_NAMES, &SEGMENT_NAMES].iter().any(|n| n.contains(&name.as_str()));
+
+    (valid && !register).then_some(name)
+}
+
+/// Splits a `name:` label off the front of a line.
+fn label(source: &str) -> (Option<String>, &str) {
+    match source.split_once(':') {
+        Some((name, rest)) => match symbol(name.trim()) {
+            Some(name) if !name.is_empty() => (Some(name), rest),
+            _ => (None, source),
+        },
+        None => (None, source),
+    }
+}
+
+/// Directives that shape the program rather than produce code.
+enum Directive {
+    Public(Vec<String>),
+    Extern(Vec<String>),
+    Segment(String, String),
+    Ends(String),
+    End,
+}
+
+fn names(list: &str) -> Result<Vec<String>, String> {
+    list.split(',')
+        .map(|n| {
+            // EXTRN takes a type after the name, which near calls ignore
+            let n = n.split(':').next().unwrap_or_default().trim();
+            symbol(n).ok_or_else(|| format!("invalid symbol name {n:?}"))
+        })
+        .collect()
+}
+
+fn directive(source: &str) -> Option<Result<Directive, String>> {
+    let words: Vec<&str> = source.split_whitespace().collect();
+    let rest = |n: usize| words[n..].join(" ");
+
+    let d = match words.as_slice() {
+        [d, ..] if d.eq_ignore_ascii_case("public") => names(&rest(1)).map(Directive::Public),
+        [d, ..] if d.eq_ignore_ascii_case("extrn") || d.eq_ignore_ascii_case("extern") => {
+            names(&rest(1)).map(Directive::Extern)
+        }
+        [d, ..] if d.eq_ignore_ascii_case("end") => Ok(Directive::End),
+        [name, d, ..] if d.eq_ignore_ascii_case("segment") => match symbol(name) {
+            Some(name) => {
+                let class = words[2..]
+                    .iter()
+                    .find(|w| w.starts_with('\''))
+                    .map(|w| w.trim_matches('\'').to_uppercase())
+                    .unwrap_or_default();
+                Ok(Directive::Segment(name, class))
+            }
+            None => Err(format!("invalid segment name {name}")),
+        },
+        [name, d] if d.eq_ignore_ascii_case("ends") => Ok(Directive::Ends(name.to_uppercase())),
+        _ => return None,
+    };
+
+    Some(d)
+}
+
+/// Machine code and the symbol its last two bytes refer to.
+type Symbolic = (Vec<u8>, Option<(FixupKind, String)>);
+
+/// Instructions with a symbol operand the parser does not know: near
+/// `CALL` and `JMP`, `RET`, and `MOV r16, OFFSET sym` or `SEG sym`. The
+/// field to fill in is always the last two bytes.
+fn symbolic(source: &str) -> Option<Result<Symbolic, String>> {
+    let (name, operands) = source.trim().split_once(char::is_whitespace).unwrap_or((source.trim(), ""));
+    let operands: Vec<&str> = operands.split(',').map(str::trim).collect();
+    let name = name.to_lowercase();
+
+    let (opcode, kind, target) = match (name.as_str(), operands.as_slice()) {
+        ("ret", [""]) => return Some(Ok((vec![0xC3], None))),
+        ("call" | "jmp", [target]) => {
+            let opcode = if name == "call" { 0xE8 } else { 0xE9 };
+            (opcode, FixupKind::Relative, *target)
+        }
+        ("mov", [reg, operand]) => {
+            let (kind, target) = operand.split_once(char::is_whitespace)?;
+            let kind = match kind.to_lowercase().as_str() {
+                "offset" => FixupKind::Offset,
+                "seg" => FixupKind::Base,
+                _ => return None,
+            };
+
+            match REG16_NAMES.iter().position(|r| r.eq_ignore_ascii_case(reg)) {
+                Some(r) => (0xB8 | r as u8, kind, target.trim()),
+                None => return Some(Err(format!("{} needs a word register", name.to_uppercase()))),
+            }
+        }
+        _ => return None,
+    };
+
+    Some(match symbol(target) {
+        Some(target) => Ok((vec![opcode, 0, 0], Some((kind, target)))),
+        None => Err(format!("invalid symbol name {target:?}")),
+    })
+}
+
+/// The segment code goes to. Code outside an explicit segment opens an
+/// implicit one, unless the program has segments of its own.
+fn open(program: &mut Program, offsets: &mut Vec<u16>, current: &mut Option<usize>, origin: u16) -> Result<usize, String> {
+    match *current {
+        Some(s) => Ok(s),
+        None if program.segments.is_empty() => {
+            program.segments.push(SegmentDef {
+                name: "_TEXT".to_string(),
+                class: "CODE".to_string(),
+                start: origin,
+            });
+            offsets.push(origin);
+            *current = Some(0);
+            Ok(0)
+        }
+        None => Err("code outside a segment".to_string()),
+    }
+}
+
+/// Assembles `src` line by line, the first instruction placed at `origin`
+/// unless an `ORG` before it says otherwise. A later `ORG` may only move
+/// forward; the gap is filled with zeros.
+///
+/// Lines may start with a `label:`, and `PUBLIC`, `EXTRN`, `SEGMENT`,
+/// `ENDS` and `END` lay out the program for the linker. Near jumps and
+/// offsets within a segment are filled in here, everything else is left
+/// in `references`.
+pub fn assemble(src: &str, mut origin: u16) -> Result<Program, Error> {
+    let mut program = Program {
+        origin,
+        lines: vec![],
+        segments: vec![],
+        labels: vec![],
+        publics: vec![],
+        externs: vec![],
+        references: vec![],
+    };
+    let mut offsets: Vec<u16> = vec![];
+    let mut current: Option<usize> = None;
+
+    for (i, source) in src.lines().enumerate() {
+        let number = i + 1;
+        let err = |e: String| Error::syntax(e).at(Position::new(number, 0));
+        let mut bytes = vec![];
+        let mut reference = None;
+
+        if let Some(d) = directive(source) {
+            match d.map_err(err)? {
+                Directive::Public(names) => program.publics.extend(names),
+                Directive::Extern(names) => program.externs.extend(names),
+                Directive::Segment(name, class) => {
+                    if program.segments.iter().any(|s| s.name == name) {
+                        return Err(err(format!("segment {name} is already defined")));
+                    }
+
+                    program.segments.push(SegmentDef { name, class, start: 0 });
+                    offsets.push(0);
+                    current = Some(program.segments.len() - 1);
+                }
+                Directive::Ends(name) => match current {
+                    Some(s) if program.segments[s].name == name => current = None,
+                    _ => return Err(err(format!("{name} ENDS without a matching SEGMENT"))),
+                },
+                Directive::End => break,
+            }
+        } else if let Some(addr) = org(source) {
+            let addr = addr.map_err(err)?;
+
+            match current {
+                None if program.segments.is_empty() => origin = addr,
+                None => return Err(err("ORG outside a segment".to_string())),
+                Some(s) => {
+                    if program.lines.iter().all(|l| l.segment != s || l.bytes.is_empty()) {
+                        program.segments[s].start = addr;
+                    } else if addr < offsets[s] {
+                        return Err(err(format!("ORG cannot move back to {addr:X}h")));
+                    }
+
+                    offsets[s] = addr;
+                }
+            }
+        } else {
+            let (name, rest) = label(source);
+
+            if let Some(name) = name {
+                let segment = open(&mut program, &mut offsets, &mut current, origin).map_err(err)?;
+
+                if program.label(&name).is_some() {
+                    return Err(err(format!("label {name} is already defined")));
+                }
+                program.labels.push(Label {
+                    name,
+                    segment,
+                    offset: offsets[segment],
+                });
+            }
+
+            if let Some(code) = symbolic(rest) {
+                let (code, r) = code.map_err(err)?;
+                bytes = code;
+                reference = r;
+            } else {
+                // parser columns count from the start of the label's rest
+                let indent = source.len() - rest.len();
+                let mut parser = Parser::new(rest);
+
+                while let Some(token) = parser.next() {
+                    let at = parser.location();
+                    let code = token.and_then(|t| encode(&t).map_err(|e| e.at(at))).map_err(|e| {
+                        let column = e.position().column + indent;
+                        e.at(Position::new(number, column))
+                    })?;
+                    bytes.extend(code);
+                }
+            }
+        }
+
+        let segment = match (bytes.is_empty(), current) {
+            (true, s) => s.unwrap_or(0),
+            (false, _) => open(&mut program, &mut offsets, &mut current, origin).map_err(err)?,
+        };
+        let offset = offsets.get(segment).copied().unwrap_or(origin);
+
+        if let Some((kind, symbol)) = reference {
+            program.references.push(Reference {
+                line: program.lines.len(),
+                at: bytes.len() - 2,
+                kind,
+                symbol,
+            });
+        }
+
+        program.lines.push(Line {
+            number,
+            segment,
+            offset,
+            source: source.to_string(),
+            bytes,
+        });
+
+        if let Some(o) = offsets.get_mut(segment) {
+            *o = o.wrapping_add(program.lines.last().unwrap().bytes.len() as u16);
+        }
+    }
+
+    // fill in what needs no linker, and catch names nobody defines
+    let mut patches = vec![];
+
+    for r in &program.references {
+        let line = &program.lines[r.line];
+
+        match program.label(&r.symbol) {
+            Some(l) if program.is_local(r) => {
+                let value = match r.kind {
+                    FixupKind::Relative => l.offset.wrapping_sub(line.offset.wrapping_add(r.at as u16 + 2)),
+                    _ => l.offset,
+                };
+                patches.push((r.line, r.at, value));
+            }
+            None if !program.externs.contains(&r.symbol) => {
+                let e = Error::syntax(format!("undefined symbol {}", r.symbol));
+                return Err(e.at(Position::new(line.number, 0)));
+            }
+            _ => {}
+        }
+    }
+
+    for (line, at, value) in patches {
+        program.lines[line].bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    program.origin = program.segments.first().map_or(origin, |s| s.start);
+    Ok(program)
+}
+
+impl Cpu {
+    /// Places an assembled program at `LOAD_SEGMENT:origin` followed by a HLT
+    /// and points CS:IP at it.
+    pub fn load_program(&mut self, program: &Program) {
+        let mut code = program.bytes();
+        code.push(0xF4);
+
+        self.load_code(LOAD_SEGMENT, program.origin, &code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, encode, LOAD_OFFSET};
+    use crate::{
+        cpu::{Cpu, State},
+        error::{Error, Position},
+        parser::Parser,
+    };
+
+    fn bytes(s: &str) -> Vec<u8> {
+        encode(&Parser::new(s).next().unwrap().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn encodings() {
+        assert_eq!(bytes("MOV AL, 5"), [0xB0, 0x05]);
+        assert_eq!(bytes("MOV BH, CL"), [0x88, 0xCF]);
+        assert_eq!(bytes("MOV AX, BX"), [0x89, 0xD8]);
+        assert_eq!(bytes("MOV DS, AX"), [0x8E, 0xD8]);
+        assert_eq!(bytes("MOV [1234h], AL"), [0xA2, 0x34, 0x12]);
+        assert_eq!(bytes("MOV [10h], 7"), [0xC6, 0x06, 0x10, 0x00, 0x07]);
+        assert_eq!(bytes("MOV CL, [200h]"), [0x8A, 0x0E, 0x00, 0x02]);
+        assert_eq!(bytes("ADD AL, 10"), [0x04, 0x0A]);
+        assert_eq!(bytes("XOR BL, 0FFh"), [0x80, 0xF3, 0xFF]);
+        assert_eq!(bytes("SUB DH, AH"), [0x28, 0xE6]);
+        assert_eq!(bytes("MUL AL, BL"), [0xF6, 0xE3]);
+        assert_eq!(bytes("INC CH"), [0xFE, 0xC5]);
+        assert_eq!(bytes("INT 10h"), [0xCD, 0x10]);
+        assert_eq!(bytes("OUT 43h, AL"), [0xE6, 0x43]);
+        assert_eq!(bytes("MOV AX, 1234h"), [0xB8, 0x34, 0x12]);
+        assert_eq!(bytes("ADD CX, 200h"), [0x81, 0xC1, 0x00, 0x02]);
+        assert_eq!(bytes("SUB DX, 0FFFFh"), [0x83, 0xEA, 0xFF]);
+    }
+
+    #[test]
+    fn rejects_what_8086_cannot_encode() {
+        let p = assemble("MOV AL, 1\nL: MOV [0B8000h], AL", 0);
+        assert_eq!(
+            p.unwrap_err(),
+            Error::OperandMismatch {
+                position: Position::new(2, 4),
+                message: "memory operand B8000h is outside the 64K data segment".into(),
+            }
+        );
+
+        assert!(matches!(assemble("MUL BL, 2", 0), Err(Error::OperandMismatch { .. })));
+        assert!(matches!(assemble("MOV AL, 300", 0), Err(Error::OperandMismatch { .. })));
+        assert!(matches!(assemble("MOV AX, 10000h", 0), Err(Error::OperandMismatch { .. })));
+        assert!(matches!(assemble("PUSH AX", 0), Err(Error::UnknownMnemonic { .. })));
+
+        let p = assemble("MOV AL, 1\n\nINT 10h", 0x100).unwrap();
+        assert_eq!([0x101, 0x102, 0x104].map(|o| p.line_at(o)), [Some(1), Some(3), None]);
+        assert_eq!(assemble("DIV CH", 0).unwrap().bytes(), [0xF6, 0xF5]);
+        assert!(assemble("MOV CS, AX", 0).is_err());
+    }
+
+    #[test]
+    fn lst_has_symbols() {
+        let src = "PUBLIC MAIN\nEXTRN PUTC\nMAIN: MOV [10h], 7\nMOV CL, [200h]\nCALL PUTC\nJMP MAIN";
+        let lst = assemble(src, 0x100).unwrap().lst();
+        let lines: Vec<&str> = lst.lines().collect();
+
+        assert_eq!(lines[3], "    3  0100  C6 06 10 00 07      MAIN: MOV [10h], 7");
+        assert_eq!(lines[5], "    5  0109  E8 00 00 R          CALL PUTC");
+        assert_eq!(lines[6], "    6  010C  E9 F1 FF            JMP MAIN");
+        assert!(lst.contains("  _TEXT           000F bytes  CODE\n"));
+        assert!(lst.contains("  MAIN            _TEXT:0100  public\n"));
+        assert!(lst.contains("  PUTC            external\n"));
+    }
+
+    #[test]
+    fn runs_as_a_binary() {
+        let src = "MOV AL, 6\nMOV BL, 7\n\nMUL AL, BL\nMOV [300h], AL";
+        let program = assemble(src, LOAD_OFFSET).unwrap();
+
+        assert_eq!(program.lines[3].offset, 0x104);
+        assert_eq!(program.lines[3].bytes, [0xF6, 0xE3]);
+        assert!(program.listing().contains("0104  F6 E3       MUL AL, BL"));
+        assert!(program.lst().contains("    4  0104  F6 E3               MUL AL, BL\n"));
+
+        let mut cpu = Cpu::default();
+        cpu.load_program(&program);
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.memory.read_byte(0x300), 42);
+        assert_eq!(cpu.state, State::Halted);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::{
+    clock::to_bcd,
+    cpu::Cpu,
+    disk::{SECTOR_SIZE, STATUS_BAD_COMMAND, STATUS_NOT_READY, STATUS_OK},
+    memory::linear,
+};
+
+impl Cpu {
+    /// Services a software interrupt with the built-in BIOS.
+    pub fn interrupt(&mut self, n: u8) -> Result<(), String> {
+        match n {
+            // the BIOS points single step, NMI and breakpoint at an IRET
+            0x01..=0x03 => Ok(()),
+            0x10 => self.int10(),
+            0x13 => self.int13(),
+            0x1A => self.int1a(),
+            0x20 => {
+                self.terminate(0);
+                Ok(())
+            }
+            0x21 => self.int21(),
+            0x33 => self.int33(),
+            _ => Err(format!("unsupported interrupt {n:02X}h")),
+        }
+    }
+
+    /// Video services.
+    fn int10(&mut self) -> Result<(), String> {
+        let ah = self.get_reg("AH").extract();
+        let al = self.get_reg("AL").extract();
+
+        match ah {
+            0x00 => self.video.set_mode(al, &mut self.memory),
+            0x0B => {
+                let bl = self.get_reg("BL").extract();
+                let cs = self.video.color_select;
+
+                self.video.color_select = match self.get_reg("BH").extract() {
+                    0 => (cs & 0xE0) | (bl & 0x1F),
+                    _ => (cs & !0x20) | ((bl & 1) << 5),
+                };
+            }
+            0x0C => {
+                let (x, y) = (self.get_word("CX"), self.get_word("DX"));
+                self.video.write_pixel(&mut self.memory, x as usize, y as usize, al);
+            }
+            0x0D => {
+                let (x, y) = (self.get_word("CX"), self.get_word("DX"));
+                let color = self.video.read_pixel(&self.memory, x as usize, y as usize);
+                self.set_byte("AL", color)?;
+            }
+            0x0F => {
+                self.set_byte("AL", self.video.mode)?;
+                self.set_byte("AH", self.video.columns())?;
+                self.set_byte("BH", 0)?;
+            }
+            _ => return Err(format!("unsupported video function {ah:02X}h")),
+        }
+
+        Ok(())
+    }
+
+    /// Floppy disk services. Results are reported in AH and the carry flag.
+    fn int13(&mut self) -> Result<(), String> {
+        let ah = self.get_reg("AH").extract();
+        let count = self.get_reg("AL").extract();
+        let cl = self.get_reg("CL").extract();
+        let cylinder = self.get_reg("CH").extract() as u16 | ((cl as u16 & 0xC0) << 2);
+        let sector = cl & 0x3F;
+        let head = self.get_reg("DH").extract();
+        let buffer = linear(self.get_word("ES"), self.get_word("BX"));
+        let drive = self.get_reg("DL").extract();
+
+        let floppy = match &mut self.floppy {
+            Some(f) if drive == 0 => f,
+            _ => return self.disk_result(STATUS_NOT_READY, 0),
+        };
+
+        match ah {
+            0x00 => self.set_disk_status(STATUS_OK),
+            0x01 => {
+                let last = self.disk_status;
+                self.disk_result(last, last)
+            }
+            0x02 => match floppy.read_sectors(cylinder, head, sector, count) {
+                Ok(data) => {
+                    let data = data.to_vec();
+
+                    // the transfer goes through the memory map like the cpu's writes
+                    for (i, b) in data.into_iter().enumerate() {
+                        self.memory.write_byte(buffer.wrapping_add(i as u32), b);
+                    }
+                    self.disk_result(STATUS_OK, count)
+                }
+                Err(status) => self.disk_result(status, 0),
+            },
+            0x03 => {
+                let data = self.memory.read(buffer, count as usize * SECTOR_SIZE);
+
+                match floppy.write_sectors(cylinder, head, sector, &data) {
+                    Ok(()) => self.disk_result(STATUS_OK, count),
+                    Err(status) => self.disk_result(status, 0),
+                }
+            }
+            0x04 => match floppy.read_sectors(cylinder, head, sector, count) {
+                Ok(_) => self.disk_result(STATUS_OK, count),
+                Err(status) => self.disk_result(status, 0),
+            },
+            0x08 => {
+                let g = floppy.geometry;
+                let max_cyl = g.cylinders - 1;
+
+                self.set_byte("BL", g.drive_type())?;
+                self.set_byte("CH", max_cyl as u8)?;
+                self.set_byte("CL", g.sectors | ((max_cyl >> 2) as u8 & 0xC0))?;
+                self.set_byte("DH", g.heads - 1)?;
+                self.set_byte("DL", 1)?;
+                self.disk_result(STATUS_OK, 0)
+            }
+            _ => self.disk_result(STATUS_BAD_COMMAND, 0),
+        }
+    }
+
+    /// Time of day services.
+    fn int1a(&mut self) -> Result<(), String> {
+        let ah = self.get_reg("AH").extract();
+        let now = self.clock.date_time(self.cycles);
+
+        match ah {
+            0x00 => {
+                let ticks = self.clock.ticks(self.cycles);
+                let midnight = self.clock.take_midnight(self.cycles);
+
+                self.set_word("CX", (ticks >> 16) as u16)?;
+                self.set_word("DX", ticks as u16)?;
+                self.set_byte("AL", midnight as u8)?;
+            }
+            0x01 => {
+                let ticks = (self.get_word("CX") as u32) << 16 | self.get_word("DX") as u32;
+                self.clock.set_ticks(self.cycles, ticks);
+            }
+            0x02 => {
+                self.set_byte("CH", to_bcd(now.hour))?;
+                self.set_byte("CL", to_bcd(now.minute))?;
+                self.set_byte("DH", to_bcd(now.second))?;
+                self.set_byte("DL", 0)?;
+                self.set_carry(false);
+            }
+            0x04 => {
+                self.set_byte("CH", to_bcd((now.year / 100) as u8))?;
+                self.set_byte("CL", to_bcd((now.year % 100) as u8))?;
+                self.set_byte("DH", to_bcd(now.month))?;
+                self.set_byte("DL", to_bcd(now.day))?;
+                self.set_carry(false);
+            }
+            _ => return Err(format!("unsupported time function {ah:02X}h")),
+        }
+
+        Ok(())
+    }
+
+    /// Finishes an INT 13h call: AH holds the status, AL the number of
+    /// sectors transferred and the carry flag is set on failure.
+    fn disk_result(&mut self, status: u8, sectors: u8) -> Result<(), String> {
+        self.set_disk_status(status)?;
+        Ok(self.set_byte("AL", sectors)?)
+    }
+
+    /// Reports `status` in AH and the carry flag, leaving AL alone.
+    fn set_disk_status(&mut self, status: u8) -> Result<(), String> {
+        self.disk_status = status;
+        self.set_carry(status != STATUS_OK);
+        Ok(self.set_byte("AH", status)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{Clock, DOS_EPOCH};
+    use crate::cpu::{Cpu, State};
+    use crate::disk::{Floppy, BOOT_ADDRESS, SECTOR_SIZE};
+    use crate::memory::Mapping;
+    use crate::video::CGA_BASE;
+
+    #[test]
+    fn int10_draws_pixels() {
+        let str = r#"
+            MOV AH, 0
+            MOV AL, 4
+            INT 10h
+            MOV AH, 0Ch
+            MOV AL, 3
+            MOV CH, 1
+            MOV CL, 3Fh
+            MOV DL, 199
+            INT 10h
+            MOV AH, 0Dh
+            MOV AL, 0
+            INT 10h
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.video.mode, 4);
+        assert_eq!(cpu.get_reg("AL").extract(), 3);
+        assert_eq!(cpu.video.read_pixel(&cpu.memory, 319, 199), 3);
+    }
+
+    #[test]
+    fn direct_frame_buffer_writes() {
+        let str = r#"
+            MOV AH, 0
+            MOV AL, 6
+            INT 10h
+            MOV AL, 0F0h
+            MOV [0B8000h], AL
+            MOV BL, [0B8000h]
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        cpu.run().unwrap();
+
+        let frame = cpu.video.render(&cpu.memory).unwrap();
+        assert_eq!(cpu.memory.read_byte(CGA_BASE), 0xF0);
+        assert_eq!(cpu.get_reg("BL").extract(), 0xF0);
+        assert_eq!(&frame.rgb[9..12], &[0xFF, 0xFF, 0xFF]);
+        assert_eq!(&frame.rgb[12..15], &[0, 0, 0]);
+    }
+
+    fn image() -> Floppy {
+        let mut data = vec![0; 360 * 1024];
+        data[0] = 0xEB;
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        data[SECTOR_SIZE..2 * SECTOR_SIZE].fill(0x42);
+
+        Floppy::from_bytes(data).unwrap()
+    }
+
+    #[test]
+    fn boot_loads_sector_zero() {
+        let mut cpu = Cpu {
+            state: State::Halted,
+            ..Cpu::default()
+        };
+        cpu.boot(image()).unwrap();
+
+        assert_eq!(cpu.state, State::Running);
+        assert_eq!(cpu.memory.read_byte(BOOT_ADDRESS), 0xEB);
+        assert_eq!(cpu.memory.read_word(BOOT_ADDRESS + 510), 0xAA55);
+        assert!(cpu.boot(Floppy::from_bytes(vec![0; 360 * 1024]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn int13_reads_and_writes() {
+        let str = r#"
+            MOV AH, 10h
+            MOV AL, 0
+            MOV ES, AX
+            MOV BH, 0
+            MOV BL, 20h
+            MOV AH, 2
+            MOV AL, 1
+            MOV CH, 0
+            MOV CL, 2
+            MOV DH, 0
+            INT 13h
+            MOV [10020h], 7
+            MOV AH, 3
+            MOV AL, 1
+            MOV CL, 9
+            MOV DH, 1
+            INT 13h
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        cpu.boot(image()).unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.memory.read_byte(0x10021), 0x42);
+        assert_eq!(cpu.get_reg("AH").extract(), 0);
+        assert!(!cpu.carry());
+
+        let floppy = cpu.floppy.as_ref().unwrap();
+        assert_eq!(floppy.read_sectors(0, 1, 9, 1).unwrap()[..2], [7, 0x42]);
+
+        // the read cannot write into ROM
+        let mut cpu = Cpu::from_str(str.split("MOV [10020h]").next().unwrap());
+        cpu.boot(image()).unwrap();
+        cpu.memory.map(0x10000..0x10040, Mapping::Rom);
+        cpu.run().unwrap();
+        assert_eq!(cpu.memory.read_byte(0x10021), 0);
+    }
+
+    #[test]
+    fn int13_reports_errors() {
+        let str = r#"
+            MOV AH, 2
+            MOV AL, 1
+            MOV CL, 10
+            INT 13h
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        cpu.boot(image()).unwrap();
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_reg("AH").extract(), 4);
+        assert!(cpu.carry());
+    }
+
+    #[test]
+    fn int13_drive_parameters() {
+        let str = r#"
+            MOV AH, 0
+            MOV AL, 7
+            INT 13h
+            MOV BH, AL
+            MOV AH, 8
+            INT 13h
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        let mut data = vec![0; 720 * 1024];
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        cpu.boot(Floppy::from_bytes(data).unwrap()).unwrap();
+        cpu.run().unwrap();
+
+        // reset leaves AL as it was
+        assert_eq!(cpu.get_reg("BH").extract(), 7);
+        assert_eq!(cpu.get_reg("BL").extract(), 3);
+        assert_eq!(cpu.get_reg("CH").extract(), 79);
+        assert_eq!(cpu.get_reg("CL").extract(), 9);
+        assert!(!cpu.carry());
+    }
+
+    #[test]
+    fn int1a_reads_the_clock() {
+        let str = r#"
+            MOV AH, 2
+            INT 1Ah
+            MOV BH, CH
+            MOV BL, CL
+            MOV AH, 4
+            INT 1Ah
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        // 1980-01-01 12:34:56
+        cpu.clock = Clock::virtual_at(DOS_EPOCH + 12 * 3600 + 34 * 60 + 56);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_word("BX"), 0x1234);
+        assert_eq!(cpu.get_reg("DH").extract(), 0x01);
+        assert_eq!(cpu.get_reg("CH").extract(), 0x19);
+        assert_eq!(cpu.get_reg("CL").extract(), 0x80);
+    }
+
+    #[test]
+    fn int1a_tick_count() {
+        let str = r#"
+            MOV AH, 0
+            INT 1Ah
+            MOV AH, 1
+            MOV CL, 0
+            MOV DH, 0
+            MOV DL, 0
+            INT 1Ah
+            MOV AH, 0
+            INT 1Ah
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        cpu.clock = Clock::virtual_at(DOS_EPOCH + 3600);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_word("CX"), 0);
+        assert!(cpu.get_word("DX") < 2);
+        assert_eq!(cpu.get_reg("AL").extract(), 0);
+    }
+}
@@ -0,0 +1,471 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{
+    assembler::{self, LOAD_OFFSET},
+    clock::{Clock, ClockSource},
+    cpu::{Cpu, State, AF, CF, DF, IF, OF, PF, SF, TF, ZF},
+    disk::Floppy,
+    linker,
+    model::Model,
+    object::Module,
+    output::{self, Format},
+};
+
+pub const USAGE: &str = "usage: 8086emu asm <source> [-o <output>] [-f bin|com|hex|obj|exe|lst]
+       8086emu link <object>... -o <program.com|program.exe>
+       8086emu run <source> [-m 8088|8086|80186|80286] [-n <instructions>]
+                    [--clock virtual|host] [--wav <file>]
+       8086emu boot <image> [-m 8088|8086|80186|80286] [-n <instructions>]
+                    [--clock virtual|host] [--wav <file>]";
+
+/// Exit statuses of the command line.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ASSEMBLY: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_FAULT: i32 = 3;
+
+/// Instructions `run` executes before giving up on a program.
+const RUN_LIMIT: u64 = 1_000_000;
+
+/// Samples per second of the speaker output `--wav` writes.
+const WAV_SAMPLE_RATE: u32 = 44_100;
+
+const FLAG_NAMES: [(u16, &str); 9] = [
+    (OF, "OF"),
+    (DF, "DF"),
+    (IF, "IF"),
+    (TF, "TF"),
+    (SF, "SF"),
+    (ZF, "ZF"),
+    (AF, "AF"),
+    (PF, "PF"),
+    (CF, "CF"),
+];
+
+struct AsmArgs {
+    source: PathBuf,
+    output: Option<PathBuf>,
+    format: Option<Format>,
+}
+
+fn parse_asm(args: &[String]) -> Result<AsmArgs, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut format = None;
+    let mut args = args.iter();
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "-o" => output = Some(PathBuf::from(args.next().ok_or("-o needs a file name")?)),
+            "-f" => {
+                let name = args.next().ok_or("-f needs a format")?;
+                format = Some(Format::from_name(name).ok_or_else(|| format!("unknown format {name}"))?);
+            }
+            a if source.is_none() && !a.starts_with('-') => source = Some(PathBuf::from(a)),
+            a => return Err(format!("unexpected argument {a}")),
+        }
+    }
+
+    Ok(AsmArgs {
+        source: source.ok_or("missing source file")?,
+        output,
+        format,
+    })
+}
+
+/// `asm`: assembles a source file into a flat binary, .COM, Intel HEX,
+/// object or .EXE file, or writes its listing. Without ORG, .COM output
+/// starts at 100h and the others at 0.
+fn asm(args: &[String]) -> i32 {
+    let args = match parse_asm(args) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return EXIT_USAGE;
+        }
+    };
+
+    let format = args
+        .format
+        .or_else(|| args.output.as_deref().and_then(Format::from_path))
+        .unwrap_or(Format::Binary);
+    let output = args
+        .output
+        .unwrap_or_else(|| args.source.with_extension(format.extension()));
+
+    let src = match fs::read_to_string(&args.source) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {e}", args.source.display());
+            return EXIT_USAGE;
+        }
+    };
+
+    let origin = if format == Format::Com { 0x100 } else { 0 };
+
+    let result = assembler::assemble(&src, origin)
+        .map_err(|e| (EXIT_ASSEMBLY, format!("{}: {e}", args.source.display())))
+        .and_then(|p| output::write(&p, format, &output).map_err(|e| (EXIT_ASSEMBLY, e)));
+
+    match result {
+        Ok(()) => EXIT_OK,
+        Err((status, e)) => {
+            eprintln!("{e}");
+            status
+        }
+    }
+}
+
+/// `link`: links object files into the .COM or .EXE the output name asks
+/// for.
+fn link(args: &[String]) -> i32 {
+    let mut objects = vec![];
+    let mut output = None;
+    let mut args = args.iter();
+
+    while let Some(a) = args.next() {
+        match a.as_str() {
+            "-o" => output = args.next().map(PathBuf::from),
+            a if a.starts_with('-') => {
+                eprintln!("unexpected argument {a}\n{USAGE}");
+                return EXIT_USAGE;
+            }
+            a => objects.push(PathBuf::from(a)),
+        }
+    }
+
+    let format = output.as_deref().and_then(Format::from_path);
+    let (Some(output), Some(format @ (Format::Com | Format::Exe))) = (output, format) else {
+        eprintln!("link needs a .com or .exe output\n{USAGE}");
+        return EXIT_USAGE;
+    };
+
+    let result = objects
+        .iter()
+        .map(Module::open)
+        .collect::<Result<Vec<_>, String>>()
+        .and_then(|modules| linker::link(&modules, format))
+        .and_then(|data| fs::write(&output, data).map_err(|e| format!("{}: {e}", output.display())));
+
+    match result {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("{e}");
+            EXIT_ASSEMBLY
+        }
+    }
+}
+
+/// The registers, IP, flags, machine state and console output after a run.
+fn report(cpu: &Cpu) -> String {
+    let flags = FLAG_NAMES
+        .iter()
+        .filter(|(f, _)| cpu.flag(*f))
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut report = format!(
+        "{}\nIP {}\nFlags {:04X}h {flags}\nState {}",
+        cpu.registers_str(),
+        cpu.ip,
+        cpu.flags,
+        cpu.state
+    );
+
+    if !cpu.console.is_empty() {
+        report = format!("{report}\nConsole:\n{}", cpu.console);
+    }
+
+    report
+}
+
+struct RunArgs {
+    file: PathBuf,
+    model: Model,
+    limit: u64,
+    clock: ClockSource,
+    /// Where to write what the speaker played.
+    wav: Option<PathBuf>,
+}
+
+/// The file, model, instruction limit, clock and WAV output `run` and
+/// `boot` take, failing with `missing` if there is no file.
+fn parse_run(args: &[String], missing: &str) -> Result<RunArgs, String> {
+    let mut file = None;
+    let mut model = Model::default();
+    let mut limit = RUN_LIMIT;
+    let mut clock = ClockSource::Host;
+    let mut wav = None;
+    let mut args = args.iter();
+
+    while let Some(a) = args.next() {
+        let parsed = match a.as_str() {
+            "-m" => args.next().and_then(|m| Model::from_name(m)).map(|m| model = m),
+            "-n" => args.next().and_then(|n| n.parse().ok()).map(|n| limit = n),
+            "--clock" => args.next().and_then(|c| ClockSource::from_name(c)).map(|c| clock = c),
+            "--wav" => args.next().map(|w| wav = Some(PathBuf::from(w))),
+            a if file.is_none() && !a.starts_with('-') => {
+                file = Some(PathBuf::from(a));
+                Some(())
+            }
+            _ => None,
+        };
+
+        if parsed.is_none() {
+            return Err(format!("bad argument {a}"));
+        }
+    }
+
+    Ok(RunArgs {
+        file: file.ok_or(missing)?,
+        model,
+        limit,
+        clock,
+        wav,
+    })
+}
+
+/// Prints the machine after a run, writes the speaker output if asked to
+/// and gives the exit status the machine's state calls for.
+fn finish(cpu: &Cpu, args: &RunArgs) -> i32 {
+    println!("{}", report(cpu));
+
+    if let Some(wav) = &args.wav {
+        if let Err(e) = cpu.speaker.save_wav(wav, cpu.cycles, WAV_SAMPLE_RATE) {
+            eprintln!("{}: {e}", wav.display());
+            return EXIT_USAGE;
+        }
+    }
+
+    match &cpu.state {
+        State::Running => {
+            eprintln!("{}: still running after {} instructions", args.file.display(), args.limit);
+            EXIT_FAULT
+        }
+        State::Faulted(e) => {
+            eprintln!("{}: {e}", args.file.display());
+            EXIT_FAULT
+        }
+        _ => EXIT_OK,
+    }
+}
+
+/// `run`: assembles a source file, runs it as the GUI does in binary mode
+/// until it halts, exits or faults, and prints the machine afterwards.
+fn execute(args: &[String]) -> i32 {
+    let args = match parse_run(args, "missing source file") {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return EXIT_USAGE;
+        }
+    };
+
+    let src = match fs::read_to_string(&args.file) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {e}", args.file.display());
+            return EXIT_USAGE;
+        }
+    };
+
+    let program = match assembler::assemble(&src, LOAD_OFFSET) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{}: {e}", args.file.display());
+            return EXIT_ASSEMBLY;
+        }
+    };
+
+    let mut cpu = Cpu {
+        model: args.model,
+        clock: Clock::new(args.clock),
+        ..Cpu::default()
+    };
+    cpu.load_program(&program);
+    // a fault is recorded in the state
+    let _ = cpu.run_code(args.limit);
+
+    finish(&cpu, &args)
+}
+
+/// `boot`: boots a floppy image as the BIOS does, runs it like `run` and
+/// writes the sectors it changed back to the image.
+fn boot(args: &[String]) -> i32 {
+    let args = match parse_run(args, "missing floppy image") {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return EXIT_USAGE;
+        }
+    };
+
+    let mut cpu = Cpu {
+        model: args.model,
+        clock: Clock::new(args.clock),
+        ..Cpu::default()
+    };
+    let floppy = match Floppy::open(&args.file) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("{e}");
+            return EXIT_USAGE;
+        }
+    };
+    if let Err(e) = cpu.boot(floppy.clone()) {
+        eprintln!("{}: {e}", args.file.display());
+        return EXIT_USAGE;
+    }
+    // a fault is recorded in the state
+    let _ = cpu.run_code(args.limit);
+
+    let status = finish(&cpu, &args);
+
+    match cpu.floppy.as_ref().filter(|f| f.bytes() != floppy.bytes()).map(|f| f.save(&args.file)) {
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            EXIT_FAULT
+        }
+        _ => status,
+    }
+}
+
+/// Runs a command line, returning the process exit status.
+pub fn run(args: &[String]) -> i32 {
+    match args.split_first() {
+        Some((cmd, rest)) if cmd == "asm" => asm(rest),
+        Some((cmd, rest)) if cmd == "link" => link(rest),
+        Some((cmd, rest)) if cmd == "run" => execute(rest),
+        Some((cmd, rest)) if cmd == "boot" => boot(rest),
+        _ => {
+            eprintln!("{USAGE}");
+            EXIT_USAGE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{parse_run, report, run, EXIT_ASSEMBLY, EXIT_FAULT, EXIT_OK, EXIT_USAGE};
+    use crate::{
+        assembler::{assemble, LOAD_OFFSET},
+        clock::ClockSource,
+        cpu::Cpu,
+    };
+
+    #[test]
+    fn asm_and_link() {
+        let dir = std::env::temp_dir().join(format!("8086emu-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let src = dir.join("prog.asm");
+        fs::write(&src, "MOV DL, 41h\nMOV AH, 2\nINT 21h").unwrap();
+
+        let args = ["asm".to_string(), src.display().to_string(), "-f".into(), "com".into()];
+        assert_eq!(run(&args), EXIT_OK);
+        assert_eq!(
+            fs::read(dir.join("prog.com")).unwrap(),
+            [0xB2, 0x41, 0xB4, 0x02, 0xCD, 0x21, 0xCD, 0x20]
+        );
+
+        let lib = dir.join("lib.asm");
+        fs::write(&lib, "PUBLIC SHOW\nSHOW: MOV AH, 2\nINT 21h\nRET").unwrap();
+        fs::write(&src, "EXTRN SHOW\nMOV DL, 41h\nCALL SHOW").unwrap();
+
+        for f in [&src, &lib] {
+            assert_eq!(run(&["asm".to_string(), f.display().to_string(), "-f".into(), "obj".into()]), EXIT_OK);
+        }
+
+        let objects = [dir.join("prog.obj"), dir.join("lib.obj")].map(|p| p.display().to_string());
+        let out = dir.join("prog.exe").display().to_string();
+        assert_eq!(run(&["link".to_string(), objects[0].clone(), objects[1].clone(), "-o".into(), out]), EXIT_OK);
+        assert_eq!(run(&["link".to_string(), objects[0].clone(), "-o".into(), "a.com".into()]), EXIT_ASSEMBLY);
+        assert_eq!(run(&["link".to_string(), objects[0].clone(), "-x".into(), "-o".into(), "a.com".into()]), EXIT_USAGE);
+
+        fs::write(&src, "MOV [0B8000h], AL").unwrap();
+        assert_eq!(run(&args[..2]), EXIT_ASSEMBLY);
+        assert_eq!(run(&["asm".to_string()]), EXIT_USAGE);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn run_reports_the_machine() {
+        let dir = std::env::temp_dir().join(format!("8086emu-run-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("prog.asm");
+        let args = ["run".to_string(), src.display().to_string()];
+
+        fs::write(&src, "MOV DL, 41h\nMOV AH, 2\nINT 21h\nSUB CX, CX").unwrap();
+        assert_eq!(run(&args), EXIT_OK);
+
+        fs::write(&src, "MOV AX, 1\nMOV BL, 0\nDIV BL").unwrap();
+        assert_eq!(run(&args), EXIT_FAULT);
+
+        fs::write(&src, "START: JMP START").unwrap();
+        assert_eq!(run(&[&args[..], &["-n".into(), "100".into()]].concat()), EXIT_FAULT);
+
+        fs::write(&src, "MOV [0B8000h], AL").unwrap();
+        assert_eq!(run(&args), EXIT_ASSEMBLY);
+        assert_eq!(run(&[&args[..], &["-m".into(), "z80".into()]].concat()), EXIT_USAGE);
+        assert_eq!(run(&[&args[..], &["--clock".into(), "utc".into()]].concat()), EXIT_USAGE);
+
+        let options = ["prog.asm", "--clock", "virtual"].map(String::from);
+        assert_eq!(parse_run(&options, "").unwrap().clock, ClockSource::VIRTUAL);
+
+        // a 1 kHz tone for a few thousand instructions
+        fs::write(
+            &src,
+            "MOV AL, 0B6h\nOUT 43h, AL\nMOV AL, 0A9h\nOUT 42h, AL\nMOV AL, 4\nOUT 42h, AL\n\
+             IN AL, 61h\nOR AL, 3\nOUT 61h, AL\nDELAY: JMP DELAY",
+        )
+        .unwrap();
+        let wav = dir.join("tone.wav");
+        let options = ["-n".into(), "5000".into(), "--wav".into(), wav.display().to_string()];
+        assert_eq!(run(&[&args[..], &options].concat()), EXIT_FAULT);
+        let data = fs::read(&wav).unwrap();
+        assert_eq!(&data[..4], b"RIFF");
+        assert!(data[44..].chunks(2).any(|s| s != [0, 0]));
+
+        fs::remove_dir_all(dir).unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load_program(&assemble("MOV DL, 41h\nMOV AH, 2\nINT 21h\nSUB CX, CX", LOAD_OFFSET).unwrap());
+        cpu.run_code(100).unwrap();
+
+        let report = report(&cpu);
+        assert!(report.contains("Flags 0044h ZF PF\nState halted with interrupts disabled"), "{report}");
+        assert!(report.ends_with("Console:\nA"), "{report}");
+    }
+
+    #[test]
+    fn boot_writes_the_image_back() {
+        let dir = std::env::temp_dir().join(format!("8086emu-boot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("disk.img");
+        let args = ["boot".to_string(), image.display().to_string()];
+
+        // MOV AX, 0301h; MOV BX, 7C00h; MOV CX, 2; MOV DH, 0; INT 13h; CLI; HLT
+        // copies the boot sector to sector 2
+        let code = [
+            0xB8, 0x01, 0x03, 0xBB, 0x00, 0x7C, 0xB9, 0x02, 0x00, 0xB6, 0x00, 0xCD, 0x13, 0xFA, 0xF4,
+        ];
+        let mut data = vec![0; 360 * 1024];
+        data[..code.len()].copy_from_slice(&code);
+        data[510] = 0x55;
+        data[511] = 0xAA;
+        fs::write(&image, &data).unwrap();
+
+        assert_eq!(run(&args), EXIT_OK);
+        assert_eq!(fs::read(&image).unwrap()[512..1024], data[..512]);
+
+        fs::write(&image, vec![0; 360 * 1024]).unwrap();
+        assert_eq!(run(&args), EXIT_USAGE);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
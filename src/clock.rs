@@ -0,0 +1,212 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+use crate::speaker::{CPU_HZ, PIT_HZ};
+
+const NANOS: i128 = 1_000_000_000;
+const DAY: i128 = 86_400 * NANOS;
+
+/// 1980-01-01 00:00:00, the DOS epoch, in Unix seconds.
+pub const DOS_EPOCH: u64 = 315_532_800;
+
+/// BIOS timer ticks per day, at PIT_HZ / 65536 (about 18.2 Hz).
+pub const TICKS_PER_DAY: u32 = 0x1800B0;
+
+/// Where the emulated machine gets its time of day from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub enum ClockSource {
+    /// The host's wall clock, in UTC.
+    Host,
+    /// A clock that starts at `start` (Unix seconds) and advances only with
+    /// executed cycles, so runs are reproducible.
+    Virtual { start: u64 },
+}
+
+impl ClockSource {
+    /// A virtual clock starting at the DOS epoch, 1980-01-01 00:00:00.
+    pub const VIRTUAL: Self = Self::Virtual { start: DOS_EPOCH };
+
+    /// `host`, or `virtual` for [`ClockSource::VIRTUAL`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "host" => Some(Self::Host),
+            "virtual" => Some(Self::VIRTUAL),
+            _ => None,
+        }
+    }
+}
+
+/// A date and time in UTC, which is what the BIOS and DOS services report,
+/// since the emulator does not know the host's time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    /// 0 is Sunday.
+    pub weekday: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub hundredths: u8,
+}
+
+impl DateTime {
+    /// Splits nanoseconds since the Unix epoch into a calendar date.
+    pub fn from_unix_nanos(ns: i128) -> Self {
+        let days = ns.div_euclid(DAY) as i64;
+        let time = ns.rem_euclid(DAY);
+        let secs = (time / NANOS) as u32;
+
+        // civil_from_days, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+        let year = (yoe + era * 400 + (month <= 2) as i64) as u16;
+
+        Self {
+            year,
+            month,
+            day,
+            weekday: (days + 4).rem_euclid(7) as u8,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+            hundredths: (time % NANOS / 10_000_000) as u8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Clock {
+    pub source: ClockSource,
+    /// Adjustment made by INT 1Ah AH=01h, in nanoseconds.
+    offset: i64,
+    /// Day of the last tick count read, for the midnight flag.
+    last_day: Option<i64>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new(ClockSource::Host)
+    }
+}
+
+impl Clock {
+    pub fn new(source: ClockSource) -> Self {
+        Self {
+            source,
+            offset: 0,
+            last_day: None,
+        }
+    }
+
+    pub fn virtual_at(start: u64) -> Self {
+        Self::new(ClockSource::Virtual { start })
+    }
+
+    /// Nanoseconds since the Unix epoch after `cycles` executed cycles.
+    pub fn now(&self, cycles: u64) -> i128 {
+        let base = match self.source {
+            ClockSource::Host => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as i128)
+                .unwrap_or(0),
+            ClockSource::Virtual { start } => {
+                start as i128 * NANOS + cycles as i128 * NANOS / CPU_HZ as i128
+            }
+        };
+
+        base + self.offset as i128
+    }
+
+    pub fn date_time(&self, cycles: u64) -> DateTime {
+        DateTime::from_unix_nanos(self.now(cycles))
+    }
+
+    /// BIOS tick count since midnight.
+    pub fn ticks(&self, cycles: u64) -> u32 {
+        let since_midnight = self.now(cycles).rem_euclid(DAY);
+        (since_midnight * PIT_HZ as i128 / 65536 / NANOS) as u32
+    }
+
+    /// Adjusts the clock so the tick count since midnight reads `ticks`.
+    pub fn set_ticks(&mut self, cycles: u64, ticks: u32) {
+        let now = self.now(cycles);
+        let pit = PIT_HZ as i128;
+        let wanted = (ticks as i128 * 65536 * NANOS + pit - 1) / pit;
+
+        self.offset += (wanted - now.rem_euclid(DAY)) as i64;
+    }
+
+    /// True once after each midnight that passed since the previous call,
+    /// like the BIOS rollover flag.
+    pub fn take_midnight(&mut self, cycles: u64) -> bool {
+        let day = self.now(cycles).div_euclid(DAY) as i64;
+        let passed = matches!(self.last_day, Some(last) if last < day);
+
+        self.last_day = Some(day);
+        passed
+    }
+}
+
+pub fn to_bcd(v: u8) -> u8 {
+    ((v / 10) << 4) | (v % 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_bcd, Clock, ClockSource, DateTime, DOS_EPOCH, TICKS_PER_DAY};
+    use crate::speaker::CPU_HZ;
+
+    #[test]
+    fn calendar() {
+        let d = DateTime::from_unix_nanos(DOS_EPOCH as i128 * 1_000_000_000);
+        assert_eq!((d.year, d.month, d.day, d.weekday), (1980, 1, 1, 2));
+
+        // 2024-02-29 13:45:30.25
+        let d = DateTime::from_unix_nanos(1_709_214_330_250_000_000);
+        assert_eq!((d.year, d.month, d.day, d.weekday), (2024, 2, 29, 4));
+        assert_eq!((d.hour, d.minute, d.second, d.hundredths), (13, 45, 30, 25));
+    }
+
+    #[test]
+    fn virtual_clock_follows_cycles() {
+        let clock = Clock::virtual_at(DOS_EPOCH + 3600);
+
+        assert_eq!(clock.date_time(0).hour, 1);
+        assert_eq!(clock.date_time(CPU_HZ * 90).minute, 1);
+        assert_eq!(clock.ticks(0), 65543);
+        assert!(clock.ticks(CPU_HZ) > clock.ticks(0));
+    }
+
+    #[test]
+    fn set_ticks_and_midnight() {
+        let mut clock = Clock::virtual_at(DOS_EPOCH);
+
+        clock.set_ticks(0, TICKS_PER_DAY - 1);
+        assert_eq!(clock.ticks(0), TICKS_PER_DAY - 1);
+        assert!(!clock.take_midnight(0));
+        assert!(clock.take_midnight(CPU_HZ));
+        assert!(!clock.take_midnight(CPU_HZ));
+
+        assert_eq!(ClockSource::from_name("virtual"), Some(ClockSource::Virtual { start: DOS_EPOCH }));
+        assert_eq!(ClockSource::from_name("host"), Some(ClockSource::Host));
+        assert_eq!(ClockSource::from_name("utc"), None);
+    }
+
+    #[test]
+    fn bcd() {
+        assert_eq!(to_bcd(59), 0x59);
+        assert_eq!(to_bcd(7), 0x07);
+    }
+}
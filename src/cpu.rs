@@ -1,17 +1,106 @@
+use std::fmt;
 use std::str::FromStr;
 
-use druid::{Lens, Data};
+#[cfg(feature = "gui")]
+use druid::{Data, Lens};
 
 use crate::{
+    clock::Clock,
+    disk::{Floppy, BOOT_ADDRESS},
+    error::Error,
+    fpu::Fpu,
+    handler::Handlers,
+    memory::Memory,
+    model::Model,
+    mouse::Mouse,
     parser::Parser,
-    register::{Register},
+    register::{self, Register},
+    speaker::Speaker,
     token::Token,
+    video::Video,
 };
 
-#[derive(Data, Lens, Debug, Clone)]
+/// Clock cycles charged for every interpreted instruction.
+pub const INSTRUCTION_CYCLES: u64 = 4;
+
+/// Carry flag bit, used by BIOS services to report failure.
+pub const CF: u16 = 0x0001;
+pub const PF: u16 = 0x0004;
+pub const AF: u16 = 0x0010;
+pub const ZF: u16 = 0x0040;
+pub const SF: u16 = 0x0080;
+pub const TF: u16 = 0x0100;
+pub const IF: u16 = 0x0200;
+pub const DF: u16 = 0x0400;
+pub const OF: u16 = 0x0800;
+
+/// FLAGS bits that always read as set on the 8086, 8088 and 80186.
+pub const FLAGS_FIXED: u16 = 0xF002;
+
+pub const SEGMENT_NAMES: [&str; 4] = ["ES", "CS", "SS", "DS"];
+pub const POINTER_NAMES: [&str; 4] = ["SP", "BP", "SI", "DI"];
+
+/// Whether the machine runs, and why it stopped if it does not.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub enum State {
+    #[default]
+    Running,
+    /// HLT with interrupts disabled, which only a reset or NMI ends.
+    Halted,
+    /// HLT with interrupts enabled, resumed by the next interrupt.
+    WaitingForInterrupt,
+    /// The program ended, through DOS or by running off the end of the
+    /// source.
+    Terminated(u8),
+    /// An instruction could not be executed.
+    Faulted(String),
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::Running => write!(f, "running"),
+            State::Halted => write!(f, "halted with interrupts disabled"),
+            State::WaitingForInterrupt => write!(f, "halted, waiting for an interrupt"),
+            State::Terminated(code) => write!(f, "terminated with exit code {code}"),
+            State::Faulted(e) => write!(f, "faulted: {e}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "gui", derive(Data, Lens))]
 pub struct Cpu {
+    pub model: Model,
+    /// Reproduce the model's undocumented opcodes, undefined flags and
+    /// address wrap like the real chip.
+    pub quirks: bool,
     pub registers: [Register; 8],
+    /// ES, CS, SS and DS in their encoding order.
+    pub segments: [u16; 4],
+    /// SP, BP, SI and DI.
+    pub pointers: [u16; 4],
+    pub ip: u16,
+    pub flags: u16,
+    /// `run_code` stops as soon as this is anything but running.
+    pub state: State,
+    /// Text the program wrote through DOS.
+    pub console: String,
     pub parser: Parser,
+    pub cycles: u64,
+    pub clock: Clock,
+    pub memory: Memory,
+    pub speaker: Speaker,
+    pub video: Video,
+    pub mouse: Mouse,
+    pub floppy: Option<Floppy>,
+    /// Status of the last INT 13h call, returned by function 01h.
+    pub disk_status: u8,
+    /// The 8087, if one is fitted.
+    pub fpu: Option<Fpu>,
+    /// Interrupts serviced by host code.
+    pub handlers: Handlers,
 }
 
 impl Cpu {
@@ -34,12 +123,35 @@ impl Default for Cpu {
         }
 
         Cpu {
+            model: Model::default(),
+            quirks: false,
             registers,
+            segments: [0; 4],
+            pointers: [0; 4],
+            ip: 0,
+            flags: 0,
+            state: State::Running,
+            console: String::new(),
             parser: Parser::default(),
+            cycles: 0,
+            clock: Clock::default(),
+            memory: Memory::default(),
+            speaker: Speaker::default(),
+            video: Video::default(),
+            mouse: Mouse::default(),
+            floppy: None,
+            disk_status: 0,
+            fpu: None,
+            handlers: Handlers::default(),
         }
     }
 }
 
+/// A number the interpreter's byte operands can hold.
+fn byte(n: u32) -> Result<u8, Error> {
+    u8::try_from(n).map_err(|_| Error::operands(format!("{n} does not fit in a byte")))
+}
+
 impl Cpu {
     pub fn from_str(s: impl Into<String>) -> Self {
         Cpu {
@@ -64,7 +176,7 @@ impl Cpu {
         self.registers[idx]
     }
 
-    pub fn set_reg(&mut self, name: &str, val: Register) -> Result<(), String> {
+    pub fn set_reg(&mut self, name: &str, val: Register) -> Result<(), Error> {
         let idx = match name {
             "AH" => 0,
             "AL" => 1,
@@ -74,7 +186,7 @@ impl Cpu {
             "CL" => 5,
             "DH" => 6,
             "DL" => 7,
-            _ => return Err("wrong name".to_string()),
+            _ => return Err(Error::operands(format!("{name} is not a byte register"))),
         };
 
         self.registers[idx] = val;
@@ -82,48 +194,252 @@ impl Cpu {
         Ok(())
     }
 
+    pub fn set_byte(&mut self, name: &str, v: u8) -> Result<(), Error> {
+        self.set_reg(name, self.get_reg(name).set_value(v))
+    }
+
+    /// Reads a segment, pointer or index register, IP, or AX, BX, CX or DX
+    /// from its two halves.
+    pub fn get_word(&self, name: &str) -> u16 {
+        if let Some(i) = SEGMENT_NAMES.iter().position(|s| *s == name) {
+            return self.segments[i];
+        }
+
+        if let Some(i) = POINTER_NAMES.iter().position(|s| *s == name) {
+            return self.pointers[i];
+        }
+
+        if name == "IP" {
+            return self.ip;
+        }
+
+        let hi = self.get_reg(&name.replace('X', "H")).extract();
+        let lo = self.get_reg(&name.replace('X', "L")).extract();
+
+        u16::from_be_bytes([hi, lo])
+    }
+
+    pub fn set_word(&mut self, name: &str, v: u16) -> Result<(), Error> {
+        if let Some(i) = SEGMENT_NAMES.iter().position(|s| *s == name) {
+            self.segments[i] = v;
+            return Ok(());
+        }
+
+        if let Some(i) = POINTER_NAMES.iter().position(|s| *s == name) {
+            self.pointers[i] = v;
+            return Ok(());
+        }
+
+        if name == "IP" {
+            self.ip = v;
+            return Ok(());
+        }
+
+        if !register::is_word_register(name) {
+            return Err(Error::operands(format!("{name} is not a word register")));
+        }
+
+        let [hi, lo] = v.to_be_bytes();
+
+        self.set_byte(&name.replace('X', "H"), hi)?;
+        self.set_byte(&name.replace('X', "L"), lo)
+    }
+
+    /// Exit code of a program that has terminated.
+    pub fn exit_code(&self) -> Option<u8> {
+        match self.state {
+            State::Terminated(code) => Some(code),
+            _ => None,
+        }
+    }
+
+    pub fn carry(&self) -> bool {
+        self.flags & CF != 0
+    }
+
+    pub fn set_carry(&mut self, on: bool) {
+        self.set_flag(CF, on);
+    }
+
+    pub fn flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u16, on: bool) {
+        if on {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+
+    /// Inserts a floppy and loads its boot sector to 0000:7C00 the way the
+    /// BIOS does, with DL holding the boot drive, ready to run from there.
+    pub fn boot(&mut self, floppy: Floppy) -> Result<(), String> {
+        if !floppy.is_bootable() {
+            return Err("boot sector is missing the 55AAh signature".to_string());
+        }
+
+        self.memory.load(BOOT_ADDRESS, floppy.boot_sector());
+        self.segments = [0; 4];
+        self.ip = BOOT_ADDRESS as u16;
+        self.set_byte("DL", 0)?;
+        self.floppy = Some(floppy);
+        self.state = State::Running;
+
+        Ok(())
+    }
+
     pub fn registers_str(&self) -> String {
-        self.registers
+        let regs = self
+            .registers
             .iter()
             .enumerate()
             .fold("".into(), |s, (i, r)| {
                 let str = if i == 0 { "" } else { "\n" };
                 format!("{s}{str}{r}")
-            })
+            });
+
+        let regs = POINTER_NAMES
+            .iter()
+            .zip(self.pointers)
+            .fold(regs, |s, (name, v)| format!("{s}\n{name} {v}"));
+
+        let regs = SEGMENT_NAMES
+            .iter()
+            .zip(self.segments)
+            .fold(regs, |s, (name, v)| format!("{s}\n{name} {v}"));
+
+        format!("{regs}\nCycles {}", self.cycles)
     }
 
     pub fn print_registers(&self) {
         println!("{}", self.registers_str())
     }
 
-    pub fn eval(&mut self, cmd: Token) -> Result<(), String> {
+    pub fn port_out(&mut self, port: u16, val: u8) {
+        if Speaker::handles(port) {
+            self.speaker.write_port(port, val, self.cycles);
+        } else if Video::handles(port) {
+            self.video.write_port(port, val);
+        }
+    }
+
+    pub fn port_in(&mut self, port: u16) -> u8 {
+        if Speaker::handles(port) {
+            self.speaker.read_port(port, self.cycles)
+        } else if Video::handles(port) {
+            self.video.read_port(port)
+        } else {
+            0xFF
+        }
+    }
+
+    /// Executes one parsed instruction. Its errors have no position, `run`
+    /// places them at the instruction.
+    pub fn eval(&mut self, cmd: Token) -> Result<(), Error> {
         match cmd {
             Token::Instruction { name, left, right } => {
+                if !self.model.supports_mnemonic(&name) {
+                    let message = format!("{} is not supported on the {}", name.to_uppercase(), self.model.name());
+                    return Err(Error::unknown_mnemonic(name, message));
+                }
+
+                self.cycles += INSTRUCTION_CYCLES;
+
+                match (name.to_lowercase().as_str(), &*left) {
+                    ("out", Token::Num(port)) => {
+                        let port = byte(*port)?;
+
+                        return match right.as_deref() {
+                            Some(Token::Reg(r)) if r == "AL" => {
+                                self.port_out(port as u16, self.get_reg("AL").extract());
+                                Ok(())
+                            }
+                            _ => Err(Error::operands("expected AL at the second position")),
+                        };
+                    }
+                    ("out", _) => {
+                        return Err(Error::operands("expected a port number at the first position"))
+                    }
+                    ("int", Token::Num(n)) => {
+                        let n = byte(*n)?;
+
+                        return self
+                            .host_interrupt(n)
+                            .unwrap_or_else(|| self.interrupt(n))
+                            .map_err(Error::runtime)
+                    }
+                    ("mov", Token::Reg(l)) if register::is_word_register(l) => {
+                        return match right.as_deref() {
+                            Some(Token::Reg(r)) if register::is_word_register(r) => {
+                                self.set_word(l, self.get_word(r))
+                            }
+                            _ => Err(Error::operands("expected a 16-bit register at the second position")),
+                        };
+                    }
+                    ("mov", Token::Mem(addr)) => {
+                        let v = match right.as_deref() {
+                            Some(Token::Reg(r)) if !register::is_word_register(r) => {
+                                self.get_reg(r).extract()
+                            }
+                            Some(Token::Num(n)) => byte(*n)?,
+                            _ => return Err(Error::operands("expected a register or a number")),
+                        };
+
+                        self.memory.write_byte(*addr, v);
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+
                 let left = match *left {
+                    Token::Reg(n) if register::is_word_register(&n) => {
+                        return Err(Error::operands(format!("{n} can only be used with MOV")))
+                    }
                     Token::Reg(n) => n,
-                    _ => return Err("expected register name at the second position".to_string()),
+                    _ => return Err(Error::operands("expected register name at the second position")),
                 };
 
+                if let Some(Token::Num(n)) = right.as_deref() {
+                    byte(*n)?;
+                }
+
                 let right_int = || match &right {
                     Some(v) => match &**v {
-                        Token::Num(n) => Ok(n.clone()),
-                        _ => Err("expected a number".to_string()),
+                        Token::Num(n) => Ok(*n as u8),
+                        _ => Err(Error::operands("expected a number")),
                     },
-                    None => Err("wrong number of arguments".to_string()),
+                    None => Err(Error::operands("wrong number of arguments")),
                 };
 
                 let right_reg = || match &right {
                     Some(v) => match &**v {
+                        Token::Reg(name) if register::is_word_register(name) => {
+                            Err(Error::operands(format!("{name} can only be used with MOV")))
+                        }
                         Token::Reg(name) => Ok(name),
-                        v => Err(
-                            format!("expected a register at the second position {:?}", v)
-                                .to_string(),
-                        ),
+                        v => Err(Error::operands(format!("expected a register at the second position {:?}", v))),
                     },
-                    None => Err("wrong number of arguments".to_string()),
+                    None => Err(Error::operands("wrong number of arguments")),
                 };
 
                 match name.to_lowercase().as_str() {
+                    "mul" if right.is_none() => {
+                        let ax = self.get_reg("AL").extract() as u16 * self.get_reg(&left).extract() as u16;
+                        self.set_word("AX", ax)?
+                    }
+                    "div" if right.is_none() => {
+                        let ax = self.get_word("AX");
+                        let d = self.get_reg(&left).extract() as u16;
+
+                        if d == 0 || ax / d > 0xFF {
+                            return Err(Error::runtime("divide error"));
+                        }
+
+                        self.set_byte("AL", (ax / d) as u8)?;
+                        self.set_byte("AH", (ax % d) as u8)?
+                    }
                     "add" => {
                         if let Ok(i) = right_int() {
                             self.set_reg(&left, self.get_reg(&left) + i)?
@@ -191,38 +507,60 @@ impl Cpu {
                         self.set_reg(&left, self.get_reg(&left).set_value(right_reg.extract()))?;
                         self.set_reg(&right, self.get_reg(&right).set_value(left_reg.extract()))?;
                     }
+                    "in" => {
+                        if left != "AL" {
+                            return Err(Error::operands("expected AL at the first position"));
+                        }
+
+                        let v = self.port_in(right_int()? as u16);
+                        self.set_reg(&left, self.get_reg(&left).set_value(v))?
+                    }
                     "mov" => {
                         if let Ok(i) = right_int() {
                             self.set_reg(&left, self.get_reg(&left).set_value(i))?
+                        } else if let Some(Token::Mem(addr)) = right.as_deref() {
+                            self.set_byte(&left, self.memory.read_byte(*addr))?
                         } else {
                             let name = right_reg()?;
                             self.set_reg(&left, self.get_reg(&name))?
                         }
                     }
-                    lol => {
-                        println!("{lol}")
+                    _ => {
+                        let message = format!("the interpreter has no {}", name.to_uppercase());
+                        return Err(Error::unknown_mnemonic(name, message));
                     }
                 }
                 Ok(())
             }
-            _ => Err("expected instruction".to_string()),
+            t => Err(Error::syntax(format!("expected an instruction, found {t:?}"))),
         }
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
+    /// Interprets the source to its end, which terminates the program
+    /// with exit code 0.
+    pub fn run(&mut self) -> Result<(), Error> {
         while let Some(t) = self.parser.next() {
-            self.eval(t)?;
+            let position = self.parser.location();
+
+            if let Err(e) = t.and_then(|t| self.eval(t).map_err(|e| e.or_at(position))) {
+                self.state = State::Faulted(e.to_string());
+                return Err(e);
+            }
         }
 
+        self.state = State::Terminated(0);
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::Parser;
+    use crate::{
+        error::{Error, Position},
+        parser::Parser,
+    };
 
-    use super::Cpu;
+    use super::{Cpu, Token};
     use super::Register::*;
 
     #[test]
@@ -249,4 +587,81 @@ mod tests {
 
         cpu.print_registers();
     }
+
+    #[test]
+    fn cpu_single_operand_mul_div() {
+        let str = r#"
+            MOV AL, 200
+            MOV BL, 3
+            MUL BL
+            MOV CL, 7
+            DIV CL
+        "#;
+
+        let mut cpu = Cpu::new(Parser::new(str));
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_reg("AL").extract(), 85);
+        assert_eq!(cpu.get_reg("AH").extract(), 5);
+    }
+
+    #[test]
+    fn errors_say_what_and_where() {
+        let mut cpu = Cpu::from_str("MOV AL, 1\n  MOV BL, 0\n  DIV BL");
+        assert_eq!(
+            cpu.run().unwrap_err(),
+            Error::Runtime {
+                position: Position::new(3, 3),
+                message: "divide error".into(),
+            }
+        );
+        assert_eq!(cpu.state.to_string(), "faulted: line 3, column 3: divide error");
+
+        let e = Cpu::from_str("MOV AL, 1\nPUSH AL").run().unwrap_err();
+        assert!(matches!(&e, Error::UnknownMnemonic { mnemonic, .. } if mnemonic == "PUSH"));
+        assert_eq!(e.position(), Position::new(2, 1));
+
+        let e = Cpu::from_str("ADD AL, DX").run().unwrap_err();
+        assert!(matches!(e, Error::OperandMismatch { .. }), "{e}");
+        assert!(matches!(Cpu::from_str("MOV AL 1").run(), Err(Error::Syntax { .. })));
+        assert!(matches!(Cpu::from_str("MOV AL, 300").run(), Err(Error::OperandMismatch { .. })));
+    }
+
+    #[test]
+    fn model_rejects_newer_instructions() {
+        let pusha = Token::Instruction {
+            name: "PUSHA".into(),
+            left: Box::new(Token::Num(0)),
+            right: None,
+        };
+
+        let mut cpu = Cpu::default();
+        assert_eq!(cpu.eval(pusha.clone()).unwrap_err().to_string(), "PUSHA is not supported on the 8086");
+
+        // the interpreter has no PUSHA of its own, but the model lets it through
+        cpu.model = crate::model::Model::I80186;
+        assert!(!cpu.eval(pusha).unwrap_err().to_string().contains("not supported"));
+    }
+
+    #[test]
+    fn cpu_speaker() {
+        let str = r#"
+            MOV AL, 0B6h
+            OUT 43h, AL
+            MOV AL, 0A9h
+            OUT 42h, AL
+            MOV AL, 4
+            OUT 42h, AL
+            IN AL, 61h
+            OR AL, 3
+            OUT 61h, AL
+        "#;
+
+        let mut cpu = Cpu::new(Parser::new(str));
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.speaker.frequency().map(|f| f.round()), Some(1000.));
+        assert!(cpu.speaker.state().gate && cpu.speaker.state().enabled);
+        assert_eq!(cpu.cycles, 9 * super::INSTRUCTION_CYCLES);
+    }
 }
@@ -0,0 +1,687 @@
+use crate::model::Model;
+
+pub const REG8_NAMES: [&str; 8] = ["AL", "CL", "DL", "BL", "AH", "CH", "DH", "BH"];
+pub const REG16_NAMES: [&str; 8] = ["AX", "CX", "DX", "BX", "SP", "BP", "SI", "DI"];
+
+pub const ES: u8 = 0;
+pub const CS: u8 = 1;
+pub const SS: u8 = 2;
+pub const DS: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Word,
+}
+
+/// String instruction repeat prefixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rep {
+    /// F3h, REP or REPE depending on the instruction.
+    Rep,
+    /// F2h, REPNE.
+    Repne,
+}
+
+/// A ModR/M memory operand. `rm` is the r/m field selecting the base and
+/// index registers, `None` for a direct address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Address {
+    pub rm: Option<u8>,
+    pub disp: u16,
+}
+
+impl Address {
+    /// BP based modes address the stack segment by default.
+    pub fn default_segment(&self) -> u8 {
+        match self.rm {
+            Some(2) | Some(3) | Some(6) => SS,
+            _ => DS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Reg8(u8),
+    Reg16(u8),
+    Seg(u8),
+    Mem(Address),
+    Imm(u16),
+    /// Target offset of a relative jump, already resolved against IP.
+    Rel(u16),
+    Far(u16, u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mnemonic {
+    Add,
+    Or,
+    Adc,
+    Sbb,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    Shl,
+    Shr,
+    Sar,
+    Test,
+    Not,
+    Neg,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+    Inc,
+    Dec,
+    Push,
+    Pop,
+    Mov,
+    Lea,
+    Les,
+    Lds,
+    Xchg,
+    Daa,
+    Das,
+    Aaa,
+    Aas,
+    Aam,
+    Aad,
+    Cbw,
+    Cwd,
+    Xlat,
+    Lahf,
+    Sahf,
+    Pushf,
+    Popf,
+    Movs,
+    Cmps,
+    Stos,
+    Lods,
+    Scas,
+    /// Conditional jump, with the condition in the low nibble of 70h-7Fh.
+    Jcc(u8),
+    Jmp,
+    JmpFar,
+    Call,
+    CallFar,
+    Ret,
+    Retf,
+    Int,
+    Int3,
+    Into,
+    Iret,
+    Loop,
+    Loopz,
+    Loopnz,
+    Jcxz,
+    In,
+    Out,
+    Hlt,
+    Cmc,
+    Clc,
+    Stc,
+    Cli,
+    Sti,
+    Cld,
+    Std,
+    Wait,
+    /// Coprocessor escape, D8h-DFh, with the opcode and reg bits combined.
+    Esc(u8),
+    Nop,
+    Pusha,
+    Popa,
+    Bound,
+    Enter,
+    Leave,
+    Ins,
+    Outs,
+    /// Undocumented D6h, sets AL to FFh if CF is set and to 0 otherwise.
+    Salc,
+}
+
+pub const ALU_OPS: [Mnemonic; 8] = [
+    Mnemonic::Add,
+    Mnemonic::Or,
+    Mnemonic::Adc,
+    Mnemonic::Sbb,
+    Mnemonic::And,
+    Mnemonic::Sub,
+    Mnemonic::Xor,
+    Mnemonic::Cmp,
+];
+
+pub const SHIFT_OPS: [Option<Mnemonic>; 8] = [
+    Some(Mnemonic::Rol),
+    Some(Mnemonic::Ror),
+    Some(Mnemonic::Rcl),
+    Some(Mnemonic::Rcr),
+    Some(Mnemonic::Shl),
+    Some(Mnemonic::Shr),
+    None,
+    Some(Mnemonic::Sar),
+];
+
+/// A decoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub mnemonic: Mnemonic,
+    pub width: Width,
+    pub dst: Option<Operand>,
+    pub src: Option<Operand>,
+    /// Segment override prefix.
+    pub segment: Option<u8>,
+    pub rep: Option<Rep>,
+    pub lock: bool,
+    pub len: u8,
+    /// Third operand of the 80186 `IMUL r16, r/m16, imm`.
+    pub imm: Option<u16>,
+}
+
+enum Rm {
+    Reg(u8),
+    Mem(Address),
+}
+
+impl Rm {
+    fn operand(&self, width: Width) -> Operand {
+        match (self, width) {
+            (Rm::Reg(r), Width::Byte) => Operand::Reg8(*r),
+            (Rm::Reg(r), Width::Word) => Operand::Reg16(*r),
+            (Rm::Mem(a), _) => Operand::Mem(*a),
+        }
+    }
+}
+
+struct Reader<'a> {
+    code: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let b = *self
+            .code
+            .get(self.pos)
+            .ok_or_else(|| "truncated instruction".to_string())?;
+        self.pos += 1;
+
+        Ok(b)
+    }
+
+    fn word(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn imm(&mut self, width: Width) -> Result<u16, String> {
+        match width {
+            Width::Byte => Ok(self.byte()? as u16),
+            Width::Word => self.word(),
+        }
+    }
+
+    /// Sign-extended 8-bit immediate.
+    fn simm8(&mut self) -> Result<u16, String> {
+        Ok(self.byte()? as i8 as u16)
+    }
+
+    fn modrm(&mut self) -> Result<(u8, Rm), String> {
+        let b = self.byte()?;
+        let (md, reg, rm) = (b >> 6, (b >> 3) & 7, b & 7);
+
+        let rm = match md {
+            3 => Rm::Reg(rm),
+            0 if rm == 6 => Rm::Mem(Address {
+                rm: None,
+                disp: self.word()?,
+            }),
+            0 => Rm::Mem(Address { rm: Some(rm), disp: 0 }),
+            1 => Rm::Mem(Address {
+                rm: Some(rm),
+                disp: self.simm8()?,
+            }),
+            _ => Rm::Mem(Address {
+                rm: Some(rm),
+                disp: self.word()?,
+            }),
+        };
+
+        Ok((reg, rm))
+    }
+}
+
+fn width_of(op: u8) -> Width {
+    if op & 1 == 0 {
+        Width::Byte
+    } else {
+        Width::Word
+    }
+}
+
+fn acc(width: Width) -> Operand {
+    match width {
+        Width::Byte => Operand::Reg8(0),
+        Width::Word => Operand::Reg16(0),
+    }
+}
+
+/// Decodes the instruction at the start of `code`. `ip` is the offset the
+/// instruction was fetched from and is used to resolve relative jumps.
+/// Everything up to the 80286's real mode instructions is accepted.
+pub fn decode(code: &[u8], ip: u16) -> Result<Instruction, String> {
+    decode_for(code, ip, Model::I80286, false)
+}
+
+/// Decodes an instruction as `model` sees it, opcodes it lacks being
+/// invalid. With `quirks` the undocumented opcodes of real chips decode
+/// too: SALC everywhere, and on the 8086 and 8088 POP CS, MOV CS, segment
+/// register fields of 4-7 aliasing 0-3 and 60h-6Fh aliasing the
+/// conditional jumps at 70h-7Fh.
+pub fn decode_for(code: &[u8], ip: u16, model: Model, quirks: bool) -> Result<Instruction, String> {
+    use Mnemonic::*;
+    use Operand::*;
+
+    let mut r = Reader { code, pos: 0 };
+    let mut segment = None;
+    let mut rep = None;
+    let mut lock = false;
+    let mut imm = None;
+
+    let op = loop {
+        match r.byte()? {
+            0x26 => segment = Some(ES),
+            0x2E => segment = Some(CS),
+            0x36 => segment = Some(SS),
+            0x3E => segment = Some(DS),
+            0xF0 => lock = true,
+            0xF2 => rep = Some(Rep::Repne),
+            0xF3 => rep = Some(Rep::Rep),
+            b => break b,
+        }
+    };
+
+    let early = quirks && !model.has_80186_instructions();
+    let op = if early && (0x60..=0x6F).contains(&op) { op | 0x10 } else { op };
+
+    if !model.supports_opcode(op) {
+        return Err(format!("invalid opcode {op:02X}h on the {}", model.name()));
+    }
+
+    let w = width_of(op);
+    let (mnemonic, width, dst, src) = match op {
+        0x00..=0x3F if op & 7 < 6 => {
+            let m = ALU_OPS[(op >> 3) as usize];
+
+            match op & 7 {
+                0 | 1 => {
+                    let (reg, rm) = r.modrm()?;
+                    let reg = if w == Width::Byte { Reg8(reg) } else { Reg16(reg) };
+                    (m, w, Some(rm.operand(w)), Some(reg))
+                }
+                2 | 3 => {
+                    let (reg, rm) = r.modrm()?;
+                    let reg = if w == Width::Byte { Reg8(reg) } else { Reg16(reg) };
+                    (m, w, Some(reg), Some(rm.operand(w)))
+                }
+                _ => (m, w, Some(acc(w)), Some(Imm(r.imm(w)?))),
+            }
+        }
+        0x06 | 0x0E | 0x16 | 0x1E => (Push, Width::Word, Some(Seg(op >> 3)), None),
+        0x07 | 0x17 | 0x1F => (Pop, Width::Word, Some(Seg(op >> 3)), None),
+        0x0F if early => (Pop, Width::Word, Some(Seg(CS)), None),
+        0x27 => (Daa, Width::Byte, None, None),
+        0x2F => (Das, Width::Byte, None, None),
+        0x37 => (Aaa, Width::Byte, None, None),
+        0x3F => (Aas, Width::Byte, None, None),
+        0x40..=0x47 => (Inc, Width::Word, Some(Reg16(op & 7)), None),
+        0x48..=0x4F => (Dec, Width::Word, Some(Reg16(op & 7)), None),
+        0x50..=0x57 => (Push, Width::Word, Some(Reg16(op & 7)), None),
+        0x58..=0x5F => (Pop, Width::Word, Some(Reg16(op & 7)), None),
+        0x60 => (Pusha, Width::Word, None, None),
+        0x61 => (Popa, Width::Word, None, None),
+        0x62 => {
+            let (reg, rm) = r.modrm()?;
+
+            if let Rm::Reg(_) = rm {
+                return Err("BOUND needs a memory operand".to_string());
+            }
+            (Bound, Width::Word, Some(Reg16(reg)), Some(rm.operand(Width::Word)))
+        }
+        0x68 => (Push, Width::Word, Some(Imm(r.word()?)), None),
+        0x6A => (Push, Width::Word, Some(Imm(r.simm8()?)), None),
+        0x69 | 0x6B => {
+            let (reg, rm) = r.modrm()?;
+            imm = Some(if op == 0x69 { r.word()? } else { r.simm8()? });
+            (Imul, Width::Word, Some(Reg16(reg)), Some(rm.operand(Width::Word)))
+        }
+        0x6C | 0x6D => (Ins, w, None, None),
+        0x6E | 0x6F => (Outs, w, None, None),
+        0x70..=0x7F => {
+            let disp = r.simm8()?;
+            (Jcc(op & 0xF), Width::Byte, Some(Rel(0)), Some(Imm(disp)))
+        }
+        0x80..=0x83 => {
+            let (reg, rm) = r.modrm()?;
+            let imm = if op == 0x83 { r.simm8()? } else { r.imm(w)? };
+            (ALU_OPS[reg as usize], w, Some(rm.operand(w)), Some(Imm(imm)))
+        }
+        0x84..=0x87 => {
+            let (reg, rm) = r.modrm()?;
+            let reg = if w == Width::Byte { Reg8(reg) } else { Reg16(reg) };
+            let m = if op < 0x86 { Test } else { Xchg };
+            (m, w, Some(rm.operand(w)), Some(reg))
+        }
+        0x88..=0x8B => {
+            let (reg, rm) = r.modrm()?;
+            let reg = if w == Width::Byte { Reg8(reg) } else { Reg16(reg) };
+
+            if op & 2 == 0 {
+                (Mov, w, Some(rm.operand(w)), Some(reg))
+            } else {
+                (Mov, w, Some(reg), Some(rm.operand(w)))
+            }
+        }
+        0x8C => {
+            let (reg, rm) = r.modrm()?;
+
+            if reg > 3 && !early {
+                return Err(format!("invalid opcode {op:02X}h /{reg}"));
+            }
+            (Mov, Width::Word, Some(rm.operand(Width::Word)), Some(Seg(reg & 3)))
+        }
+        0x8D | 0xC4 | 0xC5 => {
+            let (reg, rm) = r.modrm()?;
+
+            if let Rm::Reg(_) = rm {
+                return Err(format!("{op:02X}h needs a memory operand"));
+            }
+
+            let m = match op {
+                0x8D => Lea,
+                0xC4 => Les,
+                _ => Lds,
+            };
+            (m, Width::Word, Some(Reg16(reg)), Some(rm.operand(Width::Word)))
+        }
+        0x8E => {
+            let (reg, rm) = r.modrm()?;
+
+            if (reg > 3 || reg == CS) && !early {
+                return Err(format!("invalid opcode {op:02X}h /{reg}"));
+            }
+            (Mov, Width::Word, Some(Seg(reg & 3)), Some(rm.operand(Width::Word)))
+        }
+        0x8F => {
+            let (_, rm) = r.modrm()?;
+            (Pop, Width::Word, Some(rm.operand(Width::Word)), None)
+        }
+        0x90 => (Nop, Width::Word, None, None),
+        0x91..=0x97 => (Xchg, Width::Word, Some(Reg16(0)), Some(Reg16(op & 7))),
+        0x98 => (Cbw, Width::Byte, None, None),
+        0x99 => (Cwd, Width::Word, None, None),
+        0x9A => {
+            let off = r.word()?;
+            let seg = r.word()?;
+            (CallFar, Width::Word, Some(Far(seg, off)), None)
+        }
+        0x9B => (Wait, Width::Byte, None, None),
+        0x9C => (Pushf, Width::Word, None, None),
+        0x9D => (Popf, Width::Word, None, None),
+        0x9E => (Sahf, Width::Byte, None, None),
+        0x9F => (Lahf, Width::Byte, None, None),
+        0xA0..=0xA3 => {
+            let mem = Mem(Address {
+                rm: None,
+                disp: r.word()?,
+            });
+
+            if op & 2 == 0 {
+                (Mov, w, Some(acc(w)), Some(mem))
+            } else {
+                (Mov, w, Some(mem), Some(acc(w)))
+            }
+        }
+        0xA4 | 0xA5 => (Movs, w, None, None),
+        0xA6 | 0xA7 => (Cmps, w, None, None),
+        0xA8 | 0xA9 => (Test, w, Some(acc(w)), Some(Imm(r.imm(w)?))),
+        0xAA | 0xAB => (Stos, w, None, None),
+        0xAC | 0xAD => (Lods, w, None, None),
+        0xAE | 0xAF => (Scas, w, None, None),
+        0xB0..=0xB7 => (Mov, Width::Byte, Some(Reg8(op & 7)), Some(Imm(r.byte()? as u16))),
+        0xB8..=0xBF => (Mov, Width::Word, Some(Reg16(op & 7)), Some(Imm(r.word()?))),
+        0xC0 | 0xC1 => {
+            let (reg, rm) = r.modrm()?;
+            let m = SHIFT_OPS[reg as usize].ok_or_else(|| format!("invalid opcode {op:02X}h /{reg}"))?;
+
+            (m, w, Some(rm.operand(w)), Some(Imm(r.byte()? as u16)))
+        }
+        0xC2 => (Ret, Width::Word, Some(Imm(r.word()?)), None),
+        0xC3 => (Ret, Width::Word, None, None),
+        0xC6 | 0xC7 => {
+            let (reg, rm) = r.modrm()?;
+
+            if reg != 0 {
+                return Err(format!("invalid opcode {op:02X}h /{reg}"));
+            }
+
+            (Mov, w, Some(rm.operand(w)), Some(Imm(r.imm(w)?)))
+        }
+        0xC8 => {
+            let size = r.word()?;
+            (Enter, Width::Word, Some(Imm(size)), Some(Imm(r.byte()? as u16)))
+        }
+        0xC9 => (Leave, Width::Word, None, None),
+        0xCA => (Retf, Width::Word, Some(Imm(r.word()?)), None),
+        0xCB => (Retf, Width::Word, None, None),
+        0xCC => (Int3, Width::Byte, None, None),
+        0xCD => (Int, Width::Byte, Some(Imm(r.byte()? as u16)), None),
+        0xCE => (Into, Width::Byte, None, None),
+        0xCF => (Iret, Width::Word, None, None),
+        0xD0..=0xD3 => {
+            let (reg, rm) = r.modrm()?;
+            let m = SHIFT_OPS[reg as usize].ok_or_else(|| format!("invalid opcode {op:02X}h /{reg}"))?;
+            let count = if op < 0xD2 { Imm(1) } else { Reg8(1) };
+
+            (m, w, Some(rm.operand(w)), Some(count))
+        }
+        0xD4 => (Aam, Width::Byte, Some(Imm(r.byte()? as u16)), None),
+        0xD5 => (Aad, Width::Byte, Some(Imm(r.byte()? as u16)), None),
+        0xD6 if quirks => (Salc, Width::Byte, None, None),
+        0xD7 => (Xlat, Width::Byte, None, None),
+        0xD8..=0xDF => {
+            let (reg, rm) = r.modrm()?;
+            (Esc((op & 7) << 3 | reg), Width::Word, Some(rm.operand(Width::Word)), None)
+        }
+        0xE0..=0xE3 => {
+            let disp = r.simm8()?;
+            let m = [Loopnz, Loopz, Loop, Jcxz][(op & 3) as usize];
+            (m, Width::Byte, Some(Rel(0)), Some(Imm(disp)))
+        }
+        0xE4 | 0xE5 => (In, w, Some(acc(w)), Some(Imm(r.byte()? as u16))),
+        0xE6 | 0xE7 => (Out, w, Some(Imm(r.byte()? as u16)), Some(acc(w))),
+        0xE8 => {
+            let disp = r.word()?;
+            (Call, Width::Word, Some(Rel(0)), Some(Imm(disp)))
+        }
+        0xE9 => {
+            let disp = r.word()?;
+            (Jmp, Width::Word, Some(Rel(0)), Some(Imm(disp)))
+        }
+        0xEA => {
+            let off = r.word()?;
+            let seg = r.word()?;
+            (JmpFar, Width::Word, Some(Far(seg, off)), None)
+        }
+        0xEB => {
+            let disp = r.simm8()?;
+            (Jmp, Width::Byte, Some(Rel(0)), Some(Imm(disp)))
+        }
+        0xEC | 0xED => (In, w, Some(acc(w)), Some(Reg16(2))),
+        0xEE | 0xEF => (Out, w, Some(Reg16(2)), Some(acc(w))),
+        0xF4 => (Hlt, Width::Byte, None, None),
+        0xF5 => (Cmc, Width::Byte, None, None),
+        0xF6 | 0xF7 => {
+            let (reg, rm) = r.modrm()?;
+            let dst = Some(rm.operand(w));
+
+            match reg {
+                0 => (Test, w, dst, Some(Imm(r.imm(w)?))),
+                2 => (Not, w, dst, None),
+                3 => (Neg, w, dst, None),
+                4 => (Mul, w, dst, None),
+                5 => (Imul, w, dst, None),
+                6 => (Div, w, dst, None),
+                7 => (Idiv, w, dst, None),
+                _ => return Err(format!("invalid opcode {op:02X}h /{reg}")),
+            }
+        }
+        0xF8 => (Clc, Width::Byte, None, None),
+        0xF9 => (Stc, Width::Byte, None, None),
+        0xFA => (Cli, Width::Byte, None, None),
+        0xFB => (Sti, Width::Byte, None, None),
+        0xFC => (Cld, Width::Byte, None, None),
+        0xFD => (Std, Width::Byte, None, None),
+        0xFE | 0xFF => {
+            let (reg, rm) = r.modrm()?;
+            let dst = Some(rm.operand(w));
+            let is_mem = matches!(rm, Rm::Mem(_));
+
+            match (reg, w) {
+                (0, _) => (Inc, w, dst, None),
+                (1, _) => (Dec, w, dst, None),
+                (2, Width::Word) => (Call, w, dst, None),
+                (3, Width::Word) if is_mem => (CallFar, w, dst, None),
+                (4, Width::Word) => (Jmp, w, dst, None),
+                (5, Width::Word) if is_mem => (JmpFar, w, dst, None),
+                (6, Width::Word) => (Push, w, dst, None),
+                _ => return Err(format!("invalid opcode {op:02X}h /{reg}")),
+            }
+        }
+        _ => return Err(format!("invalid opcode {op:02X}h")),
+    };
+
+    let len = r.pos as u8;
+    let next = ip.wrapping_add(len as u16);
+
+    // relative targets are only known once the length is
+    let (dst, src) = match (dst, src) {
+        (Some(Rel(_)), Some(Imm(disp))) => (Some(Rel(next.wrapping_add(disp))), None),
+        other => other,
+    };
+
+    Ok(Instruction {
+        mnemonic,
+        width,
+        dst,
+        src,
+        segment,
+        rep,
+        lock,
+        len,
+        imm,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, decode_for, Address, Instruction, Mnemonic::*, Operand::*, Rep, Width, CS, DS, ES};
+    use crate::model::Model;
+
+    #[test]
+    fn modrm_forms() {
+        // ADD [BX+SI+12h], AL
+        let i = decode(&[0x00, 0x40, 0x12], 0).unwrap();
+        assert_eq!(i.mnemonic, Add);
+        assert_eq!(i.dst, Some(Mem(Address { rm: Some(0), disp: 0x12 })));
+        assert_eq!(i.src, Some(Reg8(0)));
+        assert_eq!(i.len, 3);
+
+        // MOV AX, [1234h]
+        let i = decode(&[0x8B, 0x06, 0x34, 0x12], 0).unwrap();
+        assert_eq!(i.dst, Some(Reg16(0)));
+        assert_eq!(i.src, Some(Mem(Address { rm: None, disp: 0x1234 })));
+
+        // SUB WORD [BP-2], 5
+        let i = decode(&[0x83, 0x6E, 0xFE, 0x05], 0).unwrap();
+        assert_eq!(i.mnemonic, Sub);
+        assert_eq!(i.width, Width::Word);
+        assert_eq!(i.dst, Some(Mem(Address { rm: Some(6), disp: 0xFFFE })));
+        assert_eq!(i.src, Some(Imm(5)));
+    }
+
+    #[test]
+    fn prefixes() {
+        // ES: REP MOVSW
+        let i = decode(&[0x26, 0xF3, 0xA5], 0).unwrap();
+
+        assert_eq!(
+            i,
+            Instruction {
+                mnemonic: Movs,
+                width: Width::Word,
+                dst: None,
+                src: None,
+                segment: Some(ES),
+                rep: Some(Rep::Rep),
+                lock: false,
+                len: 3,
+                imm: None,
+            }
+        );
+    }
+
+    #[test]
+    fn relative_targets() {
+        // JNE -2 at 100h jumps to itself
+        let i = decode(&[0x75, 0xFE], 0x100).unwrap();
+        assert_eq!(i.mnemonic, Jcc(5));
+        assert_eq!(i.dst, Some(Rel(0x100)));
+
+        // CALL +3 at 100h
+        let i = decode(&[0xE8, 0x03, 0x00], 0x100).unwrap();
+        assert_eq!(i.dst, Some(Rel(0x106)));
+    }
+
+    #[test]
+    fn model_instruction_sets() {
+        // IMUL AX, BX, 10
+        let i = decode(&[0x6B, 0xC3, 0x0A], 0).unwrap();
+        assert_eq!((i.mnemonic, i.dst, i.src, i.imm), (Imul, Some(Reg16(0)), Some(Reg16(3)), Some(10)));
+
+        // ENTER 8, 0
+        let i = decode(&[0xC8, 0x08, 0x00, 0x00], 0).unwrap();
+        assert_eq!((i.mnemonic, i.dst, i.src), (Enter, Some(Imm(8)), Some(Imm(0))));
+
+        assert!(decode_for(&[0x60], 0, Model::I8086, false).is_err());
+        assert!(decode_for(&[0xC1, 0xE0, 0x04], 0, Model::I8088, false).is_err());
+        assert_eq!(decode_for(&[0x60], 0, Model::I80186, false).unwrap().mnemonic, Pusha);
+    }
+
+    #[test]
+    fn quirks_decode_undocumented_opcodes() {
+        let quirk = |code: &[u8], model| decode_for(code, 0x100, model, true).map(|i| (i.mnemonic, i.dst));
+
+        assert_eq!(quirk(&[0xD6], Model::I80286), Ok((Salc, None)));
+        assert_eq!(quirk(&[0x0F], Model::I8088), Ok((Pop, Some(Seg(CS)))));
+        assert_eq!(quirk(&[0x64, 0x02], Model::I8086), Ok((Jcc(4), Some(Rel(0x104)))));
+        assert_eq!(quirk(&[0x8E, 0xC8], Model::I8086), Ok((Mov, Some(Seg(CS)))));
+        assert_eq!(quirk(&[0x8E, 0xE0], Model::I8086), Ok((Mov, Some(Seg(ES)))));
+        assert!(quirk(&[0x0F], Model::I80186).is_err());
+
+        assert!(decode_for(&[0xD6], 0, Model::I8086, false).is_err());
+        assert!(decode_for(&[0x8E, 0xC8], 0, Model::I8086, false).is_err());
+    }
+
+    #[test]
+    fn invalid_and_truncated() {
+        assert!(decode(&[0x0F], 0).is_err());
+        assert!(decode(&[0xB8, 0x00], 0).is_err());
+        assert!(decode(&[0x8D, 0xC0], 0).is_err());
+        assert_eq!(Address { rm: Some(7), disp: 0 }.default_segment(), DS);
+    }
+}
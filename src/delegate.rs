@@ -1,8 +1,31 @@
-use druid::{Selector, AppDelegate, Handled};
+use druid::{Selector, AppDelegate, Handled, FileInfo};
 
-use crate::{AppState, parser::Parser, cpu::Cpu};
+use proc::{
+    assembler::{self, LOAD_OFFSET, LOAD_SEGMENT},
+    cpu::Cpu,
+    disassembler,
+    disk::{Floppy, BOOT_ADDRESS},
+    error::{Error, Position},
+    fpu::Fpu,
+    output::{self, Format},
+    parser::Parser,
+};
+
+use crate::AppState;
 
 pub const SHOULD: Selector = Selector::new("cpu.should_rerender");
+pub const EXPORT_PNG: Selector<FileInfo> = Selector::new("cpu.export_png");
+pub const DISASSEMBLE_FILE: Selector<FileInfo> = Selector::new("cpu.disassemble_file");
+pub const RUN_PROGRAM: Selector<FileInfo> = Selector::new("cpu.run_program");
+pub const BOOT_IMAGE: Selector<FileInfo> = Selector::new("cpu.boot_image");
+pub const EXPORT_PROGRAM: Selector<FileInfo> = Selector::new("cpu.export_program");
+pub const NMI: Selector = Selector::new("cpu.nmi");
+
+/// Instructions a binary may run for before it is stopped.
+const RUN_LIMIT: u64 = 1_000_000;
+
+/// Bytes disassembled from the entry point of a loaded program.
+const PROGRAM_PREVIEW: usize = 256;
 
 pub struct Delegate;
 
@@ -20,27 +43,176 @@ impl AppDelegate<AppState> for Delegate {
 
             if data.input.is_empty() {
                 data.cpu.parser = Parser::default();
+                data.cpu.model = data.model;
+                data.cpu.quirks = data.quirks;
+                data.cpu.fpu = data.coprocessor.then(Fpu::default);
+                data.cpu.clock = data.clock();
                 empty = true;
             } else {
                 data.cpu = Cpu {
                     parser: Parser::new(&data.input),
+                    model: data.model,
+                    quirks: data.quirks,
+                    fpu: data.coprocessor.then(Fpu::default),
+                    clock: data.clock(),
+                    mouse: data.cpu.mouse.reset_driver(),
                     ..Cpu::default()
                 };
             }
 
-            match data.cpu.run() {
+            let program = assembler::assemble(&data.input, LOAD_OFFSET);
+
+            data.listing = match &program {
+                Ok(p) => p.listing(),
+                Err(e) => e.to_string(),
+            };
+
+            let result = match program {
+                Ok(p) if data.binary && !empty => {
+                    data.cpu.load_program(&p);
+                    data.disassembly = disassembler::listing(&disassembler::disassemble_memory(
+                        &data.cpu.memory,
+                        LOAD_SEGMENT,
+                        LOAD_OFFSET,
+                        p.bytes().len(),
+                    ));
+                    data.cpu.run_code(RUN_LIMIT).map(|_| ()).map_err(|e| {
+                        // point at the source line of the faulting instruction
+                        let line = match data.cpu.get_word("CS") {
+                            LOAD_SEGMENT => p.line_at(data.cpu.ip).unwrap_or(0),
+                            _ => 0,
+                        };
+                        Error::runtime(e).at(Position::new(line, 0))
+                    })
+                }
+                Err(e) if data.binary && !empty => Err(e),
+                _ => data.cpu.run(),
+            };
+
+            match result {
                 _ if empty => {
                     data.output = data.cpu.registers_str();
+                    data.error.clear();
                 }
                 Ok(()) => {
+                    data.output = format!("{}\n{}", data.cpu.registers_str(), data.cpu.state);
+                    data.error.clear();
+                }
+                Err(e) => {
+                    // the registers as they were when it stopped
                     data.output = data.cpu.registers_str();
+                    data.error = crate::error_text(&data.input, &e);
+                }
+            }
+
+            Handled::Yes
+        } else if cmd.is(NMI) {
+            let result = data.cpu.nmi().and_then(|_| data.cpu.run_code(RUN_LIMIT));
+
+            report(data, result);
+
+            Handled::Yes
+        } else if let Some(file) = cmd.get(EXPORT_PNG) {
+            let result = match data.cpu.video.render(&data.cpu.memory) {
+                Some(frame) => frame.save_png(file.path()).map_err(|e| e.to_string()),
+                None => Err("the screen is in a text mode".to_string()),
+            };
+
+            if let Err(e) = result {
+                data.error = format!("could not save {}: {e}", file.path().display());
+            }
+
+            Handled::Yes
+        } else if let Some(file) = cmd.get(RUN_PROGRAM) {
+            data.cpu = Cpu {
+                model: data.model,
+                quirks: data.quirks,
+                fpu: data.coprocessor.then(Fpu::default),
+                clock: data.clock(),
+                mouse: data.cpu.mouse.reset_driver(),
+                ..Cpu::default()
+            };
+
+            let result = data.cpu.load_dos_file(file.path(), "").and_then(|_| {
+                data.disassembly = disassembler::listing(&disassembler::disassemble_memory(
+                    &data.cpu.memory,
+                    data.cpu.get_word("CS"),
+                    data.cpu.ip,
+                    PROGRAM_PREVIEW,
+                ));
+                data.cpu.run_code(RUN_LIMIT)
+            });
+
+            report(data, result);
+
+            Handled::Yes
+        } else if let Some(file) = cmd.get(BOOT_IMAGE) {
+            data.cpu = Cpu {
+                model: data.model,
+                quirks: data.quirks,
+                fpu: data.coprocessor.then(Fpu::default),
+                clock: data.clock(),
+                mouse: data.cpu.mouse.reset_driver(),
+                ..Cpu::default()
+            };
+
+            let result = Floppy::open(file.path()).and_then(|floppy| {
+                data.cpu.boot(floppy.clone())?;
+                data.disassembly = disassembler::listing(&disassembler::disassemble_memory(
+                    &data.cpu.memory,
+                    0,
+                    BOOT_ADDRESS as u16,
+                    PROGRAM_PREVIEW,
+                ));
+                data.cpu.run_code(RUN_LIMIT)?;
+
+                // sectors the program wrote go back to the image
+                match &data.cpu.floppy {
+                    Some(f) if f.bytes() != floppy.bytes() => f.save(file.path()),
+                    _ => Ok(()),
                 }
-                Err(_) => {}
+            });
+
+            report(data, result);
+
+            Handled::Yes
+        } else if let Some(file) = cmd.get(EXPORT_PROGRAM) {
+            let format = Format::from_path(file.path()).unwrap_or(Format::Binary);
+            let origin = if format == Format::Com { 0x100 } else { 0 };
+
+            let result = assembler::assemble(&data.input, origin)
+                .map_err(String::from)
+                .and_then(|p| output::write(&p, format, file.path()));
+
+            if let Err(e) = result {
+                data.error = format!("could not export {}: {e}", file.path().display());
             }
 
+            Handled::Yes
+        } else if let Some(file) = cmd.get(DISASSEMBLE_FILE) {
+            data.disassembly = match disassembler::disassemble_file(file.path(), LOAD_OFFSET) {
+                Ok(lines) => disassembler::listing(&lines),
+                Err(e) => e,
+            };
+
             Handled::Yes
         } else {
             Handled::No
         }
     }    
 }
+
+/// Shows the machine after a run of a program that is not the source, or
+/// why it stopped in the error panel.
+fn report<T>(data: &mut AppState, result: Result<T, String>) {
+    match result {
+        Ok(_) => {
+            data.output = format!("{}\n{}\n{}", data.cpu.registers_str(), data.cpu.state, data.cpu.console);
+            data.error.clear();
+        }
+        Err(e) => {
+            data.output = format!("{}\n{}", data.cpu.registers_str(), data.cpu.console);
+            data.error = e;
+        }
+    }
+}
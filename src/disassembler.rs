@@ -0,0 +1,282 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    cpu::SEGMENT_NAMES,
+    decoder::{decode, Address, Instruction, Mnemonic, Operand, Rep, Width, REG16_NAMES, REG8_NAMES},
+    memory::{linear, Memory},
+};
+
+const CONDITIONS: [&str; 16] = [
+    "JO", "JNO", "JB", "JAE", "JE", "JNE", "JBE", "JA", "JS", "JNS", "JP", "JNP", "JL", "JGE", "JLE", "JG",
+];
+
+/// Base and index registers selected by the r/m field.
+const BASES: [&str; 8] = ["BX+SI", "BX+DI", "BP+SI", "BP+DI", "SI", "DI", "BP", "BX"];
+
+/// Decimal below 10, otherwise hex with an `h` suffix and a leading zero
+/// where needed, so the parser can read it back.
+fn hex(v: u16) -> String {
+    if v < 10 {
+        return v.to_string();
+    }
+
+    let s = format!("{v:X}h");
+    if s.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        format!("0{s}")
+    } else {
+        s
+    }
+}
+
+fn name(m: Mnemonic) -> String {
+    use Mnemonic::*;
+
+    let s = match m {
+        Jcc(cc) => CONDITIONS[cc as usize & 0xF],
+        JmpFar => "JMP",
+        CallFar => "CALL",
+        Int3 => "INT 3",
+        Esc(_) => "ESC",
+        m => return format!("{m:?}").to_uppercase(),
+    };
+
+    s.to_string()
+}
+
+fn address(addr: &Address) -> String {
+    match addr.rm {
+        None => format!("[{}]", hex(addr.disp)),
+        Some(rm) => {
+            let disp = addr.disp as i16;
+            let base = BASES[rm as usize];
+
+            match disp {
+                0 => format!("[{base}]"),
+                d if d < 0 => format!("[{base}-{}]", hex(d.unsigned_abs())),
+                d => format!("[{base}+{}]", hex(d as u16)),
+            }
+        }
+    }
+}
+
+fn operand(ins: &Instruction, op: Operand) -> String {
+    match op {
+        Operand::Reg8(r) => REG8_NAMES[r as usize].to_string(),
+        Operand::Reg16(r) => REG16_NAMES[r as usize].to_string(),
+        Operand::Seg(s) => SEGMENT_NAMES[s as usize].to_string(),
+        Operand::Mem(a) => {
+            let segment = ins
+                .segment
+                .map(|s| format!("{}:", SEGMENT_NAMES[s as usize]))
+                .unwrap_or_default();
+
+            // sizes a register operand does not already give
+            let size = match (ins.mnemonic, ins.width, ins.src) {
+                (Mnemonic::JmpFar | Mnemonic::CallFar, _, _) => "DWORD PTR ",
+                (Mnemonic::Lea | Mnemonic::Les | Mnemonic::Lds, _, _) => "",
+                (_, _, Some(Operand::Reg8(_) | Operand::Reg16(_) | Operand::Seg(_)))
+                    if !matches!(ins.mnemonic, Mnemonic::Rol
+                        | Mnemonic::Ror
+                        | Mnemonic::Rcl
+                        | Mnemonic::Rcr
+                        | Mnemonic::Shl
+                        | Mnemonic::Shr
+                        | Mnemonic::Sar) =>
+                {
+                    ""
+                }
+                (_, _, Some(Operand::Mem(_))) if Some(op) == ins.src => "",
+                (_, Width::Byte, _) => "BYTE PTR ",
+                (_, Width::Word, _) => "WORD PTR ",
+            };
+
+            format!("{size}{segment}{}", address(&a))
+        }
+        Operand::Imm(v) | Operand::Rel(v) => hex(v),
+        Operand::Far(seg, off) => format!("{}:{}", hex(seg), hex(off)),
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Mnemonic::*;
+
+        if self.lock {
+            write!(f, "LOCK ")?;
+        }
+
+        let string_op = matches!(self.mnemonic, Movs | Cmps | Stos | Lods | Scas | Ins | Outs);
+
+        match (self.rep, string_op) {
+            (Some(Rep::Rep), true) if matches!(self.mnemonic, Cmps | Scas) => write!(f, "REPE ")?,
+            (Some(Rep::Rep), _) => write!(f, "REP ")?,
+            (Some(Rep::Repne), _) => write!(f, "REPNE ")?,
+            _ => {}
+        }
+
+        write!(f, "{}", name(self.mnemonic))?;
+
+        if string_op {
+            write!(f, "{}", if self.width == Width::Byte { "B" } else { "W" })?;
+        }
+
+        if let Esc(code) = self.mnemonic {
+            write!(f, " {},", hex(code as u16))?;
+        }
+
+        // string instructions only show an override, on their source
+        if string_op {
+            if let Some(s) = self.segment {
+                write!(f, " {}:[SI]", SEGMENT_NAMES[s as usize])?;
+            }
+            return Ok(());
+        }
+
+        let ops: Vec<String> = [self.dst, self.src]
+            .into_iter()
+            .flatten()
+            .map(|op| operand(self, op))
+            .collect();
+
+        if !ops.is_empty() {
+            write!(f, " {}", ops.join(", "))?;
+        }
+        if let Some(imm) = self.imm {
+            write!(f, ", {}", hex(imm))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// One disassembled instruction. Bytes that do not decode are shown as DB.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub segment: u16,
+    pub offset: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex: Vec<String> = self.bytes.iter().map(|b| format!("{b:02X}")).collect();
+        write!(f, "{:04X}:{:04X}  {:<18}{}", self.segment, self.offset, hex.join(" "), self.text)
+    }
+}
+
+/// Disassembles `code` as if it were loaded at `segment:origin`.
+pub fn disassemble(code: &[u8], segment: u16, origin: u16) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut pos = 0;
+
+    while pos < code.len() {
+        let offset = origin.wrapping_add(pos as u16);
+
+        let (len, text) = match decode(&code[pos..], offset) {
+            Ok(ins) => (ins.len as usize, ins.to_string()),
+            Err(_) => (1, format!("DB {}", hex(code[pos] as u16))),
+        };
+
+        lines.push(Line {
+            segment,
+            offset,
+            bytes: code[pos..pos + len].to_vec(),
+            text,
+        });
+        pos += len;
+    }
+
+    lines
+}
+
+/// Disassembles `len` bytes of memory starting at `segment:offset`.
+pub fn disassemble_memory(memory: &Memory, segment: u16, offset: u16, len: usize) -> Vec<Line> {
+    disassemble(&memory.read(linear(segment, offset), len), segment, offset)
+}
+
+/// Disassembles a flat binary file loaded at `origin`.
+pub fn disassemble_file(path: impl AsRef<Path>, origin: u16) -> Result<Vec<Line>, String> {
+    let path = path.as_ref();
+    let code = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+    Ok(disassemble(&code, 0, origin))
+}
+
+pub fn listing(lines: &[Line]) -> String {
+    lines.iter().map(|l| l.to_string()).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, listing};
+    use crate::assembler::assemble;
+
+    fn text(code: &[u8]) -> Vec<String> {
+        disassemble(code, 0, 0x100).into_iter().map(|l| l.text).collect()
+    }
+
+    #[test]
+    fn intel_syntax() {
+        let code = [
+            0x8B, 0x46, 0xFE, // MOV AX, [BP-2]
+            0x26, 0x80, 0x3F, 0x0A, // CMP BYTE PTR ES:[BX], 0Ah
+            0xF3, 0xA5, // REP MOVSW
+            0xEB, 0xFE, // JMP 109h
+            0xD1, 0xE0, // SHL AX, 1
+            0xFF, 0x1E, 0x00, 0x02, // CALL DWORD PTR [200h]
+            0x69, 0xC3, 0x00, 0x01, // IMUL AX, BX, 100h
+            0x0F, // not an 8086 opcode
+        ];
+
+        assert_eq!(
+            text(&code),
+            [
+                "MOV AX, [BP-2]",
+                "CMP BYTE PTR ES:[BX], 0Ah",
+                "REP MOVSW",
+                "JMP 109h",
+                "SHL AX, 1",
+                "CALL DWORD PTR [200h]",
+                "IMUL AX, BX, 100h",
+                "DB 0Fh",
+            ]
+        );
+    }
+
+    #[test]
+    fn listing_shows_addresses_and_bytes() {
+        let lines = disassemble(&[0xB0, 0x05, 0xCD, 0x21], 0x1000, 0x100);
+
+        assert_eq!(
+            listing(&lines),
+            "1000:0100  B0 05             MOV AL, 5\n1000:0102  CD 21             INT 21h"
+        );
+    }
+
+    #[test]
+    fn assembler_round_trip() {
+        let src = r#"
+            MOV AL, 200
+            MOV [1234h], AL
+            MOV [10h], 7
+            ADD BL, [300h]
+            XOR CX, DX
+            MOV DS, AX
+            MUL AL, CH
+            INC DH
+            NOT BYTE PTR [20h]
+            IN AL, 61h
+            OUT 43h, AL
+            INT 10h
+        "#;
+
+        let code = assemble(src, 0x100).unwrap().bytes();
+        let text: Vec<String> = text(&code);
+        let again = assemble(&text.join("\n"), 0x100).unwrap().bytes();
+
+        assert_eq!(code, again);
+    }
+}
@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// Address the BIOS loads the boot sector to, 0000:7C00.
+pub const BOOT_ADDRESS: u32 = 0x7C00;
+
+/// INT 13h status codes.
+pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_BAD_COMMAND: u8 = 0x01;
+pub const STATUS_SECTOR_NOT_FOUND: u8 = 0x04;
+pub const STATUS_NOT_READY: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Geometry {
+    pub cylinders: u16,
+    pub heads: u8,
+    pub sectors: u8,
+}
+
+impl Geometry {
+    /// Geometry of the standard PC floppy formats, by image size.
+    pub fn from_size(size: usize) -> Option<Self> {
+        let (cylinders, heads, sectors) = match size / 1024 {
+            160 => (40, 1, 8),
+            180 => (40, 1, 9),
+            320 => (40, 2, 8),
+            360 => (40, 2, 9),
+            720 => (80, 2, 9),
+            1200 => (80, 2, 15),
+            1440 => (80, 2, 18),
+            2880 => (80, 2, 36),
+            _ => return None,
+        };
+
+        if !size.is_multiple_of(1024) {
+            return None;
+        }
+
+        Some(Self {
+            cylinders,
+            heads,
+            sectors,
+        })
+    }
+
+    /// Drive type INT 13h AH=08h reports in BL for a drive taking this
+    /// format: 1 for 360K, 2 for 1.2M, 3 for 720K, 4 for 1.44M and 5 for
+    /// 2.88M drives.
+    pub fn drive_type(&self) -> u8 {
+        match (self.cylinders, self.sectors) {
+            (80, 9) => 3,
+            (80, 15) => 2,
+            (80, 18) => 4,
+            (80, 36) => 5,
+            _ => 1,
+        }
+    }
+
+    /// Logical block address of a cylinder/head/sector triple, sectors
+    /// counting from 1.
+    pub fn lba(&self, cylinder: u16, head: u8, sector: u8) -> Option<usize> {
+        if cylinder >= self.cylinders || head >= self.heads || sector == 0 || sector > self.sectors {
+            return None;
+        }
+
+        let track = cylinder as usize * self.heads as usize + head as usize;
+        Some(track * self.sectors as usize + sector as usize - 1)
+    }
+}
+
+/// A raw floppy disk image held in memory.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Floppy {
+    data: Arc<Vec<u8>>,
+    pub geometry: Geometry,
+}
+
+impl Floppy {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, String> {
+        let geometry = Geometry::from_size(data.len())
+            .ok_or_else(|| format!("{} bytes is not a floppy image size", data.len()))?;
+
+        Ok(Self {
+            data: Arc::new(data),
+            geometry,
+        })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        Self::from_bytes(data).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Writes the image, including any sectors the program wrote, to disk.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, &*self.data).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn boot_sector(&self) -> &[u8] {
+        &self.data[..SECTOR_SIZE]
+    }
+
+    /// True if the boot sector ends with the 55h AAh signature.
+    pub fn is_bootable(&self) -> bool {
+        self.data[510] == 0x55 && self.data[511] == 0xAA
+    }
+
+    fn range(&self, cylinder: u16, head: u8, sector: u8, count: u8) -> Result<std::ops::Range<usize>, u8> {
+        let lba = self
+            .geometry
+            .lba(cylinder, head, sector)
+            .ok_or(STATUS_SECTOR_NOT_FOUND)?;
+        let start = lba * SECTOR_SIZE;
+        let end = start + count as usize * SECTOR_SIZE;
+
+        if count == 0 || end > self.data.len() {
+            return Err(STATUS_SECTOR_NOT_FOUND);
+        }
+
+        Ok(start..end)
+    }
+
+    pub fn read_sectors(&self, cylinder: u16, head: u8, sector: u8, count: u8) -> Result<&[u8], u8> {
+        let range = self.range(cylinder, head, sector, count)?;
+        Ok(&self.data[range])
+    }
+
+    pub fn write_sectors(&mut self, cylinder: u16, head: u8, sector: u8, data: &[u8]) -> Result<(), u8> {
+        let count = (data.len() / SECTOR_SIZE) as u8;
+        let range = self.range(cylinder, head, sector, count)?;
+
+        Arc::make_mut(&mut self.data)[range].copy_from_slice(&data[..count as usize * SECTOR_SIZE]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Floppy, Geometry, SECTOR_SIZE, STATUS_SECTOR_NOT_FOUND};
+
+    #[test]
+    fn geometries() {
+        let g = Geometry::from_size(1440 * 1024).unwrap();
+        assert_eq!((g.cylinders, g.heads, g.sectors), (80, 2, 18));
+        assert_eq!(g.lba(0, 0, 1), Some(0));
+        assert_eq!(g.lba(0, 1, 1), Some(18));
+        assert_eq!(g.lba(1, 0, 1), Some(36));
+        assert_eq!(g.lba(0, 0, 19), None);
+
+        assert!(Geometry::from_size(1000).is_none());
+
+        let types = [360, 1200, 720, 1440, 2880].map(|k| Geometry::from_size(k * 1024).unwrap().drive_type());
+        assert_eq!(types, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn read_and_write() {
+        let mut f = Floppy::from_bytes(vec![0; 360 * 1024]).unwrap();
+
+        f.write_sectors(1, 1, 9, &[7; SECTOR_SIZE]).unwrap();
+        let lba = f.geometry.lba(1, 1, 9).unwrap();
+
+        assert_eq!(f.bytes()[lba * SECTOR_SIZE], 7);
+        assert_eq!(f.read_sectors(1, 1, 9, 1).unwrap(), &[7; SECTOR_SIZE][..]);
+        assert_eq!(f.read_sectors(39, 1, 9, 2), Err(STATUS_SECTOR_NOT_FOUND));
+        assert!(!f.is_bootable());
+    }
+}
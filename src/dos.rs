@@ -0,0 +1,197 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    cpu::{Cpu, State},
+    memory::linear,
+};
+
+/// Segment programs are loaded at, their PSP at offset 0.
+pub const PSP_SEGMENT: u16 = 0x1000;
+
+/// Size of the program segment prefix.
+pub const PSP_SIZE: u16 = 0x100;
+
+/// A .COM image has to fit in its segment after the PSP, with room for a stack.
+pub const COM_MAX_SIZE: usize = 0xFF00;
+
+/// Longest command tail the PSP holds, not counting the closing CR.
+pub const TAIL_MAX: usize = 126;
+
+impl Cpu {
+    /// Writes a program segment prefix at `seg:0`: INT 20h at offset 0, the
+    /// memory size at 2 and the command tail at 80h.
+    pub fn build_psp(&mut self, seg: u16, tail: &str) -> Result<(), String> {
+        if tail.len() > TAIL_MAX {
+            return Err(format!("command tail is longer than {TAIL_MAX} characters"));
+        }
+
+        let base = linear(seg, 0);
+        let mut psp = vec![0; PSP_SIZE as usize];
+
+        psp[0..2].copy_from_slice(&[0xCD, 0x20]);
+        // the first segment past the RAM programs can have
+        psp[2..4].copy_from_slice(&((self.memory.ram_size() / 16) as u16).to_le_bytes());
+        // unopened FCBs are blank
+        psp[0x5D..0x68].fill(b' ');
+        psp[0x6D..0x78].fill(b' ');
+        psp[0x80] = tail.len() as u8;
+        psp[0x81..0x81 + tail.len()].copy_from_slice(tail.as_bytes());
+        psp[0x81 + tail.len()] = 0x0D;
+
+        self.memory.load(base, &psp);
+        Ok(())
+    }
+
+    /// Loads a .COM image at offset 100h of `PSP_SEGMENT` with all segment
+    /// registers on the PSP, the way DOS starts one. The word 0 on top of the
+    /// stack makes a final RET reach the INT 20h in the PSP.
+    pub fn load_com(&mut self, code: &[u8], tail: &str) -> Result<(), String> {
+        if code.len() > COM_MAX_SIZE {
+            return Err(format!("{} bytes is too large for a .COM program", code.len()));
+        }
+        // the program, and its stack at the top, get the whole segment
+        if linear(PSP_SEGMENT, 0) + 0x10000 > self.memory.ram_size() {
+            return Err(format!("a .COM program needs 64K of RAM from {PSP_SEGMENT:04X}:0000"));
+        }
+
+        self.build_psp(PSP_SEGMENT, tail)?;
+        self.load_code(PSP_SEGMENT, PSP_SIZE, code);
+        self.segments = [PSP_SEGMENT; 4];
+        self.pointers = [0xFFFE, 0, 0, 0];
+        self.write16(PSP_SEGMENT, 0xFFFE, 0);
+        self.set_word("AX", 0)?;
+
+        Ok(())
+    }
+
+    pub fn load_com_file(&mut self, path: impl AsRef<Path>, tail: &str) -> Result<(), String> {
+        let path = path.as_ref();
+        let code = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        self.load_com(&code, tail)
+    }
+
+    /// Ends the program, as INT 20h and INT 21h functions 00h and 4Ch do.
+    pub(crate) fn terminate(&mut self, code: u8) {
+        self.state = State::Terminated(code);
+    }
+
+    /// DOS services.
+    pub(crate) fn int21(&mut self) -> Result<(), String> {
+        let ah = self.get_reg("AH").extract();
+        let now = self.clock.date_time(self.cycles);
+
+        match ah {
+            0x00 => self.terminate(0),
+            0x02 => self.console.push(self.get_reg("DL").extract() as char),
+            0x09 => {
+                let (ds, dx) = (self.get_word("DS"), self.get_word("DX"));
+                let text = (0..=0xFFFF)
+                    .map(|i| self.read8(ds, dx.wrapping_add(i)))
+                    .take_while(|&c| c != b'$')
+                    .collect::<Vec<_>>();
+
+                // the whole segment was read without finding the end
+                if text.len() == 0x10000 {
+                    return Err(format!("no '$' ends the string at {ds:04X}:{dx:04X}"));
+                }
+                self.console.extend(text.into_iter().map(char::from));
+            }
+            // date and time are in UTC, see DateTime
+            0x2A => {
+                self.set_word("CX", now.year)?;
+                self.set_byte("DH", now.month)?;
+                self.set_byte("DL", now.day)?;
+                self.set_byte("AL", now.weekday)?;
+            }
+            0x2C => {
+                self.set_byte("CH", now.hour)?;
+                self.set_byte("CL", now.minute)?;
+                self.set_byte("DH", now.second)?;
+                self.set_byte("DL", now.hundredths)?;
+            }
+            // DOS 3.30
+            0x30 => self.set_word("AX", 0x1E03)?,
+            0x4C => self.terminate(self.get_reg("AL").extract()),
+            _ => return Err(format!("unsupported DOS function {ah:02X}h")),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PSP_SEGMENT, PSP_SIZE};
+    use crate::clock::{Clock, DOS_EPOCH};
+    use crate::cpu::Cpu;
+    use crate::memory::{linear, Memory, MemoryMap};
+
+    #[test]
+    fn date_and_time() {
+        let str = r#"
+            MOV AH, 2Ah
+            INT 21h
+            MOV BH, DH
+            MOV BL, DL
+            MOV AH, 2Ch
+            INT 21h
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        // 1980-03-01 23:59:58
+        cpu.clock = Clock::virtual_at(DOS_EPOCH + 60 * 86400 + 86398);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.get_word("BX"), 0x0301);
+        assert_eq!(cpu.get_reg("AL").extract(), 6);
+        assert_eq!(cpu.get_word("CX"), 23 << 8 | 59);
+        assert_eq!(cpu.get_reg("DH").extract(), 58);
+    }
+
+    #[test]
+    fn com_programs() {
+        let code = [
+            0xB4, 0x09, // MOV AH, 9
+            0xBA, 0x0E, 0x01, // MOV DX, 10Eh
+            0xCD, 0x21, // INT 21h
+            0xA0, 0x81, 0x00, // MOV AL, [81h]
+            0xB4, 0x4C, // MOV AH, 4Ch
+            0xCD, 0x21, // INT 21h
+            b'h', b'i', b'$',
+        ];
+
+        let mut cpu = Cpu::default();
+        cpu.load_com(&code, "x").unwrap();
+
+        assert_eq!(cpu.read16(PSP_SEGMENT, 0), 0x20CD);
+        assert_eq!(cpu.read16(PSP_SEGMENT, 2), 0xA000);
+        assert_eq!(cpu.memory.read(linear(PSP_SEGMENT, 0x80), 3), [1, b'x', 0x0D]);
+        assert_eq!((cpu.get_word("SS"), cpu.get_word("SP"), cpu.ip), (PSP_SEGMENT, 0xFFFE, PSP_SIZE));
+
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.console, "hi");
+        assert_eq!(cpu.exit_code(), Some(b'x'));
+
+        // a string without its terminator faults instead of wrapping forever
+        let mut cpu = Cpu::from_str("MOV AH, 9\nINT 21h");
+        let e = cpu.run().unwrap_err();
+        assert!(e.message().contains("no '$' ends the string"), "{e}");
+        assert_eq!(cpu.console, "");
+    }
+
+    #[test]
+    fn ret_reaches_int_20h() {
+        let mut cpu = Cpu::default();
+        cpu.load_com(&[0xC3], "").unwrap();
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.exit_code(), Some(0));
+        assert!(cpu.load_com(&vec![0x90; 0x10000], "").is_err());
+
+        cpu.memory = Memory::new(MemoryMap { ram_size: 0x18000, rom_bios: false });
+        assert!(cpu.load_com(&[0xC3], "").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+
+/// Where in the source an error is, counting lines and columns from 1.
+/// Column 0 stands for the whole line and line 0 for no place in the
+/// source, as for an instruction evaluated on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+/// Why the source could not be assembled or interpreted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Text that is not an instruction, register, number or memory operand
+    /// where one is expected.
+    Syntax { position: Position, message: String },
+    /// A word in place of an instruction that is not one, or one the
+    /// processor model lacks.
+    UnknownMnemonic { position: Position, mnemonic: String, message: String },
+    /// Operands the instruction does not take.
+    OperandMismatch { position: Position, message: String },
+    /// A well-formed instruction that failed as it ran.
+    Runtime { position: Position, message: String },
+}
+
+impl Error {
+    pub fn syntax(message: impl Into<String>) -> Self {
+        Error::Syntax {
+            position: Position::default(),
+            message: message.into(),
+        }
+    }
+
+    pub fn unknown_mnemonic(mnemonic: impl Into<String>, message: impl Into<String>) -> Self {
+        Error::UnknownMnemonic {
+            position: Position::default(),
+            mnemonic: mnemonic.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn operands(message: impl Into<String>) -> Self {
+        Error::OperandMismatch {
+            position: Position::default(),
+            message: message.into(),
+        }
+    }
+
+    pub fn runtime(message: impl Into<String>) -> Self {
+        Error::Runtime {
+            position: Position::default(),
+            message: message.into(),
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        match self {
+            Error::Syntax { position, .. }
+            | Error::UnknownMnemonic { position, .. }
+            | Error::OperandMismatch { position, .. }
+            | Error::Runtime { position, .. } => *position,
+        }
+    }
+
+    /// The error without its position.
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Syntax { message, .. }
+            | Error::UnknownMnemonic { message, .. }
+            | Error::OperandMismatch { message, .. }
+            | Error::Runtime { message, .. } => message,
+        }
+    }
+
+    /// The same error placed at `at`.
+    pub fn at(mut self, at: Position) -> Self {
+        match &mut self {
+            Error::Syntax { position, .. }
+            | Error::UnknownMnemonic { position, .. }
+            | Error::OperandMismatch { position, .. }
+            | Error::Runtime { position, .. } => *position = at,
+        }
+        self
+    }
+
+    /// Places an error that has no position yet at `at`.
+    pub fn or_at(self, at: Position) -> Self {
+        if self.position().line == 0 {
+            self.at(at)
+        } else {
+            self
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position() {
+            Position { line: 0, .. } => write!(f, "{}", self.message()),
+            Position { line, column: 0 } => write!(f, "line {line}: {}", self.message()),
+            Position { line, column } => write!(f, "line {line}, column {column}: {}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Lets code still reporting errors as text pass these on with `?`.
+impl From<Error> for String {
+    fn from(e: Error) -> Self {
+        e.to_string()
+    }
+}
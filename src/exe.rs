@@ -0,0 +1,267 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{
+    cpu::{Cpu, State},
+    dos::{PSP_SEGMENT, PSP_SIZE},
+    memory::linear,
+};
+
+const PAGE_SIZE: usize = 512;
+const HEADER_SIZE: usize = 0x1C;
+
+/// The fixed part of an MZ header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MzHeader {
+    pub last_page_bytes: u16,
+    pub pages: u16,
+    pub relocations: u16,
+    pub header_paragraphs: u16,
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub checksum: u16,
+    pub ip: u16,
+    pub cs: u16,
+    pub relocation_table: u16,
+    pub overlay: u16,
+}
+
+/// True if `data` starts with the MZ (or ZM) signature.
+pub fn is_exe(data: &[u8]) -> bool {
+    matches!(data, [b'M', b'Z', ..] | [b'Z', b'M', ..])
+}
+
+impl MzHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if !is_exe(data) {
+            return Err("missing the MZ signature".to_string());
+        }
+        if data.len() < HEADER_SIZE {
+            return Err("truncated MZ header".to_string());
+        }
+
+        let w = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+
+        Ok(Self {
+            last_page_bytes: w(0x02),
+            pages: w(0x04),
+            relocations: w(0x06),
+            header_paragraphs: w(0x08),
+            min_alloc: w(0x0A),
+            max_alloc: w(0x0C),
+            ss: w(0x0E),
+            sp: w(0x10),
+            checksum: w(0x12),
+            ip: w(0x14),
+            cs: w(0x16),
+            relocation_table: w(0x18),
+            overlay: w(0x1A),
+        })
+    }
+
+    pub fn header_size(&self) -> usize {
+        self.header_paragraphs as usize * 16
+    }
+
+    /// Size of the file the header describes, the last page possibly partial.
+    pub fn file_size(&self) -> usize {
+        let size = self.pages as usize * PAGE_SIZE;
+
+        match self.last_page_bytes {
+            0 => size,
+            n => size.saturating_sub(PAGE_SIZE) + n as usize,
+        }
+    }
+
+    /// The header for an image of `image_len` bytes with `relocations`, the
+    /// table right after the fixed part and padded to a paragraph.
+    pub fn for_image(image_len: usize, relocations: &[(u16, u16)]) -> Self {
+        let header_size = (HEADER_SIZE + relocations.len() * 4).div_ceil(16) * 16;
+        let size = header_size + image_len;
+
+        Self {
+            last_page_bytes: (size % PAGE_SIZE) as u16,
+            pages: size.div_ceil(PAGE_SIZE) as u16,
+            relocations: relocations.len() as u16,
+            header_paragraphs: (header_size / 16) as u16,
+            min_alloc: 0,
+            max_alloc: 0xFFFF,
+            ss: 0,
+            sp: 0,
+            checksum: 0,
+            ip: 0,
+            cs: 0,
+            relocation_table: HEADER_SIZE as u16,
+            overlay: 0,
+        }
+    }
+
+    /// The header and relocation table, padded to the header size.
+    pub fn to_bytes(&self, relocations: &[(u16, u16)]) -> Vec<u8> {
+        let fields = [
+            self.last_page_bytes,
+            self.pages,
+            self.relocations,
+            self.header_paragraphs,
+            self.min_alloc,
+            self.max_alloc,
+            self.ss,
+            self.sp,
+            self.checksum,
+            self.ip,
+            self.cs,
+            self.relocation_table,
+            self.overlay,
+        ];
+
+        let mut out = b"MZ".to_vec();
+        out.extend(fields.iter().flat_map(|f| f.to_le_bytes()));
+
+        for (seg, off) in relocations {
+            out.extend(off.to_le_bytes());
+            out.extend(seg.to_le_bytes());
+        }
+
+        out.resize(self.header_size(), 0);
+        out
+    }
+
+    /// Segment:offset pairs of the words that need the load segment added.
+    pub fn relocation_entries(&self, data: &[u8]) -> Result<Vec<(u16, u16)>, String> {
+        let start = self.relocation_table as usize;
+        let end = start + self.relocations as usize * 4;
+        let table = data.get(start..end).ok_or("truncated relocation table")?;
+
+        Ok(table
+            .chunks(4)
+            .map(|e| (u16::from_le_bytes([e[2], e[3]]), u16::from_le_bytes([e[0], e[1]])))
+            .collect())
+    }
+}
+
+impl Cpu {
+    /// Loads an MZ executable right after a PSP at `PSP_SEGMENT`, applies
+    /// its relocations and starts it at the CS:IP and SS:SP from the header.
+    /// DS and ES point at the PSP as under DOS.
+    pub fn load_exe(&mut self, data: &[u8], tail: &str) -> Result<(), String> {
+        let header = MzHeader::parse(data)?;
+        let start = header.header_size();
+        let end = header.file_size().min(data.len());
+        let image = data
+            .get(start..end)
+            .ok_or("MZ header is larger than the file")?;
+
+        let load = PSP_SEGMENT + PSP_SIZE / 16;
+        let paragraphs = image.len().div_ceil(16) + header.min_alloc as usize;
+
+        if (load as usize + paragraphs) * 16 > self.memory.ram_size() as usize {
+            return Err(format!("{} bytes do not fit in conventional memory", paragraphs * 16));
+        }
+
+        self.build_psp(PSP_SEGMENT, tail)?;
+        self.memory.load(linear(load, 0), image);
+
+        for (seg, off) in header.relocation_entries(data)? {
+            let seg = load.wrapping_add(seg);
+            let v = self.read16(seg, off);
+            self.write16(seg, off, v.wrapping_add(load));
+        }
+
+        self.segments = [
+            PSP_SEGMENT,
+            load.wrapping_add(header.cs),
+            load.wrapping_add(header.ss),
+            PSP_SEGMENT,
+        ];
+        self.ip = header.ip;
+        self.pointers = [header.sp, 0, 0, 0];
+        self.set_word("AX", 0)?;
+        self.state = State::Running;
+
+        Ok(())
+    }
+
+    /// Loads a .COM or .EXE program, told apart by the MZ signature like DOS
+    /// does rather than by the file name.
+    pub fn load_dos_file(&mut self, path: impl AsRef<Path>, tail: &str) -> Result<(), String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        if is_exe(&data) {
+            self.load_exe(&data, tail)
+        } else {
+            self.load_com(&data, tail)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MzHeader;
+    use crate::{cpu::Cpu, dos::PSP_SEGMENT};
+
+    /// An .EXE with a 32 byte header, one relocation and code at 10h that
+    /// loads DS from the relocated word.
+    fn sample() -> Vec<u8> {
+        let mut exe = vec![0; 0x20];
+        let fields: [(usize, u16); 11] = [
+            (0x02, 0x40),
+            (0x04, 1),
+            (0x06, 1),
+            (0x08, 2),
+            (0x0E, 0x0003),
+            (0x10, 0x0100),
+            (0x14, 0x0010),
+            (0x16, 0x0000),
+            (0x18, 0x001C),
+            // the relocation: offset 0 in segment 0
+            (0x1C, 0x0000),
+            (0x1E, 0x0000),
+        ];
+
+        exe[..2].copy_from_slice(b"MZ");
+        for (i, v) in fields {
+            exe[i..i + 2].copy_from_slice(&v.to_le_bytes());
+        }
+
+        let mut image = vec![0; 0x20];
+        image[0..2].copy_from_slice(&0x0002u16.to_le_bytes());
+        image[0x10..0x19].copy_from_slice(&[
+            0x2E, 0x8E, 0x1E, 0x00, 0x00, // MOV DS, CS:[0]
+            0xB4, 0x4C, // MOV AH, 4Ch
+            0xCD, 0x21, // INT 21h
+        ]);
+
+        exe.extend(image);
+        exe
+    }
+
+    #[test]
+    fn header() {
+        let h = MzHeader::parse(&sample()).unwrap();
+
+        assert_eq!(h.file_size(), 0x40);
+        assert_eq!(h.header_size(), 0x20);
+        assert_eq!(h.relocation_entries(&sample()).unwrap(), [(0, 0)]);
+        assert!(MzHeader::parse(b"PK").is_err());
+    }
+
+    #[test]
+    fn relocates_and_starts_at_the_header_entry() {
+        let mut cpu = Cpu::default();
+        cpu.load_exe(&sample(), "").unwrap();
+
+        let load = PSP_SEGMENT + 0x10;
+        assert_eq!(cpu.read16(load, 0), load + 2);
+        assert_eq!((cpu.get_word("CS"), cpu.ip), (load, 0x10));
+        assert_eq!((cpu.get_word("SS"), cpu.get_word("SP")), (load + 3, 0x100));
+        assert_eq!(cpu.get_word("DS"), PSP_SEGMENT);
+
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.get_word("DS"), load + 2);
+        assert_eq!(cpu.exit_code(), Some(0));
+    }
+}
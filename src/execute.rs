@@ -0,0 +1,1199 @@
+use crate::{
+    cpu::{Cpu, State, AF, CF, DF, IF, OF, PF, SF, TF, ZF},
+    decoder::{decode_for, Address, Instruction, Mnemonic, Operand, Rep, Width, CS, DS, ES, SS},
+    memory::linear,
+    model::Model,
+    timing::{self, Timing},
+};
+
+/// Longest instruction the decoder is handed, prefixes included.
+const FETCH_LEN: u16 = 16;
+
+/// Slots in `Cpu::registers` for AL, CL, DL, BL, AH, CH, DH and BH.
+const REG8_SLOTS: [usize; 8] = [1, 5, 7, 3, 0, 4, 6, 2];
+
+fn mask(width: Width) -> u32 {
+    match width {
+        Width::Byte => 0xFF,
+        Width::Word => 0xFFFF,
+    }
+}
+
+fn sign(width: Width) -> u32 {
+    match width {
+        Width::Byte => 0x80,
+        Width::Word => 0x8000,
+    }
+}
+
+impl Cpu {
+    pub fn reg8(&self, i: u8) -> u8 {
+        self.registers[REG8_SLOTS[i as usize]].extract()
+    }
+
+    pub fn set_reg8(&mut self, i: u8, v: u8) {
+        let slot = REG8_SLOTS[i as usize];
+        self.registers[slot] = self.registers[slot].set_value(v);
+    }
+
+    /// 16-bit register by its encoding, AX, CX, DX, BX, SP, BP, SI, DI.
+    pub fn reg16(&self, i: u8) -> u16 {
+        match i {
+            0..=3 => u16::from_le_bytes([self.reg8(i), self.reg8(i + 4)]),
+            _ => self.pointers[i as usize - 4],
+        }
+    }
+
+    pub fn set_reg16(&mut self, i: u8, v: u16) {
+        match i {
+            0..=3 => {
+                let [lo, hi] = v.to_le_bytes();
+                self.set_reg8(i, lo);
+                self.set_reg8(i + 4, hi);
+            }
+            _ => self.pointers[i as usize - 4] = v,
+        }
+    }
+
+    pub fn read8(&self, seg: u16, off: u16) -> u8 {
+        self.memory.read_byte(linear(seg, off))
+    }
+
+    pub fn write8(&mut self, seg: u16, off: u16, v: u8) {
+        self.memory.write_byte(linear(seg, off), v);
+    }
+
+    /// Word at `seg:off`, the high byte wrapping around within the segment.
+    pub fn read16(&self, seg: u16, off: u16) -> u16 {
+        u16::from_le_bytes([self.read8(seg, off), self.read8(seg, off.wrapping_add(1))])
+    }
+
+    pub fn write16(&mut self, seg: u16, off: u16, v: u16) {
+        let [lo, hi] = v.to_le_bytes();
+        self.write8(seg, off, lo);
+        self.write8(seg, off.wrapping_add(1), hi);
+    }
+
+    pub fn push(&mut self, v: u16) {
+        self.pointers[0] = self.pointers[0].wrapping_sub(2);
+        self.write16(self.segments[SS as usize], self.pointers[0], v);
+    }
+
+    pub fn pop(&mut self) -> u16 {
+        let v = self.read16(self.segments[SS as usize], self.pointers[0]);
+        self.pointers[0] = self.pointers[0].wrapping_add(2);
+        v
+    }
+
+    /// Copies `code` to `seg:off` and points CS:IP at it.
+    pub fn load_code(&mut self, seg: u16, off: u16, code: &[u8]) {
+        self.memory.load(linear(seg, off), code);
+        self.segments[CS as usize] = seg;
+        self.ip = off;
+        self.state = State::Running;
+    }
+
+    /// Decodes the instruction at CS:IP without executing it.
+    pub fn fetch(&self) -> Result<Instruction, String> {
+        let cs = self.segments[CS as usize];
+        let code: Vec<u8> = (0..FETCH_LEN)
+            .map(|i| self.read8(cs, self.ip.wrapping_add(i)))
+            .collect();
+
+        decode_for(&code, self.ip, self.model, self.quirks).map_err(|e| format!("{cs:04X}:{:04X} {e}", self.ip))
+    }
+
+    /// Executes one instruction from memory at CS:IP. Code is fetched
+    /// afresh every time, so programs can modify themselves. `cycles` grows
+    /// by the instruction's 8086 timing. An instruction that starts and
+    /// ends with TF set is followed by the single step interrupt, INT 1,
+    /// unless it loaded a segment register, which holds off interrupts for
+    /// one instruction.
+    pub fn step(&mut self) -> Result<(), String> {
+        // only an 80286 has a 21st address line to reach above 1 MB with
+        self.memory.a20 = self.quirks && self.model == Model::I80286;
+
+        let ins = self.fetch()?;
+
+        let odd = [ins.dst, ins.src].into_iter().flatten().any(|op| match op {
+            Operand::Mem(a) => self.effective_address(&a) % 2 == 1,
+            _ => false,
+        });
+        let (cx, cl) = (self.reg16(1), self.reg8(1));
+        let trap = self.flag(TF);
+
+        let start = self.ip;
+        self.ip = self.ip.wrapping_add(ins.len as u16);
+        let next = (self.segments[CS as usize], self.ip);
+
+        if let Err(e) = self.execute(&ins) {
+            // a fault leaves IP at the instruction that caused it
+            self.ip = start;
+            return Err(e);
+        }
+
+        let t = Timing {
+            taken: (self.segments[CS as usize], self.ip) != next,
+            repeats: cx.wrapping_sub(self.reg16(1)) as u64,
+            count: cl,
+            odd,
+        };
+        self.cycles += timing::cycles(&ins, t, self.model);
+
+        let loads_segment = matches!((ins.mnemonic, ins.dst), (Mnemonic::Mov | Mnemonic::Pop, Some(Operand::Seg(_))));
+        if trap && self.flag(TF) && self.state == State::Running && !loads_segment {
+            self.raise(1)?;
+        }
+
+        Ok(())
+    }
+
+    /// Steps while the machine is running, at most `limit` instructions.
+    /// Returns the number of instructions executed; an instruction that
+    /// fails leaves the machine faulted.
+    pub fn run_code(&mut self, limit: u64) -> Result<u64, String> {
+        let mut steps = 0;
+
+        while self.state == State::Running && steps < limit {
+            if let Err(e) = self.step() {
+                self.state = State::Faulted(e.clone());
+                return Err(e);
+            }
+            steps += 1;
+        }
+
+        Ok(steps)
+    }
+
+    pub(crate) fn segment_of(&self, ins: &Instruction, addr: &Address) -> u16 {
+        self.segments[ins.segment.unwrap_or(addr.default_segment()) as usize]
+    }
+
+    /// Effective address of a ModR/M memory operand.
+    pub fn effective_address(&self, addr: &Address) -> u16 {
+        let (bx, bp, si, di) = (self.reg16(3), self.reg16(5), self.reg16(6), self.reg16(7));
+
+        let base = match addr.rm {
+            None => 0,
+            Some(0) => bx.wrapping_add(si),
+            Some(1) => bx.wrapping_add(di),
+            Some(2) => bp.wrapping_add(si),
+            Some(3) => bp.wrapping_add(di),
+            Some(4) => si,
+            Some(5) => di,
+            Some(6) => bp,
+            Some(_) => bx,
+        };
+
+        base.wrapping_add(addr.disp)
+    }
+
+    fn read_operand(&self, ins: &Instruction, op: Operand) -> u16 {
+        match op {
+            Operand::Reg8(r) => self.reg8(r) as u16,
+            Operand::Reg16(r) => self.reg16(r),
+            Operand::Seg(s) => self.segments[s as usize],
+            Operand::Mem(a) => {
+                let (seg, off) = (self.segment_of(ins, &a), self.effective_address(&a));
+
+                match ins.width {
+                    Width::Byte => self.read8(seg, off) as u16,
+                    Width::Word => self.read16(seg, off),
+                }
+            }
+            Operand::Imm(v) | Operand::Rel(v) => v,
+            Operand::Far(_, off) => off,
+        }
+    }
+
+    fn write_operand(&mut self, ins: &Instruction, op: Operand, v: u16) -> Result<(), String> {
+        match op {
+            Operand::Reg8(r) => self.set_reg8(r, v as u8),
+            Operand::Reg16(r) => self.set_reg16(r, v),
+            Operand::Seg(s) => self.segments[s as usize] = v,
+            Operand::Mem(a) => {
+                let (seg, off) = (self.segment_of(ins, &a), self.effective_address(&a));
+
+                match ins.width {
+                    Width::Byte => self.write8(seg, off, v as u8),
+                    Width::Word => self.write16(seg, off, v),
+                }
+            }
+            _ => return Err("cannot write to an immediate".to_string()),
+        }
+
+        Ok(())
+    }
+
+    /// Reads a far pointer, offset first, from a memory operand.
+    fn read_far(&self, ins: &Instruction, op: Operand) -> Result<(u16, u16), String> {
+        match op {
+            Operand::Mem(a) => {
+                let (seg, off) = (self.segment_of(ins, &a), self.effective_address(&a));
+                Ok((self.read16(seg, off.wrapping_add(2)), self.read16(seg, off)))
+            }
+            Operand::Far(seg, off) => Ok((seg, off)),
+            _ => Err("expected a far pointer".to_string()),
+        }
+    }
+
+    fn set_szp(&mut self, v: u32, width: Width) {
+        let v = v & mask(width);
+
+        self.set_flag(ZF, v == 0);
+        self.set_flag(SF, v & sign(width) != 0);
+        self.set_flag(PF, (v as u8).count_ones().is_multiple_of(2));
+    }
+
+    /// ADD, OR, ADC, SBB, AND, SUB, XOR and CMP, setting flags. Returns the
+    /// result, which CMP discards.
+    fn alu(&mut self, m: Mnemonic, a: u16, b: u16, width: Width) -> u16 {
+        let (a, b) = (a as u32, b as u32);
+        let carry = self.carry() as u32;
+
+        let r = match m {
+            Mnemonic::Add | Mnemonic::Adc => {
+                let c = if m == Mnemonic::Adc { carry } else { 0 };
+                let r = a + b + c;
+
+                self.set_flag(CF, r > mask(width));
+                self.set_flag(OF, (a ^ r) & (b ^ r) & sign(width) != 0);
+                self.set_flag(AF, (a ^ b ^ r) & 0x10 != 0);
+                r
+            }
+            Mnemonic::Sub | Mnemonic::Sbb | Mnemonic::Cmp => {
+                let c = if m == Mnemonic::Sbb { carry } else { 0 };
+                let r = a.wrapping_sub(b).wrapping_sub(c);
+
+                self.set_flag(CF, b + c > a);
+                self.set_flag(OF, (a ^ b) & (a ^ r) & sign(width) != 0);
+                self.set_flag(AF, (a ^ b ^ r) & 0x10 != 0);
+                r
+            }
+            _ => {
+                let r = match m {
+                    Mnemonic::Or => a | b,
+                    Mnemonic::Xor => a ^ b,
+                    _ => a & b,
+                };
+
+                self.set_flag(CF, false);
+                self.set_flag(OF, false);
+                self.set_flag(AF, false);
+                r
+            }
+        };
+
+        self.set_szp(r, width);
+        (r & mask(width)) as u16
+    }
+
+    /// Rotates and shifts by `count`, bit by bit as the 8086 does.
+    fn shift(&mut self, m: Mnemonic, v: u16, count: u8, width: Width) -> u16 {
+        let (msb, mask) = (sign(width), mask(width));
+        let mut v = v as u32;
+
+        if count == 0 {
+            return v as u16;
+        }
+
+        for _ in 0..count {
+            let cf = self.carry() as u32;
+
+            let (r, out) = match m {
+                Mnemonic::Rol => ((v << 1 | (v & msb != 0) as u32) & mask, v & msb != 0),
+                Mnemonic::Ror => (v >> 1 | if v & 1 != 0 { msb } else { 0 }, v & 1 != 0),
+                Mnemonic::Rcl => ((v << 1 | cf) & mask, v & msb != 0),
+                Mnemonic::Rcr => (v >> 1 | if cf != 0 { msb } else { 0 }, v & 1 != 0),
+                Mnemonic::Shl => ((v << 1) & mask, v & msb != 0),
+                Mnemonic::Shr => (v >> 1, v & 1 != 0),
+                _ => (v >> 1 | (v & msb), v & 1 != 0),
+            };
+
+            // the 8086 works out OF on every step, later chips only for one
+            if count == 1 || (self.quirks && !self.model.has_80186_instructions()) {
+                let of = match m {
+                    Mnemonic::Rol | Mnemonic::Rcl | Mnemonic::Shl => (r & msb != 0) != out,
+                    Mnemonic::Ror | Mnemonic::Rcr => (r ^ (r << 1)) & msb != 0,
+                    Mnemonic::Shr => v & msb != 0,
+                    _ => false,
+                };
+                self.set_flag(OF, of);
+            }
+
+            self.set_carry(out);
+            v = r;
+        }
+
+        if matches!(m, Mnemonic::Shl | Mnemonic::Shr | Mnemonic::Sar) {
+            self.set_szp(v, width);
+        }
+
+        v as u16
+    }
+
+    fn condition(&self, cc: u8) -> bool {
+        let (cf, zf, sf, of, pf) = (self.flag(CF), self.flag(ZF), self.flag(SF), self.flag(OF), self.flag(PF));
+
+        let r = match cc >> 1 {
+            0 => of,
+            1 => cf,
+            2 => zf,
+            3 => cf || zf,
+            4 => sf,
+            5 => pf,
+            6 => sf != of,
+            _ => zf || sf != of,
+        };
+
+        r != (cc & 1 != 0)
+    }
+
+    /// True if the program set up a handler for interrupt `n`.
+    fn has_vector(&self, n: u8) -> bool {
+        self.memory.read_word(n as u32 * 4) != 0 || self.memory.read_word(n as u32 * 4 + 2) != 0
+    }
+
+    /// Raises interrupt `n`. Vectors a program installed in the interrupt
+    /// table are entered like the 8086 does; the rest are served by the
+    /// built-in BIOS and DOS routines.
+    pub fn raise(&mut self, n: u8) -> Result<(), String> {
+        if let Some(result) = self.host_interrupt(n) {
+            return result;
+        }
+
+        let seg = self.memory.read_word(n as u32 * 4 + 2);
+        let off = self.memory.read_word(n as u32 * 4);
+
+        if !self.has_vector(n) {
+            return match n {
+                0 => Err("divide error".to_string()),
+                _ => self.interrupt(n),
+            };
+        }
+
+        self.push(self.model.fix_flags(self.flags));
+        self.set_flag(IF, false);
+        self.set_flag(TF, false);
+        self.push(self.segments[CS as usize]);
+        self.push(self.ip);
+        self.segments[CS as usize] = seg;
+        self.ip = off;
+
+        Ok(())
+    }
+
+    /// A maskable interrupt request from a device. It is taken only with
+    /// IF set, and wakes a cpu waiting after HLT. Returns whether it was
+    /// taken.
+    pub fn interrupt_request(&mut self, n: u8) -> Result<bool, String> {
+        if !self.flag(IF) {
+            return Ok(false);
+        }
+
+        if self.state == State::WaitingForInterrupt {
+            self.state = State::Running;
+        }
+        self.raise(n)?;
+
+        Ok(true)
+    }
+
+    /// The non-maskable interrupt line. INT 2 is taken whatever IF says,
+    /// and wakes a cpu stopped by HLT.
+    pub fn nmi(&mut self) -> Result<(), String> {
+        if matches!(self.state, State::Halted | State::WaitingForInterrupt) {
+            self.state = State::Running;
+        }
+
+        self.raise(2)
+    }
+
+    fn multiply(&mut self, m: Mnemonic, src: u16, width: Width) {
+        let overflow = match (m, width) {
+            (Mnemonic::Mul, Width::Byte) => {
+                let r = self.reg8(0) as u16 * src;
+                self.set_reg16(0, r);
+                r > 0xFF
+            }
+            (Mnemonic::Mul, Width::Word) => {
+                let r = self.reg16(0) as u32 * src as u32;
+                self.set_reg16(0, r as u16);
+                self.set_reg16(2, (r >> 16) as u16);
+                r > 0xFFFF
+            }
+            (_, Width::Byte) => {
+                let r = self.reg8(0) as i8 as i16 * src as u8 as i8 as i16;
+                self.set_reg16(0, r as u16);
+                r != r as i8 as i16
+            }
+            (_, Width::Word) => {
+                let r = self.reg16(0) as i16 as i32 * src as i16 as i32;
+                self.set_reg16(0, r as u16);
+                self.set_reg16(2, (r >> 16) as u16);
+                r != r as i16 as i32
+            }
+        };
+
+        self.set_flag(CF, overflow);
+        self.set_flag(OF, overflow);
+    }
+
+    /// DIV and IDIV. Returns false on a divide error.
+    fn divide(&mut self, m: Mnemonic, src: u16, width: Width) -> bool {
+        if src == 0 {
+            return false;
+        }
+
+        match (m, width) {
+            (Mnemonic::Div, Width::Byte) => {
+                let a = self.reg16(0);
+                let q = a / src;
+
+                if q > 0xFF {
+                    return false;
+                }
+                self.set_reg8(0, q as u8);
+                self.set_reg8(4, (a % src) as u8);
+            }
+            (Mnemonic::Div, Width::Word) => {
+                let a = (self.reg16(2) as u32) << 16 | self.reg16(0) as u32;
+                let q = a / src as u32;
+
+                if q > 0xFFFF {
+                    return false;
+                }
+                self.set_reg16(0, q as u16);
+                self.set_reg16(2, (a % src as u32) as u16);
+            }
+            (_, Width::Byte) => {
+                let a = self.reg16(0) as i16 as i32;
+                let d = src as u8 as i8 as i32;
+                let q = a / d;
+
+                if q != q as i8 as i32 {
+                    return false;
+                }
+                self.set_reg8(0, q as u8);
+                self.set_reg8(4, (a % d) as u8);
+            }
+            (_, Width::Word) => {
+                let a = ((self.reg16(2) as u32) << 16 | self.reg16(0) as u32) as i32 as i64;
+                let d = src as i16 as i64;
+                let q = a / d;
+
+                if q != q as i16 as i64 {
+                    return false;
+                }
+                self.set_reg16(0, q as u16);
+                self.set_reg16(2, (a % d) as u16);
+            }
+        }
+
+        true
+    }
+
+    /// Runs one string instruction iteration on DS:SI and ES:DI.
+    fn string_op(&mut self, ins: &Instruction) {
+        let width = ins.width;
+        let src_seg = self.segments[ins.segment.unwrap_or(DS) as usize];
+        let es = self.segments[ES as usize];
+        let (si, di) = (self.pointers[2], self.pointers[3]);
+        let step = match (width, self.flag(DF)) {
+            (Width::Byte, false) => 1,
+            (Width::Word, false) => 2,
+            (Width::Byte, true) => 0xFFFF,
+            (Width::Word, true) => 0xFFFE,
+        };
+
+        let load = |cpu: &Self, seg, off| match width {
+            Width::Byte => cpu.read8(seg, off) as u16,
+            Width::Word => cpu.read16(seg, off),
+        };
+        let acc = match width {
+            Width::Byte => self.reg8(0) as u16,
+            Width::Word => self.reg16(0),
+        };
+
+        let (uses_si, uses_di) = match ins.mnemonic {
+            Mnemonic::Movs => {
+                let v = load(self, src_seg, si);
+                match width {
+                    Width::Byte => self.write8(es, di, v as u8),
+                    Width::Word => self.write16(es, di, v),
+                }
+                (true, true)
+            }
+            Mnemonic::Cmps => {
+                let (a, b) = (load(self, src_seg, si), load(self, es, di));
+                self.alu(Mnemonic::Cmp, a, b, width);
+                (true, true)
+            }
+            Mnemonic::Stos => {
+                match width {
+                    Width::Byte => self.write8(es, di, acc as u8),
+                    Width::Word => self.write16(es, di, acc),
+                }
+                (false, true)
+            }
+            Mnemonic::Lods => {
+                let v = load(self, src_seg, si);
+                match width {
+                    Width::Byte => self.set_reg8(0, v as u8),
+                    Width::Word => self.set_reg16(0, v),
+                }
+                (true, false)
+            }
+            Mnemonic::Ins => {
+                let port = self.reg16(2);
+                let lo = self.port_in(port);
+
+                match width {
+                    Width::Byte => self.write8(es, di, lo),
+                    Width::Word => {
+                        let hi = self.port_in(port.wrapping_add(1));
+                        self.write16(es, di, u16::from_le_bytes([lo, hi]));
+                    }
+                }
+                (false, true)
+            }
+            Mnemonic::Outs => {
+                let port = self.reg16(2);
+                let [lo, hi] = load(self, src_seg, si).to_le_bytes();
+
+                self.port_out(port, lo);
+                if width == Width::Word {
+                    self.port_out(port.wrapping_add(1), hi);
+                }
+                (true, false)
+            }
+            _ => {
+                let b = load(self, es, di);
+                self.alu(Mnemonic::Cmp, acc, b, width);
+                (false, true)
+            }
+        };
+
+        if uses_si {
+            self.pointers[2] = si.wrapping_add(step);
+        }
+        if uses_di {
+            self.pointers[3] = di.wrapping_add(step);
+        }
+    }
+
+    fn execute(&mut self, ins: &Instruction) -> Result<(), String> {
+        use Mnemonic::*;
+
+        let width = ins.width;
+        let dst = ins.dst.unwrap_or(Operand::Imm(0));
+        let src = ins.src.unwrap_or(Operand::Imm(0));
+
+        match ins.mnemonic {
+            Add | Or | Adc | Sbb | And | Sub | Xor | Cmp => {
+                let r = self.alu(ins.mnemonic, self.read_operand(ins, dst), self.read_operand(ins, src), width);
+
+                if ins.mnemonic != Cmp {
+                    self.write_operand(ins, dst, r)?;
+                }
+            }
+            Test => {
+                self.alu(And, self.read_operand(ins, dst), self.read_operand(ins, src), width);
+            }
+            Rol | Ror | Rcl | Rcr | Shl | Shr | Sar => {
+                let mut count = self.read_operand(ins, src) as u8;
+
+                // from the 80186 on only the low five bits count
+                if self.model.has_80186_instructions() {
+                    count &= 0x1F;
+                }
+
+                let r = self.shift(ins.mnemonic, self.read_operand(ins, dst), count, width);
+                self.write_operand(ins, dst, r)?;
+            }
+            Not => self.write_operand(ins, dst, !self.read_operand(ins, dst))?,
+            Neg => {
+                let r = self.alu(Sub, 0, self.read_operand(ins, dst), width);
+                self.write_operand(ins, dst, r)?;
+            }
+            Inc | Dec => {
+                let cf = self.carry();
+                let op = if ins.mnemonic == Inc { Add } else { Sub };
+                let r = self.alu(op, self.read_operand(ins, dst), 1, width);
+
+                self.set_carry(cf);
+                self.write_operand(ins, dst, r)?;
+            }
+            Imul if ins.imm.is_some() => {
+                let r = self.read_operand(ins, src) as i16 as i32 * ins.imm.unwrap() as i16 as i32;
+
+                self.write_operand(ins, dst, r as u16)?;
+                self.set_carry(r != r as i16 as i32);
+                self.set_flag(OF, r != r as i16 as i32);
+            }
+            Mul | Imul => self.multiply(ins.mnemonic, self.read_operand(ins, dst), width),
+            Div | Idiv => {
+                if !self.divide(ins.mnemonic, self.read_operand(ins, dst), width) {
+                    return self.raise(0);
+                }
+            }
+            Mov => self.write_operand(ins, dst, self.read_operand(ins, src))?,
+            Xchg => {
+                let (a, b) = (self.read_operand(ins, dst), self.read_operand(ins, src));
+                self.write_operand(ins, dst, b)?;
+                self.write_operand(ins, src, a)?;
+            }
+            Lea => match src {
+                Operand::Mem(a) => self.write_operand(ins, dst, self.effective_address(&a))?,
+                _ => return Err("LEA needs a memory operand".to_string()),
+            },
+            Les | Lds => {
+                let (seg, off) = self.read_far(ins, src)?;
+                self.write_operand(ins, dst, off)?;
+                self.segments[if ins.mnemonic == Les { ES } else { DS } as usize] = seg;
+            }
+            Push => {
+                // up to the 80186 SP is pushed as it is after the decrement
+                let v = match dst {
+                    Operand::Reg16(4) if self.model != Model::I80286 => self.pointers[0].wrapping_sub(2),
+                    _ => self.read_operand(ins, dst),
+                };
+                self.push(v);
+            }
+            Pusha => {
+                let sp = self.pointers[0];
+
+                for r in 0..8 {
+                    self.push(if r == 4 { sp } else { self.reg16(r) });
+                }
+            }
+            Popa => {
+                for r in (0..8).rev() {
+                    let v = self.pop();
+
+                    if r != 4 {
+                        self.set_reg16(r, v);
+                    }
+                }
+            }
+            Enter => {
+                let size = self.read_operand(ins, dst);
+                let level = self.read_operand(ins, src) & 0x1F;
+                let ss = self.segments[SS as usize];
+
+                self.push(self.pointers[1]);
+                let frame = self.pointers[0];
+
+                if level > 0 {
+                    for _ in 1..level {
+                        self.pointers[1] = self.pointers[1].wrapping_sub(2);
+                        self.push(self.read16(ss, self.pointers[1]));
+                    }
+                    self.push(frame);
+                }
+
+                self.pointers[1] = frame;
+                self.pointers[0] = self.pointers[0].wrapping_sub(size);
+            }
+            Leave => {
+                self.pointers[0] = self.pointers[1];
+                self.pointers[1] = self.pop();
+            }
+            Bound => {
+                let Operand::Mem(a) = src else {
+                    return Err("BOUND needs a memory operand".to_string());
+                };
+                let seg = self.segment_of(ins, &a);
+                let off = self.effective_address(&a);
+                let index = self.read_operand(ins, dst) as i16;
+                let (low, high) = (self.read16(seg, off) as i16, self.read16(seg, off.wrapping_add(2)) as i16);
+
+                if index < low || index > high {
+                    if !self.has_vector(5) {
+                        return Err(format!("BOUND range exceeded, {index} is outside {low}..={high}"));
+                    }
+
+                    // the return address is BOUND itself, so the handler can retry
+                    self.ip = self.ip.wrapping_sub(ins.len as u16);
+                    return self.raise(5);
+                }
+            }
+            Pop => {
+                let v = self.pop();
+                self.write_operand(ins, dst, v)?;
+            }
+            Pushf => self.push(self.model.fix_flags(self.flags)),
+            Popf => self.flags = self.model.fix_flags(self.pop()),
+            Lahf => self.set_reg8(4, self.model.fix_flags(self.flags) as u8),
+            Sahf => self.flags = self.model.fix_flags((self.flags & 0xFF00) | (self.reg8(4) & 0xD5) as u16),
+            Cbw => self.set_reg16(0, self.reg8(0) as i8 as u16),
+            Salc => self.set_reg8(0, if self.carry() { 0xFF } else { 0 }),
+            Cwd => self.set_reg16(2, if self.reg16(0) & 0x8000 != 0 { 0xFFFF } else { 0 }),
+            Xlat => {
+                let seg = self.segments[ins.segment.unwrap_or(DS) as usize];
+                let off = self.reg16(3).wrapping_add(self.reg8(0) as u16);
+                self.set_reg8(0, self.read8(seg, off));
+            }
+            Daa | Das => {
+                let (al, cf) = (self.reg8(0), self.carry());
+                let mut v = al;
+
+                if al & 0xF > 9 || self.flag(AF) {
+                    v = if ins.mnemonic == Daa { v.wrapping_add(6) } else { v.wrapping_sub(6) };
+                    self.set_flag(AF, true);
+                }
+                if al > 0x99 || cf {
+                    v = if ins.mnemonic == Daa { v.wrapping_add(0x60) } else { v.wrapping_sub(0x60) };
+                    self.set_carry(true);
+                }
+
+                self.set_reg8(0, v);
+                self.set_szp(v as u32, Width::Byte);
+            }
+            Aaa | Aas => {
+                let adjust = self.reg8(0) & 0xF > 9 || self.flag(AF);
+
+                if adjust && self.model.has_80186_instructions() {
+                    // later chips adjust AX as a whole, so AL can carry into AH
+                    let ax = self.reg16(0);
+                    let ax = if ins.mnemonic == Aaa { ax.wrapping_add(0x106) } else { ax.wrapping_sub(0x106) };
+                    self.set_reg16(0, ax);
+                } else if adjust {
+                    let (al, ah) = (self.reg8(0), self.reg8(4));
+                    let (al, ah) = if ins.mnemonic == Aaa {
+                        (al.wrapping_add(6), ah.wrapping_add(1))
+                    } else {
+                        (al.wrapping_sub(6), ah.wrapping_sub(1))
+                    };
+                    self.set_reg8(0, al);
+                    self.set_reg8(4, ah);
+                }
+
+                self.set_reg8(0, self.reg8(0) & 0xF);
+                self.set_flag(AF, adjust);
+                self.set_carry(adjust);
+            }
+            Aam => {
+                let base = self.read_operand(ins, dst) as u8;
+
+                if base == 0 {
+                    return self.raise(0);
+                }
+
+                let al = self.reg8(0);
+                self.set_reg8(4, al / base);
+                self.set_reg8(0, al % base);
+
+                // the 8086 clears the undefined OF, AF and CF like a logical op
+                if self.quirks {
+                    self.alu(Or, (al % base) as u16, 0, Width::Byte);
+                } else {
+                    self.set_szp(self.reg8(0) as u32, Width::Byte);
+                }
+            }
+            Aad => {
+                let base = self.read_operand(ins, dst) as u8;
+                let product = self.reg8(4).wrapping_mul(base);
+                let al = self.reg8(0).wrapping_add(product);
+
+                // and leaves the flags of the final addition after AAD
+                if self.quirks {
+                    self.alu(Add, self.reg8(0) as u16, product as u16, Width::Byte);
+                } else {
+                    self.set_szp(al as u32, Width::Byte);
+                }
+                self.set_reg16(0, al as u16);
+            }
+            Movs | Cmps | Stos | Lods | Scas | Ins | Outs => {
+                let compares = matches!(ins.mnemonic, Cmps | Scas);
+
+                match ins.rep {
+                    None => self.string_op(ins),
+                    Some(rep) => {
+                        while self.reg16(1) != 0 {
+                            self.string_op(ins);
+                            self.set_reg16(1, self.reg16(1).wrapping_sub(1));
+
+                            if compares && self.flag(ZF) != (rep == Rep::Rep) {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            Jcc(cc) => {
+                if self.condition(cc) {
+                    self.ip = self.read_operand(ins, dst);
+                }
+            }
+            Loop | Loopz | Loopnz => {
+                let cx = self.reg16(1).wrapping_sub(1);
+                self.set_reg16(1, cx);
+
+                let taken = cx != 0
+                    && match ins.mnemonic {
+                        Loopz => self.flag(ZF),
+                        Loopnz => !self.flag(ZF),
+                        _ => true,
+                    };
+
+                if taken {
+                    self.ip = self.read_operand(ins, dst);
+                }
+            }
+            Jcxz => {
+                if self.reg16(1) == 0 {
+                    self.ip = self.read_operand(ins, dst);
+                }
+            }
+            Jmp => self.ip = self.read_operand(ins, dst),
+            Call => {
+                let target = self.read_operand(ins, dst);
+                self.push(self.ip);
+                self.ip = target;
+            }
+            JmpFar => {
+                let (seg, off) = self.read_far(ins, dst)?;
+                self.segments[CS as usize] = seg;
+                self.ip = off;
+            }
+            CallFar => {
+                let (seg, off) = self.read_far(ins, dst)?;
+                self.push(self.segments[CS as usize]);
+                self.push(self.ip);
+                self.segments[CS as usize] = seg;
+                self.ip = off;
+            }
+            Ret | Retf => {
+                self.ip = self.pop();
+
+                if ins.mnemonic == Retf {
+                    self.segments[CS as usize] = self.pop();
+                }
+                if let Some(Operand::Imm(n)) = ins.dst {
+                    self.pointers[0] = self.pointers[0].wrapping_add(n);
+                }
+            }
+            Iret => {
+                self.ip = self.pop();
+                self.segments[CS as usize] = self.pop();
+                self.flags = self.model.fix_flags(self.pop());
+            }
+            Int => return self.raise(self.read_operand(ins, dst) as u8),
+            Int3 => return self.raise(3),
+            Into => {
+                if self.flag(OF) {
+                    return self.raise(4);
+                }
+            }
+            In => {
+                let port = self.read_operand(ins, src);
+
+                match width {
+                    Width::Byte => {
+                        let v = self.port_in(port);
+                        self.set_reg8(0, v);
+                    }
+                    Width::Word => {
+                        let lo = self.port_in(port);
+                        let hi = self.port_in(port.wrapping_add(1));
+                        self.set_reg16(0, u16::from_le_bytes([lo, hi]));
+                    }
+                }
+            }
+            Out => {
+                let port = self.read_operand(ins, dst);
+                let [lo, hi] = self.reg16(0).to_le_bytes();
+
+                self.port_out(port, lo);
+                if width == Width::Word {
+                    self.port_out(port.wrapping_add(1), hi);
+                }
+            }
+            Hlt => {
+                self.state = if self.flag(IF) { State::WaitingForInterrupt } else { State::Halted };
+            }
+            Cmc => self.set_carry(!self.carry()),
+            Clc => self.set_carry(false),
+            Stc => self.set_carry(true),
+            Cli => self.set_flag(IF, false),
+            Sti => self.set_flag(IF, true),
+            Cld => self.set_flag(DF, false),
+            Std => self.set_flag(DF, true),
+            Esc(code) => self.esc(ins, code)?,
+            // exceptions never interrupt, so there is nothing to wait for
+            Wait | Nop => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cpu::{Cpu, State, CF, OF, ZF},
+        model::Model,
+    };
+
+    fn load(model: Model, code: &[u8]) -> Cpu {
+        let mut cpu = Cpu {
+            model,
+            segments: [0x1000; 4],
+            pointers: [0xFFFE, 0, 0, 0],
+            ..Cpu::default()
+        };
+        cpu.load_code(0x1000, 0x100, code);
+        cpu
+    }
+
+    fn run(code: &[u8]) -> Cpu {
+        let mut cpu = load(Model::I8086, code);
+        cpu.run_code(10_000).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn arithmetic_and_flags() {
+        let cpu = run(&[
+            0xB8, 0xFF, 0xFF, // MOV AX, FFFFh
+            0x05, 0x01, 0x00, // ADD AX, 1
+            0xB3, 0x7F, // MOV BL, 7Fh
+            0x80, 0xC3, 0x01, // ADD BL, 1
+            0xF4, // HLT
+        ]);
+
+        assert_eq!(cpu.get_word("AX"), 0);
+        assert_eq!(cpu.get_reg("BL").extract(), 0x80);
+        assert!(cpu.flag(OF) && !cpu.flag(CF) && !cpu.flag(ZF));
+
+        // MOV AX, 00FFh; AAA; HLT - the 8086 adjusts AL and AH apart, the
+        // 80186 and 80286 carry AL + 6 into AH
+        let code = [0xB8, 0xFF, 0x00, 0x37, 0xF4];
+        assert_eq!(run(&code).get_word("AX"), 0x0105);
+        let mut cpu = load(Model::I80286, &code);
+        cpu.run_code(100).unwrap();
+        assert_eq!(cpu.get_word("AX"), 0x0205);
+
+        // MOV AX, 0210h; SUB AL, 0Fh; AAS; HLT
+        let code = [0xB8, 0x10, 0x02, 0x2C, 0x0F, 0x3F, 0xF4];
+        assert_eq!(run(&code).get_word("AX"), 0x010B);
+        let mut cpu = load(Model::I80186, &code);
+        cpu.run_code(100).unwrap();
+        assert_eq!(cpu.get_word("AX"), 0x000B);
+    }
+
+    #[test]
+    fn loops_calls_and_stack() {
+        let cpu = run(&[
+            0xB9, 0x05, 0x00, // MOV CX, 5
+            0x31, 0xC0, // XOR AX, AX
+            0xE8, 0x04, 0x00, // CALL add3
+            0xE2, 0xFB, // LOOP -5
+            0xF4, // HLT
+            0x90, // NOP
+            0x83, 0xC0, 0x03, // add3: ADD AX, 3
+            0xC3, // RET
+        ]);
+
+        assert_eq!(cpu.get_word("AX"), 15);
+        assert_eq!(cpu.get_word("SP"), 0xFFFE);
+    }
+
+    #[test]
+    fn memory_and_strings() {
+        let mut cpu = Cpu {
+            segments: [0x2000, 0x1000, 0x1000, 0x1000],
+            pointers: [0xFFFE, 0, 0, 0],
+            ..Cpu::default()
+        };
+        cpu.memory.load(0x10200, b"hello");
+        cpu.load_code(
+            0x1000,
+            0x100,
+            &[
+                0xBE, 0x00, 0x02, // MOV SI, 200h
+                0x31, 0xFF, // XOR DI, DI
+                0xB9, 0x05, 0x00, // MOV CX, 5
+                0xFC, // CLD
+                0xF3, 0xA4, // REP MOVSB
+                0x26, 0x8A, 0x45, 0xFF, // MOV AL, ES:[DI-1]
+                0xF4, // HLT
+            ],
+        );
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.memory.read(0x20000, 5), b"hello");
+        assert_eq!(cpu.get_reg("AL").extract(), b'o');
+        assert_eq!(cpu.get_word("CX"), 0);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let cpu = run(&[
+            0xC6, 0x06, 0x08, 0x01, 0x42, // MOV BYTE [108h], 42h
+            0xB0, 0x00, // MOV AL, 0
+            0xB0, 0x00, // MOV AL, 0 (immediate patched above)
+            0xF4, // HLT
+        ]);
+
+        assert_eq!(cpu.get_reg("AL").extract(), 0x42);
+    }
+
+    #[test]
+    fn multiply_divide_and_interrupts() {
+        let mut cpu = run(&[
+            0xB8, 0x10, 0x00, // MOV AX, 10h
+            0xBB, 0x03, 0x00, // MOV BX, 3
+            0xF7, 0xE3, // MUL BX
+            0xF6, 0xF3, // DIV BL
+            0xF4, // HLT
+        ]);
+
+        assert_eq!(cpu.get_word("AX"), 0x0010);
+
+        // INT through a handler the program installed
+        cpu.memory.write_word(0x80 * 4, 0x0200);
+        cpu.memory.write_word(0x80 * 4 + 2, 0x1000);
+        cpu.memory.load(0x10200, &[0xB4, 0x07, 0xCF]); // MOV AH, 7; IRET
+        cpu.load_code(0x1000, 0x100, &[0xCD, 0x80, 0xF4]);
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.get_reg("AH").extract(), 7);
+        assert_eq!(cpu.ip, 0x103);
+
+        cpu.load_code(0x1000, 0x100, &[0xB3, 0x00, 0xF6, 0xF3]);
+        assert_eq!(cpu.run_code(100), Err("divide error".to_string()));
+    }
+
+    #[test]
+    fn instructions_of_the_80186() {
+        let code = [
+            0xB8, 0x07, 0x00, // MOV AX, 7
+            0x6B, 0xD8, 0xFA, // IMUL BX, AX, -6
+            0xC1, 0xE0, 0x03, // SHL AX, 3
+            0x60, // PUSHA
+            0x31, 0xC0, // XOR AX, AX
+            0x61, // POPA
+            0xC8, 0x04, 0x00, 0x00, // ENTER 4, 0
+            0x6A, 0xFF, // PUSH -1
+            0xC9, // LEAVE
+            0xF4, // HLT
+        ];
+
+        let mut cpu = load(Model::I80186, &code);
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.get_word("AX"), 56);
+        assert_eq!(cpu.get_word("BX"), (-42i16) as u16);
+        assert_eq!(cpu.get_word("SP"), 0xFFFE);
+
+        let mut old = load(Model::I8086, &code);
+        assert!(old.run_code(100).unwrap_err().contains("invalid opcode 6Bh on the 8086"));
+    }
+
+    #[test]
+    fn fixed_flags_tell_the_80286_apart() {
+        // PUSHF; POP AX; HLT
+        for (model, high) in [
+            (Model::I8088, 0xF000),
+            (Model::I8086, 0xF000),
+            (Model::I80186, 0xF000),
+            (Model::I80286, 0),
+        ] {
+            let mut cpu = load(model, &[0x9C, 0x58, 0xF4]);
+            cpu.run_code(100).unwrap();
+
+            assert_eq!(cpu.get_word("AX") & 0xF002, high | 2, "{}", model.name());
+        }
+    }
+
+    #[test]
+    fn quirks_of_the_8086() {
+        let code = [
+            0xF9, // STC
+            0xD6, // SALC
+            0x62, 0x02, // JC +2, aliased from 72h
+            0xF4, 0xF4, // skipped
+            0xB3, 0xC0, // MOV BL, 0C0h
+            0xB1, 0x02, // MOV CL, 2
+            0xD2, 0xE3, // SHL BL, CL
+            0xF4, // HLT
+        ];
+
+        let mut cpu = Cpu { quirks: true, ..load(Model::I8086, &code) };
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.reg8(0), 0xFF);
+        assert_eq!((cpu.reg8(3), cpu.ip), (0, 0x10D));
+        assert!(cpu.flag(OF));
+
+        let mut strict = load(Model::I8086, &code);
+        assert!(strict.run_code(100).unwrap_err().contains("invalid opcode D6h"));
+
+        // the 80186 leaves OF alone on multi-bit shifts, quirks or not
+        let mut later = Cpu { quirks: true, ..load(Model::I80186, &code[6..]) };
+        later.run_code(100).unwrap();
+        assert_eq!(later.reg8(3), 0);
+        assert!(!later.flag(OF));
+
+        // MOV AX, 2000h; PUSH AX; POP CS
+        let mut cpu = Cpu { quirks: true, ..load(Model::I8088, &[0xB8, 0x00, 0x20, 0x50, 0x0F]) };
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!((cpu.get_word("CS"), cpu.ip), (0x2000, 0x105));
+    }
+
+    #[test]
+    fn hlt_waits_for_an_interrupt() {
+        let code = [0xFB, 0xF4, 0xFA, 0xF4]; // STI, HLT, CLI, HLT
+
+        let mut cpu = load(Model::I8086, &code);
+        cpu.memory.write_word(8 * 4, 0x500);
+        cpu.memory.write_byte(0x500, 0xCF); // IRET
+        cpu.run_code(100).unwrap();
+        assert_eq!(cpu.state, State::WaitingForInterrupt);
+
+        assert!(cpu.interrupt_request(8).unwrap());
+        cpu.run_code(100).unwrap();
+        assert_eq!((cpu.state.clone(), cpu.ip), (State::Halted, 0x104));
+        assert!(!cpu.interrupt_request(8).unwrap());
+
+        let mut bad = load(Model::I8086, &[0x0F]);
+        assert!(bad.run_code(100).is_err());
+        assert!(matches!(bad.state, State::Faulted(_)));
+    }
+
+    #[test]
+    fn single_step_breakpoint_and_nmi() {
+        let code = [
+            0x9C, // PUSHF
+            0x58, // POP AX
+            0x0D, 0x00, 0x01, // OR AX, 100h
+            0x50, // PUSH AX
+            0x9D, // POPF
+            0x90, 0x90, 0x90, // NOP
+            0xCC, // INT 3
+            0xF4, // HLT
+        ];
+
+        let mut cpu = load(Model::I8086, &code);
+        // INT 1 counts steps in DI, INT 3 sets BX and NMI sets CX
+        cpu.memory.load(0x500, &[0x47, 0xCF, 0xBB, 0x34, 0x12, 0xCF, 0xB9, 0x05, 0x00, 0xCF]);
+        for (n, at) in [(1, 0x500), (3, 0x502), (2, 0x506)] {
+            cpu.memory.write_word(n * 4, at);
+        }
+        cpu.run_code(100).unwrap();
+
+        // a step after each NOP; POPF starts without TF and INT 3 clears it
+        assert_eq!(cpu.reg16(7), 3);
+        assert_eq!(cpu.reg16(3), 0x1234);
+        assert_eq!(cpu.state, State::Halted);
+
+        cpu.nmi().unwrap();
+        assert_eq!(cpu.state, State::Running);
+        cpu.step().unwrap();
+        assert_eq!(cpu.reg16(1), 5);
+    }
+}
@@ -0,0 +1,578 @@
+use std::cmp::Ordering;
+use std::ops::Neg;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+const BIAS: i32 = 16383;
+const MAX_EXPONENT: u16 = 0x7FFF;
+const INTEGER_BIT: u64 = 1 << 63;
+/// Set in the significand of a quiet NaN, clear in a signaling one.
+const QUIET: u64 = 1 << 62;
+
+/// Exceptions, in their bit positions in the 8087 status and control words.
+pub const INVALID: u8 = 0x01;
+pub const DENORMAL: u8 = 0x02;
+pub const ZERO_DIVIDE: u8 = 0x04;
+pub const OVERFLOW: u8 = 0x08;
+pub const UNDERFLOW: u8 = 0x10;
+pub const PRECISION: u8 = 0x20;
+
+/// Rounding control, bits 10 and 11 of the control word.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    #[default]
+    Nearest,
+    Down,
+    Up,
+    Chop,
+}
+
+impl Rounding {
+    pub fn from_control(control: u16) -> Self {
+        match (control >> 10) & 3 {
+            0 => Rounding::Nearest,
+            1 => Rounding::Down,
+            2 => Rounding::Up,
+            _ => Rounding::Chop,
+        }
+    }
+}
+
+/// How an operation rounds, and the exceptions it raised.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Env {
+    pub rounding: Rounding,
+    pub exceptions: u8,
+}
+
+/// A binary format values are rounded to.
+struct Format {
+    /// Significand bits, counting the leading one.
+    bits: u32,
+    bias: i32,
+    max_exponent: i32,
+}
+
+const EXTENDED: Format = Format { bits: 64, bias: BIAS, max_exponent: MAX_EXPONENT as i32 };
+const DOUBLE: Format = Format { bits: 53, bias: 1023, max_exponent: 0x7FF };
+const SINGLE: Format = Format { bits: 24, bias: 127, max_exponent: 0xFF };
+
+/// Rounds `m * 2^e` to `format`, where `sticky` stands for non-zero bits
+/// below `m`. Returns the biased exponent and the significand with its
+/// leading one at bit `format.bits - 1`; a result too large becomes
+/// infinity and one too small for a normal number becomes zero.
+fn round(sign: bool, m: u128, e: i32, sticky: bool, format: &Format, env: &mut Env) -> (i32, u64) {
+    if m == 0 {
+        return (0, 0);
+    }
+
+    let len = 128 - m.leading_zeros() as i32;
+    let shift = len - format.bits as i32;
+
+    let (mut m, mut e, guard, sticky) = if shift > 0 {
+        let dropped = m & ((1 << shift) - 1);
+        let guard = (dropped >> (shift - 1)) & 1 == 1;
+        (m >> shift, e + shift, guard, sticky || dropped & ((1 << (shift - 1)) - 1) != 0)
+    } else {
+        (m << -shift, e + shift, false, sticky)
+    };
+
+    let inexact = guard || sticky;
+    let up = match env.rounding {
+        Rounding::Nearest => guard && (sticky || m & 1 == 1),
+        Rounding::Down => inexact && sign,
+        Rounding::Up => inexact && !sign,
+        Rounding::Chop => false,
+    };
+
+    if up {
+        m += 1;
+        if m >> format.bits != 0 {
+            m >>= 1;
+            e += 1;
+        }
+    }
+    if inexact {
+        env.exceptions |= PRECISION;
+    }
+
+    let exponent = e + format.bits as i32 - 1 + format.bias;
+
+    if exponent >= format.max_exponent {
+        env.exceptions |= OVERFLOW | PRECISION;
+        (format.max_exponent, 1 << (format.bits - 1))
+    } else if exponent <= 0 {
+        env.exceptions |= UNDERFLOW | PRECISION;
+        (0, 0)
+    } else {
+        (exponent, m as u64)
+    }
+}
+
+/// Shifts `m` left until its leading one is at bit 63.
+fn normalize(m: u128, e: i32) -> (u128, i32) {
+    let shift = m.leading_zeros() as i32 - 64;
+    (m << shift, e - shift)
+}
+
+/// The 8087's temporary real: a sign, a 15-bit biased exponent and a 64-bit
+/// significand whose leading one is stored. Every register holds one and
+/// all arithmetic is done in it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct F80 {
+    pub sign: bool,
+    pub exponent: u16,
+    pub mantissa: u64,
+}
+
+impl F80 {
+    pub const ZERO: F80 = F80 { sign: false, exponent: 0, mantissa: 0 };
+    pub const ONE: F80 = F80 { sign: false, exponent: BIAS as u16, mantissa: INTEGER_BIT };
+    /// The quiet NaN masked invalid operations return.
+    pub const INDEFINITE: F80 = F80 { sign: true, exponent: MAX_EXPONENT, mantissa: 0xC000_0000_0000_0000 };
+    pub const PI: F80 = F80 { sign: false, exponent: 0x4000, mantissa: 0xC90F_DAA2_2168_C235 };
+    pub const LOG2_10: F80 = F80 { sign: false, exponent: 0x4000, mantissa: 0xD49A_784B_CD1B_8AFE };
+    pub const LOG2_E: F80 = F80 { sign: false, exponent: 0x3FFF, mantissa: 0xB8AA_3B29_5C17_F0BC };
+    pub const LOG10_2: F80 = F80 { sign: false, exponent: 0x3FFD, mantissa: 0x9A20_9A84_FBCF_F799 };
+    pub const LN_2: F80 = F80 { sign: false, exponent: 0x3FFE, mantissa: 0xB172_17F7_D1CF_79AC };
+
+    pub fn zero(sign: bool) -> Self {
+        F80 { sign, ..F80::ZERO }
+    }
+
+    pub fn infinity(sign: bool) -> Self {
+        F80 { sign, exponent: MAX_EXPONENT, mantissa: INTEGER_BIT }
+    }
+
+    /// `m * 2^e` rounded to 64 bits.
+    fn from_parts(sign: bool, m: u128, e: i32, sticky: bool, env: &mut Env) -> Self {
+        let (exponent, mantissa) = round(sign, m, e, sticky, &EXTENDED, env);
+        F80 { sign, exponent: exponent as u16, mantissa }
+    }
+
+    /// The significand and power of two the value is the product of.
+    fn unpack(self) -> (u128, i32) {
+        (self.mantissa as u128, (self.exponent as i32).max(1) - BIAS - 63)
+    }
+
+    pub fn is_nan(self) -> bool {
+        self.exponent == MAX_EXPONENT && self.mantissa << 1 != 0
+    }
+
+    pub fn is_infinite(self) -> bool {
+        self.exponent == MAX_EXPONENT && self.mantissa << 1 == 0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.exponent != MAX_EXPONENT && self.mantissa == 0
+    }
+
+    /// True for a non-zero value with the smallest exponent.
+    pub fn is_denormal(self) -> bool {
+        self.exponent == 0 && self.mantissa != 0
+    }
+
+    pub fn abs(self) -> Self {
+        F80 { sign: false, ..self }
+    }
+
+    pub fn from_bytes(b: [u8; 10]) -> Self {
+        let se = u16::from_le_bytes([b[8], b[9]]);
+        let mut mantissa = [0; 8];
+        mantissa.copy_from_slice(&b[..8]);
+
+        F80 { sign: se & 0x8000 != 0, exponent: se & MAX_EXPONENT, mantissa: u64::from_le_bytes(mantissa) }
+    }
+
+    pub fn to_bytes(self) -> [u8; 10] {
+        let se = (self.sign as u16) << 15 | self.exponent;
+        let mut b = [0; 10];
+        b[..8].copy_from_slice(&self.mantissa.to_le_bytes());
+        b[8..].copy_from_slice(&se.to_le_bytes());
+        b
+    }
+
+    /// Converts exactly, every double being a temporary real.
+    pub fn from_f64(x: f64) -> Self {
+        let bits = x.to_bits();
+        let sign = bits >> 63 == 1;
+        let exponent = (bits >> 52 & 0x7FF) as i32;
+        let fraction = bits & ((1 << 52) - 1);
+
+        match exponent {
+            0x7FF => F80 { sign, exponent: MAX_EXPONENT, mantissa: INTEGER_BIT | fraction << 11 },
+            0 if fraction == 0 => F80::zero(sign),
+            0 => F80::from_parts(sign, fraction as u128, -1074, false, &mut Env::default()),
+            _ => F80 {
+                sign,
+                exponent: (exponent - 1023 + BIAS) as u16,
+                mantissa: INTEGER_BIT | fraction << 11,
+            },
+        }
+    }
+
+    pub fn from_f32(x: f32) -> Self {
+        F80::from_f64(x as f64)
+    }
+
+    pub fn from_i64(v: i64) -> Self {
+        if v == 0 {
+            return F80::ZERO;
+        }
+        F80::from_parts(v < 0, v.unsigned_abs() as u128, 0, false, &mut Env::default())
+    }
+
+    /// The bits of the value in a narrower IEEE format.
+    fn to_bits(self, format: &Format, env: &mut Env) -> u64 {
+        let fraction_bits = format.bits - 1;
+        let sign_shift = fraction_bits + (format.max_exponent as u32).count_ones();
+
+        let (exponent, fraction) = if self.is_nan() {
+            let payload = self.mantissa << 1 >> (64 - fraction_bits);
+            (format.max_exponent, payload | 1 << (fraction_bits - 1))
+        } else if self.is_infinite() {
+            (format.max_exponent, 0)
+        } else if self.is_zero() {
+            (0, 0)
+        } else {
+            let (m, e) = self.unpack();
+            let (exponent, m) = round(self.sign, m, e, false, format, env);
+            (exponent, m & ((1 << fraction_bits) - 1))
+        };
+
+        (self.sign as u64) << sign_shift | (exponent as u64) << fraction_bits | fraction
+    }
+
+    pub fn to_f64(self, env: &mut Env) -> f64 {
+        f64::from_bits(self.to_bits(&DOUBLE, env))
+    }
+
+    pub fn to_f32(self, env: &mut Env) -> f32 {
+        f32::from_bits(self.to_bits(&SINGLE, env) as u32)
+    }
+
+    /// The nearest double, for display.
+    pub fn value(self) -> f64 {
+        self.to_f64(&mut Env::default())
+    }
+
+    /// Rounds to an integer, or None for NaNs, infinities and values
+    /// outside the i64 range.
+    pub fn to_i64(self, env: &mut Env) -> Option<i64> {
+        if self.is_nan() || self.is_infinite() {
+            return None;
+        }
+
+        let (m, e) = self.unpack();
+        let (int, guard, sticky) = match e {
+            0.. if e > 64 => return None,
+            0.. => (m << e, false, false),
+            _ if e <= -128 => (0, false, m != 0),
+            _ => {
+                let s = -e;
+                (m >> s, (m >> (s - 1)) & 1 == 1, m & ((1 << (s - 1)) - 1) != 0)
+            }
+        };
+
+        let inexact = guard || sticky;
+        let up = match env.rounding {
+            Rounding::Nearest => guard && (sticky || int & 1 == 1),
+            Rounding::Down => inexact && self.sign,
+            Rounding::Up => inexact && !self.sign,
+            Rounding::Chop => false,
+        };
+        let int = int + up as u128;
+
+        if inexact {
+            env.exceptions |= PRECISION;
+        }
+
+        match self.sign {
+            true if int <= 1 << 63 => Some((int as i128).wrapping_neg() as i64),
+            false if int < 1 << 63 => Some(int as i64),
+            _ => None,
+        }
+    }
+
+    /// Rounds to an integer in the same format, as FRNDINT does.
+    pub fn round_to_integer(self, env: &mut Env) -> Self {
+        match self.to_i64(env) {
+            Some(0) => F80::zero(self.sign),
+            Some(v) => F80::from_i64(v),
+            // NaNs, infinities and anything of 2^63 or more
+            None => self,
+        }
+    }
+
+    /// The NaN an operation on `a` and `b` returns if either is one, made
+    /// quiet. Signaling NaNs are invalid operands.
+    fn nan(a: F80, b: F80, env: &mut Env) -> Option<F80> {
+        let nan = [a, b].into_iter().find(|x| x.is_nan())?;
+
+        if nan.mantissa & QUIET == 0 {
+            env.exceptions |= INVALID;
+        }
+        Some(F80 { mantissa: nan.mantissa | QUIET, ..nan })
+    }
+
+    fn invalid(env: &mut Env) -> F80 {
+        env.exceptions |= INVALID;
+        F80::INDEFINITE
+    }
+
+    fn check_denormal(self, env: &mut Env) {
+        if self.is_denormal() {
+            env.exceptions |= DENORMAL;
+        }
+    }
+
+    pub fn add(self, other: F80, env: &mut Env) -> F80 {
+        if let Some(nan) = F80::nan(self, other, env) {
+            return nan;
+        }
+
+        match (self.is_infinite(), other.is_infinite()) {
+            (true, true) if self.sign != other.sign => return F80::invalid(env),
+            (true, _) => return self,
+            (_, true) => return other,
+            _ => {}
+        }
+
+        self.check_denormal(env);
+        other.check_denormal(env);
+
+        if self.is_zero() && other.is_zero() {
+            let sign = if self.sign == other.sign { self.sign } else { env.rounding == Rounding::Down };
+            return F80::zero(sign);
+        }
+
+        // 62 spare bits below the significands keep guard and sticky bits
+        let (ma, ea) = self.unpack();
+        let (mb, eb) = other.unpack();
+        let a = (self.sign, ma << 62, ea - 62);
+        let b = (other.sign, mb << 62, eb - 62);
+        let ((sa, ma, e), (sb, mb, eb)) = if a.2 >= b.2 { (a, b) } else { (b, a) };
+
+        let d = (e - eb) as u32;
+        let mb = if d >= 128 {
+            (mb != 0) as u128
+        } else {
+            // bits shifted out stick to the lowest bit
+            let shifted = mb >> d;
+            shifted | (shifted << d != mb) as u128
+        };
+
+        let (sign, m) = if sa == sb {
+            (sa, ma + mb)
+        } else if ma >= mb {
+            (sa, ma - mb)
+        } else {
+            (sb, mb - ma)
+        };
+
+        if m == 0 {
+            return F80::zero(env.rounding == Rounding::Down);
+        }
+        F80::from_parts(sign, m, e, false, env)
+    }
+
+    pub fn sub(self, other: F80, env: &mut Env) -> F80 {
+        if let Some(nan) = F80::nan(self, other, env) {
+            return nan;
+        }
+        self.add(-other, env)
+    }
+
+    pub fn mul(self, other: F80, env: &mut Env) -> F80 {
+        if let Some(nan) = F80::nan(self, other, env) {
+            return nan;
+        }
+
+        let sign = self.sign != other.sign;
+
+        if (self.is_infinite() && other.is_zero()) || (self.is_zero() && other.is_infinite()) {
+            return F80::invalid(env);
+        }
+        if self.is_infinite() || other.is_infinite() {
+            return F80::infinity(sign);
+        }
+        if self.is_zero() || other.is_zero() {
+            return F80::zero(sign);
+        }
+
+        self.check_denormal(env);
+        other.check_denormal(env);
+
+        let (ma, ea) = self.unpack();
+        let (mb, eb) = other.unpack();
+        F80::from_parts(sign, ma * mb, ea + eb, false, env)
+    }
+
+    pub fn div(self, other: F80, env: &mut Env) -> F80 {
+        if let Some(nan) = F80::nan(self, other, env) {
+            return nan;
+        }
+
+        let sign = self.sign != other.sign;
+
+        if (self.is_infinite() && other.is_infinite()) || (self.is_zero() && other.is_zero()) {
+            return F80::invalid(env);
+        }
+        if self.is_infinite() {
+            return F80::infinity(sign);
+        }
+        if other.is_infinite() || self.is_zero() {
+            return F80::zero(sign);
+        }
+        if other.is_zero() {
+            env.exceptions |= ZERO_DIVIDE;
+            return F80::infinity(sign);
+        }
+
+        self.check_denormal(env);
+        other.check_denormal(env);
+
+        let (ma, ea) = normalize(self.mantissa as u128, self.unpack().1);
+        let (mb, eb) = normalize(other.mantissa as u128, other.unpack().1);
+
+        // at least 64 quotient bits, then two more for rounding
+        let mut q = (ma << 64) / mb;
+        let mut r = (ma << 64) % mb;
+        for _ in 0..2 {
+            r <<= 1;
+            q <<= 1;
+            if r >= mb {
+                r -= mb;
+                q |= 1;
+            }
+        }
+
+        F80::from_parts(sign, q, ea - eb - 66, r != 0, env)
+    }
+
+    pub fn sqrt(self, env: &mut Env) -> F80 {
+        if let Some(nan) = F80::nan(self, self, env) {
+            return nan;
+        }
+        if self.is_zero() {
+            return self;
+        }
+        if self.sign {
+            return F80::invalid(env);
+        }
+        if self.is_infinite() {
+            return self;
+        }
+
+        self.check_denormal(env);
+
+        let (m, e) = normalize(self.mantissa as u128, self.unpack().1);
+        let (m, e) = if e % 2 != 0 { (m << 1, e - 1) } else { (m, e) };
+
+        // the radicand is m shifted left to about 132 bits, by an even
+        // amount, so the root has 66
+        let len = 128 - m.leading_zeros();
+        let shift = (132 - len).next_multiple_of(2);
+        let bit = |p: u32| if p >= shift { (m >> (p - shift)) & 1 } else { 0 };
+
+        let (mut root, mut rem) = (0u128, 0u128);
+        for i in (0..(len + shift) / 2).rev() {
+            rem = rem << 2 | bit(2 * i + 1) << 1 | bit(2 * i);
+            let trial = root << 2 | 1;
+            root <<= 1;
+            if rem >= trial {
+                rem -= trial;
+                root |= 1;
+            }
+        }
+
+        F80::from_parts(false, root, (e - shift as i32) / 2, rem != 0, env)
+    }
+
+    /// Orders two values, None when either is a NaN. Zeros of either sign
+    /// are equal.
+    pub fn compare(self, other: F80) -> Option<Ordering> {
+        if self.is_nan() || other.is_nan() {
+            return None;
+        }
+        if self.is_zero() && other.is_zero() {
+            return Some(Ordering::Equal);
+        }
+        if self.sign != other.sign {
+            return Some(if self.sign { Ordering::Less } else { Ordering::Greater });
+        }
+
+        let magnitude = (self.exponent, self.mantissa).cmp(&(other.exponent, other.mantissa));
+        Some(if self.sign { magnitude.reverse() } else { magnitude })
+    }
+}
+
+impl Neg for F80 {
+    type Output = F80;
+
+    fn neg(self) -> F80 {
+        F80 { sign: !self.sign, ..self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{Env, Rounding, F80, INVALID, PRECISION, ZERO_DIVIDE};
+
+    fn f(x: f64) -> F80 {
+        F80::from_f64(x)
+    }
+
+    #[test]
+    fn arithmetic_matches_doubles_when_exact() {
+        let mut env = Env::default();
+
+        assert_eq!(f(1.5).add(f(2.25), &mut env).value(), 3.75);
+        assert_eq!(f(1.5).sub(f(2.25), &mut env).value(), -0.75);
+        assert_eq!(f(-3.0).mul(f(0.5), &mut env).value(), -1.5);
+        assert_eq!(f(10.0).div(f(4.0), &mut env).value(), 2.5);
+        assert_eq!(f(2.0).sqrt(&mut env).value(), 2f64.sqrt());
+        assert_eq!(f(1e300).mul(f(1e300), &mut env).value(), f64::INFINITY);
+        assert_eq!(env.exceptions & PRECISION, PRECISION);
+        assert_eq!(F80::from_i64(-7).to_bytes(), [0, 0, 0, 0, 0, 0, 0, 0xE0, 0x01, 0xC0]);
+        assert_eq!(F80::from_bytes(F80::PI.to_bytes()), F80::PI);
+    }
+
+    #[test]
+    fn keeps_64_bits() {
+        let mut env = Env::default();
+
+        // 1 + 2^-60 is lost in a double but not in a temporary real
+        let tiny = F80 { exponent: F80::ONE.exponent - 60, ..F80::ONE };
+        let sum = F80::ONE.add(tiny, &mut env);
+        assert_eq!(sum.mantissa, 1 << 63 | 1 << 3);
+        assert_eq!(sum.sub(F80::ONE, &mut env), tiny);
+
+        let third = F80::ONE.div(f(3.0), &mut env);
+        assert_eq!(third.mantissa, 0xAAAA_AAAA_AAAA_AAAB);
+
+        env.rounding = Rounding::Chop;
+        assert_eq!(F80::ONE.div(f(3.0), &mut env).mantissa, 0xAAAA_AAAA_AAAA_AAAA);
+        assert_eq!(f(2.0).sqrt(&mut env).mantissa, 0xB504_F333_F9DE_6484);
+    }
+
+    #[test]
+    fn special_values() {
+        let mut env = Env::default();
+
+        assert_eq!(f(1.0).div(f(0.0), &mut env), F80::infinity(false));
+        assert_eq!(env.exceptions, ZERO_DIVIDE);
+        assert_eq!(f(-1.0).sqrt(&mut env), F80::INDEFINITE);
+        assert_eq!(env.exceptions & INVALID, INVALID);
+        assert!(f(f64::NAN).add(f(1.0), &mut env).is_nan());
+        assert_eq!(f(1.0).compare(f(f64::NAN)), None);
+        assert_eq!(f(-0.0).compare(f(0.0)), Some(Ordering::Equal));
+        assert_eq!(f(-2.0).compare(f(-1.0)), Some(Ordering::Less));
+        assert_eq!(f(2.5).to_i64(&mut Env::default()), Some(2));
+        assert_eq!(f(-3.5).to_i64(&mut Env::default()), Some(-4));
+    }
+}
@@ -0,0 +1,515 @@
+use std::cmp::Ordering;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+use crate::{
+    cpu::Cpu,
+    decoder::{Instruction, Operand},
+    f80::{Env, Rounding, F80, INVALID},
+};
+
+/// Control word after FINIT: every exception masked, 64-bit precision,
+/// rounding to nearest.
+pub const CONTROL_DEFAULT: u16 = 0x03FF;
+
+/// Control word bit set by FDISI, which keeps the 8087 from interrupting.
+const INTERRUPT_MASK: u16 = 0x0080;
+
+/// Status word bits.
+const ERROR_SUMMARY: u16 = 0x0080;
+const C0: u16 = 0x0100;
+const C1: u16 = 0x0200;
+const C2: u16 = 0x0400;
+const C3: u16 = 0x4000;
+const BUSY: u16 = 0x8000;
+const TOP_SHIFT: u16 = 11;
+
+/// Register tags in the tag word.
+const TAG_VALID: u16 = 0;
+const TAG_ZERO: u16 = 1;
+const TAG_SPECIAL: u16 = 2;
+const TAG_EMPTY: u16 = 3;
+
+/// Memory operands of the ESC opcodes, D8h/D9h, DAh/DBh, DCh/DDh and
+/// DEh/DFh in pairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Single,
+    Int32,
+    Double,
+    Int16,
+    Int64,
+    Extended,
+}
+
+impl Format {
+    fn of(op: u8) -> Self {
+        match op >> 1 {
+            0 => Format::Single,
+            1 => Format::Int32,
+            2 => Format::Double,
+            _ => Format::Int16,
+        }
+    }
+
+    fn len(self) -> u16 {
+        match self {
+            Format::Int16 => 2,
+            Format::Single | Format::Int32 => 4,
+            Format::Double | Format::Int64 => 8,
+            Format::Extended => 10,
+        }
+    }
+}
+
+fn tag(v: F80) -> u16 {
+    if v.is_zero() {
+        TAG_ZERO
+    } else if v.is_nan() || v.is_infinite() || v.is_denormal() {
+        TAG_SPECIAL
+    } else {
+        TAG_VALID
+    }
+}
+
+/// `op` of the arithmetic group, ADD, MUL, SUB, SUBR, DIV or DIVR, on `a`
+/// and `b`.
+fn arith(op: u8, a: F80, b: F80, env: &mut Env) -> F80 {
+    match op {
+        0 => a.add(b, env),
+        1 => a.mul(b, env),
+        4 => a.sub(b, env),
+        5 => b.sub(a, env),
+        6 => a.div(b, env),
+        _ => b.div(a, env),
+    }
+}
+
+/// The 8087 numeric coprocessor. Its eight registers are a stack, ST(0)
+/// being the one TOP in the status word points at. Exceptions always get
+/// the masked response, unmasked ones only set the error summary bit, as
+/// nothing is wired to the 8087's interrupt line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Fpu {
+    /// Physical registers; ST(i) is register TOP + i, modulo 8.
+    pub registers: [F80; 8],
+    pub control: u16,
+    /// Exception flags, condition codes and TOP in bits 11 to 13.
+    pub status: u16,
+    /// Two bits a physical register: valid, zero, special or empty.
+    pub tag: u16,
+}
+
+impl Default for Fpu {
+    fn default() -> Self {
+        Fpu {
+            registers: [F80::ZERO; 8],
+            control: CONTROL_DEFAULT,
+            status: 0,
+            tag: 0xFFFF,
+        }
+    }
+}
+
+impl Fpu {
+    pub fn top(&self) -> usize {
+        (self.status >> TOP_SHIFT) as usize & 7
+    }
+
+    fn set_top(&mut self, top: usize) {
+        self.status = self.status & !(7 << TOP_SHIFT) | ((top & 7) as u16) << TOP_SHIFT;
+    }
+
+    fn physical(&self, i: usize) -> usize {
+        (self.top() + i) & 7
+    }
+
+    fn set_tag(&mut self, r: usize, t: u16) {
+        self.tag = self.tag & !(3 << (2 * r)) | t << (2 * r);
+    }
+
+    pub fn is_empty(&self, i: usize) -> bool {
+        (self.tag >> (2 * self.physical(i))) & 3 == TAG_EMPTY
+    }
+
+    /// ST(i) as it is, empty or not.
+    pub fn st(&self, i: usize) -> F80 {
+        self.registers[self.physical(i)]
+    }
+
+    fn set_st(&mut self, i: usize, v: F80) {
+        let r = self.physical(i);
+        self.registers[r] = v;
+        self.set_tag(r, tag(v));
+    }
+
+    fn env(&self) -> Env {
+        Env {
+            rounding: Rounding::from_control(self.control),
+            exceptions: 0,
+        }
+    }
+
+    /// Records the exceptions an operation raised.
+    fn raise(&mut self, exceptions: u8) {
+        self.status |= exceptions as u16;
+
+        if exceptions as u16 & !self.control & 0x3F != 0 {
+            self.status |= ERROR_SUMMARY | BUSY;
+        }
+    }
+
+    /// ST(i), or the indefinite NaN after a stack underflow if it is empty.
+    fn read(&mut self, i: usize) -> F80 {
+        if self.is_empty(i) {
+            self.raise(INVALID);
+            F80::INDEFINITE
+        } else {
+            self.st(i)
+        }
+    }
+
+    /// Pushes `v`, or the indefinite NaN if the stack is full.
+    pub fn push(&mut self, v: F80) {
+        self.set_top(self.top().wrapping_sub(1));
+
+        if self.is_empty(0) {
+            self.set_st(0, v);
+        } else {
+            self.raise(INVALID);
+            self.set_st(0, F80::INDEFINITE);
+        }
+    }
+
+    pub fn pop(&mut self) {
+        self.set_tag(self.physical(0), TAG_EMPTY);
+        self.set_top(self.top() + 1);
+    }
+
+    /// Sets C3, C2 and C0 from comparing `a` with `b`.
+    fn compare(&mut self, a: F80, b: F80) {
+        let codes = match a.compare(b) {
+            Some(Ordering::Greater) => 0,
+            Some(Ordering::Less) => C0,
+            Some(Ordering::Equal) => C3,
+            None => {
+                self.raise(INVALID);
+                C3 | C2 | C0
+            }
+        };
+
+        self.status = self.status & !(C3 | C2 | C1 | C0) | codes;
+    }
+
+    /// Runs `f` on a fresh environment and records what it raised.
+    fn with_env<T>(&mut self, f: impl FnOnce(&mut Env) -> T) -> T {
+        let mut env = self.env();
+        let v = f(&mut env);
+        self.raise(env.exceptions);
+        v
+    }
+
+    /// Arithmetic on ST(0) and a memory operand, D8h, DAh, DCh and DEh.
+    fn arith_memory(&mut self, reg: u8, v: F80) {
+        let st0 = self.read(0);
+
+        match reg {
+            2 | 3 => {
+                self.compare(st0, v);
+                if reg == 3 {
+                    self.pop();
+                }
+            }
+            _ => {
+                let r = self.with_env(|env| arith(reg, st0, v, env));
+                self.set_st(0, r);
+            }
+        }
+    }
+
+    /// An ESC instruction whose ModR/M byte names register `i`, which
+    /// covers the arithmetic on ST(i) and everything without an operand.
+    pub fn execute_register(&mut self, op: u8, reg: u8, i: usize) -> Result<(), String> {
+        match (op, reg, i) {
+            (0, 2 | 3, _) => {
+                let (a, b) = (self.read(0), self.read(i));
+                self.compare(a, b);
+                if reg == 3 {
+                    self.pop();
+                }
+            }
+            (0, _, _) => {
+                let (a, b) = (self.read(0), self.read(i));
+                let r = self.with_env(|env| arith(reg, a, b, env));
+                self.set_st(0, r);
+            }
+            (6, 3, 1) => {
+                let (a, b) = (self.read(0), self.read(1));
+                self.compare(a, b);
+                self.pop();
+                self.pop();
+            }
+            // ST(i) is the destination, and SUB and DIV swap with their
+            // reversed forms
+            (4 | 6, 0 | 1 | 4..=7, _) => {
+                let (a, b) = (self.read(i), self.read(0));
+                let reg = if reg >= 4 { reg ^ 1 } else { reg };
+                let r = self.with_env(|env| arith(reg, a, b, env));
+                self.set_st(i, r);
+                if op == 6 {
+                    self.pop();
+                }
+            }
+            (1, 0, _) => {
+                let v = self.read(i);
+                self.push(v);
+            }
+            (1, 1, _) => {
+                let (a, b) = (self.read(0), self.read(i));
+                self.set_st(0, b);
+                self.set_st(i, a);
+            }
+            (1, 2, 0) => {}
+            (1, 4, 0) => {
+                let v = self.read(0);
+                self.set_st(0, -v);
+            }
+            (1, 4, 1) => {
+                let v = self.read(0);
+                self.set_st(0, v.abs());
+            }
+            (1, 4, 4) => {
+                let v = self.read(0);
+                self.compare(v, F80::ZERO);
+            }
+            (1, 5, 0..=6) => self.push(
+                [F80::ONE, F80::LOG2_10, F80::LOG2_E, F80::PI, F80::LOG10_2, F80::LN_2, F80::ZERO][i],
+            ),
+            (1, 6, 6) => self.set_top(self.top().wrapping_sub(1)),
+            (1, 6, 7) => self.set_top(self.top() + 1),
+            (1, 7, 2) => {
+                let v = self.read(0);
+                let r = self.with_env(|env| v.sqrt(env));
+                self.set_st(0, r);
+            }
+            (1, 7, 4) => {
+                let v = self.read(0);
+                let r = self.with_env(|env| v.round_to_integer(env));
+                self.set_st(0, r);
+            }
+            (3, 4, 0) => self.control &= !INTERRUPT_MASK,
+            (3, 4, 1) => self.control |= INTERRUPT_MASK,
+            (3, 4, 2) => self.status &= !(BUSY | ERROR_SUMMARY | 0x3F),
+            (3, 4, 3) => *self = Fpu::default(),
+            (5, 0, _) => self.set_tag(self.physical(i), TAG_EMPTY),
+            (5, 2 | 3, _) => {
+                let v = self.read(0);
+                self.set_st(i, v);
+                if reg == 3 {
+                    self.pop();
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "unsupported 8087 instruction {:02X} {:02X}",
+                    0xD8 + op,
+                    0xC0 | reg << 3 | i as u8
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The stack as the register panel shows it, then the control, status
+    /// and tag words.
+    pub fn registers_str(&self) -> String {
+        let stack = (0..8).fold(String::new(), |s, i| {
+            let sep = if i == 0 { "" } else { "\n" };
+            if self.is_empty(i) {
+                format!("{s}{sep}ST{i} empty")
+            } else {
+                format!("{s}{sep}ST{i} {}", self.st(i).value())
+            }
+        });
+
+        format!(
+            "{stack}\nControl {:04X}\nStatus {:04X}\nTag {:04X}",
+            self.control, self.status, self.tag
+        )
+    }
+}
+
+impl Cpu {
+    fn read_real(&self, seg: u16, off: u16, format: Format) -> F80 {
+        let bytes: Vec<u8> = (0..format.len()).map(|i| self.read8(seg, off.wrapping_add(i))).collect();
+        let mut b = [0; 10];
+        b[..bytes.len()].copy_from_slice(&bytes);
+
+        match format {
+            Format::Single => F80::from_f32(f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            Format::Int32 => F80::from_i64(i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as i64),
+            Format::Double => F80::from_f64(f64::from_le_bytes(b[..8].try_into().unwrap())),
+            Format::Int16 => F80::from_i64(i16::from_le_bytes([b[0], b[1]]) as i64),
+            Format::Int64 => F80::from_i64(i64::from_le_bytes(b[..8].try_into().unwrap())),
+            Format::Extended => F80::from_bytes(b),
+        }
+    }
+
+    /// Stores `v` rounded to `format`. Integers out of range are invalid
+    /// and stored as the integer indefinite, the most negative value.
+    fn write_real(&mut self, fpu: &mut Fpu, seg: u16, off: u16, format: Format, v: F80) {
+        let bytes = fpu.with_env(|env| {
+            let int = |bits: u32, env: &mut Env| match v.to_i64(env) {
+                Some(i) if (i as i128) >= -(1 << (bits - 1)) && (i as i128) < 1 << (bits - 1) => i,
+                _ => {
+                    env.exceptions |= INVALID;
+                    i64::MIN >> (64 - bits)
+                }
+            };
+
+            match format {
+                Format::Single => v.to_f32(env).to_le_bytes().to_vec(),
+                Format::Int32 => (int(32, env) as i32).to_le_bytes().to_vec(),
+                Format::Double => v.to_f64(env).to_le_bytes().to_vec(),
+                Format::Int16 => (int(16, env) as i16).to_le_bytes().to_vec(),
+                Format::Int64 => int(64, env).to_le_bytes().to_vec(),
+                Format::Extended => v.to_bytes().to_vec(),
+            }
+        });
+
+        for (i, b) in bytes.into_iter().enumerate() {
+            self.write8(seg, off.wrapping_add(i as u16), b);
+        }
+    }
+
+    /// An ESC instruction with a memory operand at `seg:off`.
+    fn esc_memory(&mut self, fpu: &mut Fpu, op: u8, reg: u8, seg: u16, off: u16) -> Result<(), String> {
+        match (op, reg) {
+            (0 | 2 | 4 | 6, _) => {
+                let v = self.read_real(seg, off, Format::of(op));
+                fpu.arith_memory(reg, v);
+            }
+            (1 | 3 | 5 | 7, 0) => fpu.push(self.read_real(seg, off, Format::of(op))),
+            (3, 5) => fpu.push(self.read_real(seg, off, Format::Extended)),
+            (7, 5) => fpu.push(self.read_real(seg, off, Format::Int64)),
+            (1 | 3 | 5 | 7, 2 | 3) | (3 | 7, 7) => {
+                let format = match (op, reg) {
+                    (3, 7) => Format::Extended,
+                    (7, 7) => Format::Int64,
+                    _ => Format::of(op),
+                };
+                let v = fpu.read(0);
+                self.write_real(fpu, seg, off, format, v);
+                if reg != 2 {
+                    fpu.pop();
+                }
+            }
+            (1, 5) => fpu.control = self.read16(seg, off),
+            (1, 7) => self.write16(seg, off, fpu.control),
+            (5, 7) => self.write16(seg, off, fpu.status),
+            _ => return Err(format!("unsupported 8087 instruction {:02X} /{reg}", 0xD8 + op)),
+        }
+
+        Ok(())
+    }
+
+    /// Hands an ESC instruction to the coprocessor; without one it does
+    /// nothing.
+    pub fn esc(&mut self, ins: &Instruction, code: u8) -> Result<(), String> {
+        let Some(mut fpu) = self.fpu else {
+            return Ok(());
+        };
+        let (op, reg) = (code >> 3, code & 7);
+
+        match ins.dst {
+            Some(Operand::Mem(a)) => {
+                let (seg, off) = (self.segment_of(ins, &a), self.effective_address(&a));
+                self.esc_memory(&mut fpu, op, reg, seg, off)?;
+            }
+            Some(Operand::Reg16(i)) => fpu.execute_register(op, reg, i as usize)?,
+            _ => return Err("ESC without a ModR/M operand".to_string()),
+        }
+
+        self.fpu = Some(fpu);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fpu;
+    use crate::{cpu::Cpu, f80::F80};
+
+    fn run(code: &[u8]) -> Cpu {
+        let mut cpu = Cpu {
+            fpu: Some(Fpu::default()),
+            segments: [0x1000; 4],
+            ..Cpu::default()
+        };
+        cpu.load_code(0x1000, 0x100, code);
+        cpu.run_code(code.len() as u64).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn computes_on_the_stack() {
+        let mut code = vec![
+            0xD9, 0x06, 0x00, 0x02, // FLD DWORD [200h]
+            0xDC, 0x06, 0x04, 0x02, // FADD QWORD [204h]
+            0xD9, 0xE8, // FLD1
+            0xDE, 0xC9, // FMULP ST(1), ST
+            0xD9, 0xFA, // FSQRT
+            0xDD, 0x1E, 0x10, 0x02, // FSTP QWORD [210h]
+            0xF4,
+        ];
+        code.resize(0x100, 0x90);
+        code.extend(2.5f32.to_le_bytes());
+        code.extend(6.5f64.to_le_bytes());
+
+        let cpu = run(&code);
+        let sqrt = f64::from_le_bytes(cpu.memory.read(0x10210, 8).try_into().unwrap());
+
+        assert_eq!(sqrt, 3.0);
+        assert!(cpu.fpu.unwrap().is_empty(0));
+    }
+
+    #[test]
+    fn compares_and_stores_the_status() {
+        let code = [
+            0xD9, 0xEB, // FLDPI
+            0xD9, 0xE8, // FLD1
+            0xDE, 0xD9, // FCOMPP
+            0xDD, 0x3E, 0x00, 0x03, // FSTSW [300h]
+            0xDB, 0xE3, // FINIT
+            0xD9, 0xC0, // FLD ST(0) from an empty stack
+            0xF4,
+        ];
+        let cpu = run(&code);
+
+        // 1 < pi sets C0, and the stack is back to TOP 0
+        assert_eq!(cpu.read16(0x1000, 0x300), 0x0100);
+
+        let fpu = cpu.fpu.unwrap();
+        assert_eq!(fpu.st(0), F80::INDEFINITE);
+        assert_eq!(fpu.status & 1, 1);
+    }
+
+    #[test]
+    fn stack_wraps_around() {
+        let mut fpu = Fpu::default();
+
+        for i in 0..8 {
+            fpu.push(F80::from_i64(i));
+        }
+        assert_eq!(fpu.top(), 0);
+        assert_eq!(fpu.status & 1, 0);
+
+        fpu.push(F80::ONE);
+        assert_eq!(fpu.st(0), F80::INDEFINITE);
+        assert_eq!(fpu.status & 1, 1);
+        assert_eq!(fpu.execute_register(1, 4, 7).unwrap_err(), "unsupported 8087 instruction D9 E7");
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+use crate::cpu::Cpu;
+
+/// Host code servicing a software interrupt in place of the machine's own
+/// handler, such as course-specific system calls. It gets the whole cpu, so
+/// it can read and change registers, flags and memory.
+pub trait InterruptHandler: Send {
+    fn handle(&mut self, cpu: &mut Cpu) -> Result<(), String>;
+}
+
+impl<F> InterruptHandler for F
+where
+    F: FnMut(&mut Cpu) -> Result<(), String> + Send,
+{
+    fn handle(&mut self, cpu: &mut Cpu) -> Result<(), String> {
+        self(cpu)
+    }
+}
+
+/// Host handlers by interrupt vector.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Handlers(Arc<BTreeMap<u8, Arc<Mutex<dyn InterruptHandler>>>>);
+
+impl fmt::Debug for Handlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+impl Cpu {
+    /// Services interrupt `n` with `handler` from now on. It takes
+    /// precedence over the interrupt vector table and the built-in BIOS and
+    /// DOS services.
+    pub fn set_handler(&mut self, n: u8, handler: impl InterruptHandler + 'static) {
+        Arc::make_mut(&mut self.handlers.0).insert(n, Arc::new(Mutex::new(handler)));
+    }
+
+    pub fn remove_handler(&mut self, n: u8) {
+        Arc::make_mut(&mut self.handlers.0).remove(&n);
+    }
+
+    /// Runs the host handler for `n`, if there is one. A handler raising
+    /// its own interrupt reaches the vector table or the built-in services,
+    /// so it can add to them rather than replace them.
+    pub(crate) fn host_interrupt(&mut self, n: u8) -> Option<Result<(), String>> {
+        let handler = self.handlers.0.get(&n)?.clone();
+        let mut handler = handler.try_lock().ok()?;
+
+        Some(handler.handle(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cpu::Cpu, memory::linear};
+
+    #[test]
+    fn host_handlers() {
+        let mut cpu = Cpu::default();
+
+        // INT 80h: AX = AX + BX, stored at DS:SI
+        cpu.set_handler(0x80, |cpu: &mut Cpu| {
+            let sum = cpu.reg16(0).wrapping_add(cpu.reg16(3));
+            cpu.set_reg16(0, sum);
+            cpu.write16(cpu.segments[3], cpu.reg16(6), sum);
+            Ok(())
+        });
+        // INT 21h prints a prefix, then lets DOS do the rest
+        cpu.set_handler(0x21, |cpu: &mut Cpu| {
+            cpu.console.push('>');
+            cpu.raise(0x21)
+        });
+
+        // MOV AX, 2; MOV BX, 3; MOV SI, 10h; INT 80h
+        // MOV DL, 'A'; MOV AH, 2; INT 21h; HLT
+        cpu.load_code(0x1000, 0, &[
+            0xB8, 0x02, 0x00, 0xBB, 0x03, 0x00, 0xBE, 0x10, 0x00, 0xCD, 0x80,
+            0xB2, 0x41, 0xB4, 0x02, 0xCD, 0x21, 0xF4,
+        ]);
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.memory.read_word(linear(0, 0x10)), 5);
+        assert_eq!(cpu.console, ">A");
+
+        cpu.remove_handler(0x80);
+        cpu.load_code(0x1000, 9, &[0xCD, 0x80]);
+        assert!(cpu.step().is_err());
+        assert_eq!(cpu.ip, 9);
+    }
+}
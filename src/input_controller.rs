@@ -10,7 +10,12 @@ pub struct InputController;
 
 impl<W: Widget<AppState>> Controller<AppState, W> for InputController {
     fn update(&mut self, child: &mut W, ctx: &mut druid::UpdateCtx, old_data: &AppState, data: &AppState, env: &druid::Env) {
-        if old_data.input != data.input {
+        if old_data.input != data.input
+            || old_data.binary != data.binary
+            || old_data.model != data.model
+            || old_data.quirks != data.quirks
+            || old_data.coprocessor != data.coprocessor
+        {
             ctx.submit_command(SHOULD);
         }
         child.update(ctx, old_data, data, env)
@@ -0,0 +1,31 @@
+//! The 8086 emulator: assembler, interpreter, machine-code cpu, BIOS and DOS
+//! services and file formats. The druid window lives in the binary behind
+//! the `gui` feature, which also derives druid's `Data` and `Lens` here.
+
+pub mod assembler;
+pub mod bios;
+pub mod cli;
+pub mod clock;
+pub mod cpu;
+pub mod decoder;
+pub mod disassembler;
+pub mod disk;
+pub mod dos;
+pub mod error;
+pub mod exe;
+pub mod execute;
+pub mod f80;
+pub mod fpu;
+pub mod handler;
+pub mod linker;
+pub mod memory;
+pub mod model;
+pub mod mouse;
+pub mod object;
+pub mod output;
+pub mod parser;
+pub mod register;
+pub mod speaker;
+pub mod timing;
+pub mod token;
+pub mod video;
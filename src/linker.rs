@@ -0,0 +1,245 @@
+use crate::{
+    dos::COM_MAX_SIZE,
+    exe::MzHeader,
+    object::{FixupKind, Module, Target},
+    output::Format,
+};
+
+/// Offset a .COM image starts at, after the PSP.
+const COM_ORIGIN: usize = 0x100;
+
+/// Stack an .EXE gets when no module has a STACK segment.
+const DEFAULT_STACK: u16 = 0x100;
+
+/// Segments of the same name from all modules, combined into one.
+struct Combined {
+    name: String,
+    class: String,
+    /// Image offset, always a paragraph boundary.
+    start: usize,
+    len: usize,
+}
+
+/// Where module segments ended up: the combined segment, their image
+/// offset.
+type Placement = Vec<Vec<(usize, usize)>>;
+
+fn layout(modules: &[Module]) -> Result<(Vec<Combined>, Placement), String> {
+    let mut combined: Vec<Combined> = vec![];
+    let mut parts: Vec<Vec<(usize, usize)>> = vec![];
+
+    // offsets within the combined segment first, every part on a paragraph
+    for m in modules {
+        let mut module_parts = vec![];
+
+        for s in &m.segments {
+            let c = match combined.iter().position(|c| c.name == s.name) {
+                Some(c) => c,
+                None => {
+                    combined.push(Combined {
+                        name: s.name.clone(),
+                        class: s.class.clone(),
+                        start: 0,
+                        len: 0,
+                    });
+                    combined.len() - 1
+                }
+            };
+
+            let at = combined[c].len.div_ceil(16) * 16;
+            combined[c].len = at + s.data.len();
+
+            if combined[c].len > 0x10000 {
+                return Err(format!("segment {} is larger than 64K", s.name));
+            }
+            module_parts.push((c, at));
+        }
+
+        parts.push(module_parts);
+    }
+
+    let mut pos = 0;
+    for c in &mut combined {
+        c.start = pos;
+        pos = (pos + c.len).div_ceil(16) * 16;
+    }
+
+    let placement = parts
+        .into_iter()
+        .map(|p| p.into_iter().map(|(c, at)| (c, combined[c].start + at)).collect())
+        .collect();
+
+    Ok((combined, placement))
+}
+
+/// Links `modules` into a .COM or .EXE. Segments of the same name are
+/// combined in module order and laid out in the order they first appear;
+/// the program starts at the beginning of the first one.
+///
+/// A .COM is one 64K segment at 100h, so it cannot take `SEG` fixups. In an
+/// .EXE those become load time relocations, and the stack is the STACK
+/// segment if there is one.
+pub fn link(modules: &[Module], format: Format) -> Result<Vec<u8>, String> {
+    if !matches!(format, Format::Com | Format::Exe) {
+        return Err("the linker writes .COM and .EXE files".to_string());
+    }
+
+    let (combined, placement) = layout(modules)?;
+    let size = combined.last().map_or(0, |c| c.start + c.len);
+
+    let mut publics: Vec<(&str, usize, usize)> = vec![];
+    for (mi, m) in modules.iter().enumerate() {
+        for p in &m.publics {
+            if let Some((_, other, _)) = publics.iter().find(|(n, _, _)| *n == p.name) {
+                return Err(format!(
+                    "{} is defined in both {} and {}",
+                    p.name, modules[*other].name, m.name
+                ));
+            }
+
+            let at = placement[mi].get(p.segment).ok_or("public in a missing segment")?.1;
+            publics.push((&p.name, mi, at + p.offset as usize));
+        }
+    }
+
+    let mut image = vec![0; size];
+    for (mi, m) in modules.iter().enumerate() {
+        for (s, (_, at)) in m.segments.iter().zip(&placement[mi]) {
+            image[*at..at + s.data.len()].copy_from_slice(&s.data);
+        }
+    }
+
+    // the paragraph of the combined segment that holds an image offset
+    let frame = |at: usize| {
+        let c = combined.iter().rev().find(|c| c.start <= at).unwrap();
+        (c.start / 16) as u16
+    };
+
+    let mut relocations = vec![];
+
+    for (mi, m) in modules.iter().enumerate() {
+        for f in &m.fixups {
+            let at = placement[mi].get(f.segment).ok_or("fixup in a missing segment")?.1 + f.offset as usize;
+
+            // the target's image offset and the frame it is addressed in
+            let (target, target_frame) = match f.target {
+                Target::Segment(s, disp) => {
+                    let (c, start) = *placement[mi].get(s).ok_or("fixup to a missing segment")?;
+                    (start + disp as usize, (combined[c].start / 16) as u16)
+                }
+                Target::External(e) => {
+                    let name = m.externs.get(e).ok_or("fixup to a missing external")?;
+                    let (_, _, at) = publics
+                        .iter()
+                        .find(|(n, _, _)| n == name)
+                        .ok_or_else(|| format!("unresolved external {name} in {}", m.name))?;
+                    (*at, frame(*at))
+                }
+            };
+
+            let value = match (f.kind, format) {
+                (FixupKind::Relative, _) => {
+                    if format == Format::Exe && frame(at) != target_frame {
+                        return Err(format!("near reference at {at:X}h in {} crosses segments", m.name));
+                    }
+                    target.wrapping_sub(at + 2) as u16
+                }
+                (FixupKind::Offset, Format::Com) => (COM_ORIGIN + target) as u16,
+                (FixupKind::Offset, _) => (target - target_frame as usize * 16) as u16,
+                (FixupKind::Base, Format::Com) => {
+                    return Err(format!("SEG fixup in {} needs an .EXE", m.name));
+                }
+                (FixupKind::Base, _) => {
+                    let source = frame(at);
+                    relocations.push((source, (at - source as usize * 16) as u16));
+                    target_frame
+                }
+            };
+
+            let field = u16::from_le_bytes([image[at], image[at + 1]]);
+            image[at..at + 2].copy_from_slice(&field.wrapping_add(value).to_le_bytes());
+        }
+    }
+
+    if format == Format::Com {
+        if size > COM_MAX_SIZE {
+            return Err(format!("{size} bytes are too many for a .COM"));
+        }
+        return Ok(image);
+    }
+
+    let mut header = MzHeader::for_image(size, &relocations);
+
+    match combined.iter().find(|c| c.name == "STACK" || c.class == "STACK") {
+        Some(c) if c.len > 0 => {
+            header.ss = (c.start / 16) as u16;
+            header.sp = c.len as u16;
+        }
+        _ => {
+            header.ss = size.div_ceil(16) as u16;
+            header.sp = DEFAULT_STACK;
+            header.min_alloc = DEFAULT_STACK / 16 + 1;
+        }
+    }
+
+    let mut out = header.to_bytes(&relocations);
+    out.extend(image);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::link;
+    use crate::{assembler::assemble, cpu::Cpu, output::Format};
+
+    fn module(src: &str, name: &str) -> crate::object::Module {
+        assemble(src, 0).unwrap().module(name).unwrap()
+    }
+
+    #[test]
+    fn com_calls_into_another_module() {
+        let main = module("EXTRN PRINT:NEAR\nCALL PRINT\nMOV AL, 0\nMOV AH, 4Ch\nINT 21h", "main");
+        let print = module("PUBLIC PRINT\nPRINT: MOV DL, 41h\nMOV AH, 2\nINT 21h\nRET", "print");
+
+        let com = link(&[main.clone(), print], Format::Com).unwrap();
+        assert_eq!(&com[..3], [0xE8, 0x0D, 0x00]);
+
+        let mut cpu = Cpu::default();
+        cpu.load_com(&com, "").unwrap();
+        cpu.run_code(100).unwrap();
+
+        assert_eq!(cpu.console, "A");
+        assert_eq!(cpu.exit_code(), Some(0));
+        assert_eq!(link(&[main], Format::Com).unwrap_err(), "unresolved external PRINT in main");
+    }
+
+    #[test]
+    fn exe_relocates_data_segment() {
+        let src = r#"
+            CODE SEGMENT 'CODE'
+            START: MOV AX, SEG VALUE
+            MOV DS, AX
+            MOV AL, [0]
+            MOV AH, 4Ch
+            INT 21h
+            CODE ENDS
+            DATA SEGMENT 'DATA'
+            VALUE: MOV AL, 1
+            DATA ENDS
+        "#;
+        let main = module(src, "main");
+        let exe = link(&[main], Format::Exe).unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.load_exe(&exe, "").unwrap();
+        cpu.run_code(100).unwrap();
+
+        // DATA starts on the paragraph after CODE, and holds B0 01
+        assert_eq!(cpu.get_word("DS"), cpu.get_word("CS") + 1);
+        assert_eq!(cpu.exit_code(), Some(0xB0));
+
+        let public = module("PUBLIC START\nSTART: RET", "other");
+        let err = link(&[public.clone(), public], Format::Exe).unwrap_err();
+        assert_eq!(err, "START is defined in both other and other");
+    }
+}
@@ -1,23 +1,43 @@
-mod cpu;
-mod input_controller;
-mod parser;
-mod register;
-mod token;
+#[cfg(feature = "gui")]
 mod delegate;
+#[cfg(feature = "gui")]
+mod input_controller;
+#[cfg(feature = "gui")]
+mod screen;
 
-use cpu::Cpu;
+use proc::cli;
+#[cfg(feature = "gui")]
+use proc::{
+    clock::{Clock, ClockSource},
+    cpu::Cpu,
+    model::Model,
+};
 
+#[cfg(feature = "gui")]
 use druid::{
-    widget::{Align, Container, Flex, Label, TextBox},
-    AppLauncher, Color, Data, Lens, UnitPoint, Widget, WidgetExt, WindowDesc, FontDescriptor, FontFamily, FontWeight,
+    widget::{Align, Button, Checkbox, Container, Flex, Label, LineBreaking, RadioGroup, TextBox},
+    AppLauncher, Color, Data, FileDialogOptions, FileSpec, Lens, UnitPoint, Widget, WidgetExt, WindowDesc, FontDescriptor, FontFamily, FontWeight,
 };
 
+#[cfg(feature = "gui")]
 const FONT: FontDescriptor = FontDescriptor::new(FontFamily::SYSTEM_UI).with_size(20.).with_weight(FontWeight::SEMI_BOLD);
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if !args.is_empty() || cfg!(not(feature = "gui")) {
+        std::process::exit(cli::run(&args));
+    }
+
+    #[cfg(feature = "gui")]
+    launch();
+}
+
+#[cfg(feature = "gui")]
+fn launch() {
     let window = WindowDesc::new(build)
         .title("my app")
-        .window_size((940., 550.));
+        .window_size((940., 1000.));
 
     let cpu = Cpu::default();
 
@@ -32,13 +52,58 @@ fn main() {
         .unwrap();
 }
 
+#[cfg(feature = "gui")]
 #[derive(Clone, Debug, Data, Lens, Default)]
 struct AppState {
     input: String,
     output: String,
+    /// Bytes each line assembles to.
+    listing: String,
+    /// Run the assembled machine code instead of interpreting the source.
+    binary: bool,
+    /// Disassembly of the loaded program or of an opened binary.
+    disassembly: String,
+    /// Processor programs run on, kept when the cpu is reset.
+    model: Model,
+    /// Fit an 8087 next to the cpu.
+    coprocessor: bool,
+    /// Run with the undocumented behaviour of the real chip.
+    quirks: bool,
+    /// Start the time of day at 1980-01-01 and advance it with executed
+    /// cycles instead of following the host clock.
+    virtual_clock: bool,
+    /// Why the last run failed, empty if it did not.
+    error: String,
     cpu: Cpu
 }
 
+#[cfg(feature = "gui")]
+impl AppState {
+    /// A fresh clock of the kind picked in the settings.
+    fn clock(&self) -> Clock {
+        Clock::new(if self.virtual_clock { ClockSource::VIRTUAL } else { ClockSource::Host })
+    }
+}
+
+/// The error panel's text: the error, then the source line it is on with a
+/// marker under its column.
+#[cfg(any(feature = "gui", test))]
+fn error_text(input: &str, e: &proc::error::Error) -> String {
+    let position = e.position();
+
+    match input.split('\n').nth(position.line.wrapping_sub(1)) {
+        Some(source) => {
+            let mut text = format!("{e}\n{:>4} | {}", position.line, source.trim_end());
+            if position.column > 0 {
+                text += &format!("\n     | {}^", " ".repeat(position.column - 1));
+            }
+            text
+        }
+        None => e.to_string(),
+    }
+}
+
+#[cfg(feature = "gui")]
 fn build() -> impl Widget<AppState> {
     let input = TextBox::multiline()
         .with_font(FONT)
@@ -46,11 +111,19 @@ fn build() -> impl Widget<AppState> {
         .fix_height(500.)
         .lens(AppState::input);
 
+    let errors = Label::dynamic(|data: &AppState, _| data.error.clone())
+        .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+        .with_text_color(Color::rgb8(0xFF, 0x60, 0x60))
+        .with_line_break_mode(LineBreaking::WordWrap)
+        .fix_width(450.);
+
     let input_label = Label::new("Input");
 
     let input = Flex::column()
         .with_child(input_label)
-        .with_flex_child(input, 1.0);
+        .with_flex_child(input, 1.0)
+        .with_spacer(5.)
+        .with_child(errors);
 
     let output = Container::new(Label::raw().with_font(FONT).lens(AppState::output))
         .background(Color::from_hex_str("#6039b3").unwrap_or(Color::grey8(0x55)))
@@ -66,5 +139,136 @@ fn build() -> impl Widget<AppState> {
 
     let output = Align::new(UnitPoint::TOP_RIGHT, output).padding(10.);
 
-    Flex::row().with_child(input).with_child(output)
+    let save_png = Button::new("Save PNG").on_click(|ctx, _data: &mut AppState, _env| {
+        let options = FileDialogOptions::new()
+            .allowed_types(vec![FileSpec::PNG])
+            .default_name("screen.png")
+            .accept_command(delegate::EXPORT_PNG);
+
+        ctx.submit_command(druid::commands::SHOW_SAVE_PANEL.with(options));
+    });
+
+    let run_program = Button::new("Run program").on_click(|ctx, _data: &mut AppState, _env| {
+        let options = FileDialogOptions::new()
+            .allowed_types(vec![FileSpec::new("DOS program", &["com", "exe"])])
+            .accept_command(delegate::RUN_PROGRAM);
+
+        ctx.submit_command(druid::commands::SHOW_OPEN_PANEL.with(options));
+    });
+
+    let boot_image = Button::new("Boot image").on_click(|ctx, _data: &mut AppState, _env| {
+        let options = FileDialogOptions::new()
+            .allowed_types(vec![FileSpec::new("Floppy image", &["img", "ima"])])
+            .accept_command(delegate::BOOT_IMAGE);
+
+        ctx.submit_command(druid::commands::SHOW_OPEN_PANEL.with(options));
+    });
+
+    let nmi = Button::new("NMI").on_click(|ctx, _data: &mut AppState, _env| {
+        ctx.submit_command(delegate::NMI);
+    });
+
+    let screen = Flex::column()
+        .with_child(Label::new("Screen"))
+        .with_child(screen::Screen.lens(AppState::cpu))
+        .with_spacer(5.)
+        .with_child(
+            Flex::row()
+                .with_child(save_png)
+                .with_spacer(5.)
+                .with_child(run_program)
+                .with_spacer(5.)
+                .with_child(boot_image)
+                .with_spacer(5.)
+                .with_child(nmi),
+        );
+
+    let screen = Align::new(UnitPoint::TOP, screen).padding(10.);
+
+    let open_binary = Button::new("Open binary").on_click(|ctx, _data: &mut AppState, _env| {
+        let options = FileDialogOptions::new().accept_command(delegate::DISASSEMBLE_FILE);
+
+        ctx.submit_command(druid::commands::SHOW_OPEN_PANEL.with(options));
+    });
+
+    let export = Button::new("Export").on_click(|ctx, _data: &mut AppState, _env| {
+        let options = FileDialogOptions::new()
+            .allowed_types(vec![
+                FileSpec::new("Flat binary", &["bin"]),
+                FileSpec::new("DOS program", &["com"]),
+                FileSpec::new("Intel HEX", &["hex"]),
+                FileSpec::new("Object module", &["obj"]),
+                FileSpec::new("DOS executable", &["exe"]),
+                FileSpec::new("Listing", &["lst"]),
+            ])
+            .default_name("program.com")
+            .accept_command(delegate::EXPORT_PROGRAM);
+
+        ctx.submit_command(druid::commands::SHOW_SAVE_PANEL.with(options));
+    });
+
+    let listing = Flex::column()
+        .with_child(Label::new("Machine code"))
+        .with_child(
+            Flex::row()
+                .with_child(Checkbox::new("Run as binary").lens(AppState::binary))
+                .with_spacer(5.)
+                .with_child(export),
+        )
+        .with_spacer(5.)
+        .with_child(Label::new("CPU"))
+        .with_child(RadioGroup::new(Model::ALL.map(|m| (m.name(), m))).lens(AppState::model))
+        .with_child(Checkbox::new("Quirks mode").lens(AppState::quirks))
+        .with_child(Checkbox::new("Virtual clock").lens(AppState::virtual_clock))
+        .with_child(Checkbox::new("8087 coprocessor").lens(AppState::coprocessor))
+        .with_child(
+            Label::dynamic(|data: &AppState, _| data.cpu.fpu.map(|f| f.registers_str()).unwrap_or_default())
+                .with_font(FontDescriptor::new(FontFamily::MONOSPACE)),
+        )
+        .with_spacer(5.)
+        .with_child(
+            Label::raw()
+                .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+                .lens(AppState::listing),
+        )
+        .with_spacer(10.)
+        .with_child(Label::new("Disassembly"))
+        .with_child(open_binary)
+        .with_spacer(5.)
+        .with_child(
+            Label::raw()
+                .with_font(FontDescriptor::new(FontFamily::MONOSPACE))
+                .lens(AppState::disassembly),
+        );
+
+    let listing = Align::new(UnitPoint::TOP_LEFT, listing).padding(10.);
+
+    Flex::column()
+        .with_child(Flex::row().with_child(input).with_child(output))
+        .with_child(Flex::row().with_child(screen).with_child(listing))
+}
+
+#[cfg(test)]
+mod tests {
+    use proc::error::{Error, Position};
+
+    use super::error_text;
+
+    #[test]
+    fn error_text_quotes_the_line() {
+        let input = "MOV AX, 1\nDIV BL\n";
+
+        let e = Error::runtime("divide error").at(Position::new(2, 0));
+        assert_eq!(error_text(input, &e), "line 2: divide error\n   2 | DIV BL");
+
+        let e = Error::syntax("expected a register").at(Position::new(1, 9));
+        assert_eq!(
+            error_text(input, &e),
+            "line 1, column 9: expected a register\n   1 | MOV AX, 1\n     |         ^"
+        );
+
+        // an error with no place in the source is shown alone
+        let e = Error::runtime("halted");
+        assert_eq!(error_text(input, &e), "halted");
+    }
 }
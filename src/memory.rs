@@ -0,0 +1,264 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+/// Size of the 8086 physical address space.
+pub const MEMORY_SIZE: usize = 1 << 20;
+
+/// End of conventional memory, where video memory starts, 640K.
+pub const CONVENTIONAL_SIZE: u32 = 0xA0000;
+
+/// Start of the BIOS ROM area, up to the end of the first megabyte.
+pub const BIOS_START: u32 = 0xF0000;
+
+/// Bytes above 1 MiB that FFFF:0010 to FFFF:FFFF reach when address line
+/// 20 is not masked.
+pub const HMA_SIZE: usize = 0x10000 - 16;
+
+/// Hardware that answers reads and writes to a range of physical
+/// addresses, such as the registers of a lab board. Offsets count from the
+/// start of the range.
+pub trait Device: Send {
+    fn read(&mut self, offset: u32) -> u8;
+    fn write(&mut self, offset: u32, val: u8);
+}
+
+/// What answers at a range of addresses.
+#[derive(Clone)]
+pub enum Mapping {
+    Ram,
+    /// Reads like RAM and ignores writes; `Memory::load` still fills it.
+    Rom,
+    /// Nothing is there, so reads float to FFh and writes are lost.
+    Unmapped,
+    Device(Arc<Mutex<dyn Device>>),
+}
+
+#[derive(Clone)]
+struct Region {
+    range: Range<u32>,
+    mapping: Mapping,
+}
+
+/// How a machine's first megabyte is laid out. Video memory from A0000h
+/// is always RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// Conventional RAM from address 0, at most 640K. The rest up to the
+    /// video memory is unmapped.
+    pub ram_size: u32,
+    /// The BIOS area from F0000h is read-only.
+    pub rom_bios: bool,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self {
+            ram_size: CONVENTIONAL_SIZE,
+            rom_bios: false,
+        }
+    }
+}
+
+/// Physical memory, shared copy-on-write so the GUI can keep cheap snapshots.
+/// Everything is RAM until parts are mapped otherwise.
+#[derive(Clone)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Memory {
+    bytes: Arc<Vec<u8>>,
+    /// Mapped ranges, later ones over earlier ones.
+    regions: Arc<Vec<Region>>,
+    /// Address line 20 is enabled, so addresses past 1 MiB do not wrap.
+    pub a20: bool,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            bytes: Arc::new(vec![0; MEMORY_SIZE + HMA_SIZE]),
+            regions: Arc::new(vec![]),
+            a20: false,
+        }
+    }
+}
+
+impl std::fmt::Debug for Memory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Memory({} KiB)", self.bytes.len() / 1024)
+    }
+}
+
+/// Physical address of `seg:off`.
+pub fn linear(seg: u16, off: u16) -> u32 {
+    ((seg as u32) << 4) + off as u32
+}
+
+fn index(addr: u32, a20: bool) -> usize {
+    match addr as usize {
+        a if a20 && a < MEMORY_SIZE + HMA_SIZE => a,
+        a => a & (MEMORY_SIZE - 1),
+    }
+}
+
+impl Memory {
+    pub fn new(map: MemoryMap) -> Self {
+        let mut memory = Memory::default();
+
+        if map.ram_size < CONVENTIONAL_SIZE {
+            memory.map(map.ram_size..CONVENTIONAL_SIZE, Mapping::Unmapped);
+        }
+        if map.rom_bios {
+            memory.map(BIOS_START..MEMORY_SIZE as u32, Mapping::Rom);
+        }
+
+        memory
+    }
+
+    /// Maps `range` of physical addresses, over whatever was there before.
+    pub fn map(&mut self, range: Range<u32>, mapping: Mapping) {
+        Arc::make_mut(&mut self.regions).push(Region { range, mapping });
+    }
+
+    /// Puts `device` at `range`.
+    pub fn attach(&mut self, range: Range<u32>, device: impl Device + 'static) {
+        self.map(range, Mapping::Device(Arc::new(Mutex::new(device))));
+    }
+
+    /// RAM from address 0 up to the first hole, at most 640K.
+    pub fn ram_size(&self) -> u32 {
+        self.regions
+            .iter()
+            .filter(|r| !matches!(r.mapping, Mapping::Ram) && r.range.start < CONVENTIONAL_SIZE)
+            .map(|r| r.range.start)
+            .fold(CONVENTIONAL_SIZE, u32::min)
+    }
+
+    fn region(&self, i: usize) -> Option<&Region> {
+        self.regions.iter().rev().find(|r| r.range.contains(&(i as u32)))
+    }
+
+    pub fn read_byte(&self, addr: u32) -> u8 {
+        let i = index(addr, self.a20);
+
+        match self.region(i) {
+            Some(Region { mapping: Mapping::Unmapped, .. }) => 0xFF,
+            Some(Region { range, mapping: Mapping::Device(d) }) => d.lock().unwrap().read(i as u32 - range.start),
+            _ => self.bytes[i],
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u32, val: u8) {
+        let i = index(addr, self.a20);
+
+        match self.region(i) {
+            Some(Region { mapping: Mapping::Rom | Mapping::Unmapped, .. }) => {}
+            Some(Region { range, mapping: Mapping::Device(d) }) => d.lock().unwrap().write(i as u32 - range.start, val),
+            _ => Arc::make_mut(&mut self.bytes)[i] = val,
+        }
+    }
+
+    pub fn read_word(&self, addr: u32) -> u16 {
+        u16::from_le_bytes([self.read_byte(addr), self.read_byte(addr + 1)])
+    }
+
+    pub fn write_word(&mut self, addr: u32, val: u16) {
+        let [lo, hi] = val.to_le_bytes();
+        self.write_byte(addr, lo);
+        self.write_byte(addr + 1, hi);
+    }
+
+    /// Copies `data` into memory starting at `addr`, wrapping at 1 MiB
+    /// unless A20 is enabled. This fills the memory behind the map, so it
+    /// can put ROM images in place.
+    pub fn load(&mut self, addr: u32, data: &[u8]) {
+        let a20 = self.a20;
+        let bytes = Arc::make_mut(&mut self.bytes);
+
+        for (i, b) in data.iter().enumerate() {
+            bytes[index(addr + i as u32, a20)] = *b;
+        }
+    }
+
+    pub fn read(&self, addr: u32, len: usize) -> Vec<u8> {
+        (0..len).map(|i| self.read_byte(addr + i as u32)).collect()
+    }
+
+    pub fn fill(&mut self, addr: u32, len: usize, val: u8) {
+        self.load(addr, &vec![val; len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{linear, Device, Mapping, Memory, MemoryMap, BIOS_START};
+
+    #[test]
+    fn words_are_little_endian() {
+        let mut m = Memory::default();
+        m.write_word(0x100, 0x1234);
+
+        assert_eq!(m.read_byte(0x100), 0x34);
+        assert_eq!(m.read_byte(0x101), 0x12);
+        assert_eq!(m.read_word(0x100), 0x1234);
+    }
+
+    #[test]
+    fn wraps_at_one_megabyte() {
+        let mut m = Memory::default();
+        m.write_word(linear(0xFFFF, 0xFFFF), 0xABCD);
+
+        assert_eq!(m.read_byte(0xFFEF), 0xCD);
+        assert_eq!(m.read_byte(0xFFF0), 0xAB);
+
+        // with A20 the same address is in the high memory area
+        m.a20 = true;
+        m.write_byte(linear(0xFFFF, 0x10), 0x55);
+        assert_eq!(m.read_byte(0x100000), 0x55);
+        assert_eq!(m.read_byte(0), 0);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut a = Memory::default();
+        let b = a.clone();
+        a.write_byte(0, 1);
+
+        assert_eq!(a.read_byte(0), 1);
+        assert_eq!(b.read_byte(0), 0);
+    }
+
+    /// Counts the writes it gets and reads back its offset.
+    struct Counter(u8);
+
+    impl Device for Counter {
+        fn read(&mut self, offset: u32) -> u8 {
+            offset as u8 + self.0
+        }
+
+        fn write(&mut self, _offset: u32, _val: u8) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn memory_map() {
+        let mut m = Memory::new(MemoryMap { ram_size: 0x40000, rom_bios: true });
+        m.load(BIOS_START, &[0xEA]);
+        m.write_byte(BIOS_START, 0x90);
+        m.write_byte(0x50000, 1);
+
+        assert_eq!(m.read_byte(BIOS_START), 0xEA);
+        assert_eq!(m.read_byte(0x50000), 0xFF);
+        assert_eq!(m.ram_size(), 0x40000);
+
+        m.attach(0xD0000..0xD0010, Counter(0));
+        m.write_byte(0xD0004, 7);
+        assert_eq!(m.read_byte(0xD0004), 5);
+
+        // a later mapping wins
+        m.map(0xD0000..0xD0008, Mapping::Ram);
+        assert_eq!(m.read_byte(0xD0004), 0);
+    }
+}
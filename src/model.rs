@@ -0,0 +1,75 @@
+#[cfg(feature = "gui")]
+use druid::Data;
+
+use crate::cpu::FLAGS_FIXED;
+
+/// The processor being emulated. All of them run in real mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub enum Model {
+    /// An 8086 with an 8-bit data bus, as in the IBM PC.
+    I8088,
+    #[default]
+    I8086,
+    /// Adds PUSHA/POPA, ENTER/LEAVE, BOUND, shifts by an immediate,
+    /// IMUL by an immediate, PUSH immediate and INS/OUTS.
+    I80186,
+    /// The 80186 instruction set; PUSH SP pushes the value before the push.
+    I80286,
+}
+
+/// Opcodes the 80186 added, after any prefixes.
+const OPCODES_80186: [u8; 15] = [
+    0x60, 0x61, 0x62, 0x68, 0x69, 0x6A, 0x6B, 0x6C, 0x6D, 0x6E, 0x6F, 0xC0, 0xC1, 0xC8, 0xC9,
+];
+
+/// Mnemonics of the 80186 additions, for the text interpreter.
+const MNEMONICS_80186: [&str; 11] = [
+    "pusha", "popa", "enter", "leave", "bound", "insb", "insw", "outsb", "outsw", "ins", "outs",
+];
+
+impl Model {
+    pub const ALL: [Model; 4] = [Model::I8088, Model::I8086, Model::I80186, Model::I80286];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Model::I8088 => "8088",
+            Model::I8086 => "8086",
+            Model::I80186 => "80186",
+            Model::I80286 => "80286",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name() == name.trim_start_matches(['i', 'I']))
+    }
+
+    pub fn has_80186_instructions(self) -> bool {
+        matches!(self, Model::I80186 | Model::I80286)
+    }
+
+    /// True if the processor decodes `opcode`, the first byte after prefixes.
+    pub fn supports_opcode(self, opcode: u8) -> bool {
+        self.has_80186_instructions() || !OPCODES_80186.contains(&opcode)
+    }
+
+    /// True if the processor has the instruction `mnemonic`.
+    pub fn supports_mnemonic(self, mnemonic: &str) -> bool {
+        self.has_80186_instructions() || !MNEMONICS_80186.contains(&mnemonic.to_lowercase().as_str())
+    }
+
+    /// `flags` with the bits the processor fixes in real mode: bits 1 and
+    /// 12-15 read as set up to the 80186, while the 80286 clears bits 12-15,
+    /// which is how programs tell it from an 8086.
+    pub fn fix_flags(self, flags: u16) -> u16 {
+        match self {
+            Model::I80286 => flags & 0x0FFF | 0x0002,
+            _ => flags | FLAGS_FIXED,
+        }
+    }
+
+    /// Words cross the 8088's bus as two bytes.
+    pub fn eight_bit_bus(self) -> bool {
+        self == Model::I8088
+    }
+}
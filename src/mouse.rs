@@ -0,0 +1,180 @@
+#[cfg(feature = "gui")]
+use druid::Data;
+
+use crate::cpu::Cpu;
+
+/// Size of the virtual screen the driver reports coordinates in.
+pub const VIRTUAL_WIDTH: u16 = 640;
+pub const VIRTUAL_HEIGHT: u16 = 200;
+
+pub const LEFT_BUTTON: u16 = 1;
+pub const RIGHT_BUTTON: u16 = 2;
+pub const MIDDLE_BUTTON: u16 = 4;
+
+/// State of the INT 33h mouse driver. The pointer itself is fed by the host,
+/// normally from mouse events over the screen widget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Mouse {
+    pub x: u16,
+    pub y: u16,
+    pub buttons: u16,
+    /// The cursor is shown while this is 0, hide calls decrement it.
+    pub visibility: i16,
+    pub min_x: u16,
+    pub max_x: u16,
+    pub min_y: u16,
+    pub max_y: u16,
+}
+
+impl Default for Mouse {
+    fn default() -> Self {
+        Self {
+            x: VIRTUAL_WIDTH / 2,
+            y: VIRTUAL_HEIGHT / 2,
+            buttons: 0,
+            visibility: -1,
+            min_x: 0,
+            max_x: VIRTUAL_WIDTH - 1,
+            min_y: 0,
+            max_y: VIRTUAL_HEIGHT - 1,
+        }
+    }
+}
+
+impl Mouse {
+    pub fn visible(&self) -> bool {
+        self.visibility >= 0
+    }
+
+    /// Fresh driver state that keeps the host pointer and buttons, used when
+    /// the program is run again.
+    pub fn reset_driver(&self) -> Self {
+        let mut m = Self {
+            buttons: self.buttons,
+            ..Self::default()
+        };
+
+        m.move_to(self.x, self.y);
+        m
+    }
+
+    /// Moves the pointer, clamped to the range set by the program.
+    pub fn move_to(&mut self, x: u16, y: u16) {
+        self.x = x.clamp(self.min_x, self.max_x);
+        self.y = y.clamp(self.min_y, self.max_y);
+    }
+
+    /// Takes a pointer event from the host, only updating the driver state
+    /// the program reads through INT 33h. True if the pointer or buttons
+    /// changed.
+    pub fn feed(&mut self, x: u16, y: u16, buttons: u16) -> bool {
+        let old = *self;
+
+        self.buttons = buttons;
+        self.move_to(x, y);
+        *self != old
+    }
+
+    pub fn set_horizontal_range(&mut self, a: u16, b: u16) {
+        self.min_x = a.min(b);
+        self.max_x = a.max(b);
+        self.move_to(self.x, self.y);
+    }
+
+    pub fn set_vertical_range(&mut self, a: u16, b: u16) {
+        self.min_y = a.min(b);
+        self.max_y = a.max(b);
+        self.move_to(self.x, self.y);
+    }
+}
+
+impl Cpu {
+    /// Mouse driver services, selected by AX.
+    pub(crate) fn int33(&mut self) -> Result<(), String> {
+        let ax = self.get_word("AX");
+        let cx = self.get_word("CX");
+        let dx = self.get_word("DX");
+
+        match ax {
+            0x00 => {
+                self.mouse = self.mouse.reset_driver();
+                self.set_word("AX", 0xFFFF)?;
+                self.set_word("BX", 2)?;
+            }
+            0x01 => self.mouse.visibility = (self.mouse.visibility + 1).min(0),
+            0x02 => self.mouse.visibility -= 1,
+            0x03 => {
+                self.set_word("BX", self.mouse.buttons)?;
+                self.set_word("CX", self.mouse.x)?;
+                self.set_word("DX", self.mouse.y)?;
+            }
+            0x04 => self.mouse.move_to(cx, dx),
+            0x07 => self.mouse.set_horizontal_range(cx, dx),
+            0x08 => self.mouse.set_vertical_range(cx, dx),
+            _ => return Err(format!("unsupported mouse function {ax:02X}h")),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mouse, LEFT_BUTTON};
+    use crate::{cpu::Cpu, memory::linear};
+
+    #[test]
+    fn ranges_clamp_the_pointer() {
+        let mut m = Mouse::default();
+
+        m.set_horizontal_range(600, 100);
+        m.move_to(639, 0);
+
+        assert_eq!((m.min_x, m.max_x), (100, 600));
+        assert_eq!((m.x, m.y), (600, 0));
+    }
+
+    #[test]
+    fn int33() {
+        let str = r#"
+            MOV AH, 0
+            MOV AL, 0
+            INT 33h
+            MOV AL, 1
+            MOV AH, 0
+            INT 33h
+            MOV AL, 3
+            INT 33h
+        "#;
+
+        let mut cpu = Cpu::from_str(str);
+        cpu.mouse.move_to(320, 150);
+        cpu.mouse.buttons = LEFT_BUTTON;
+        cpu.run().unwrap();
+
+        assert!(cpu.mouse.visible());
+        assert_eq!(cpu.get_word("BX"), LEFT_BUTTON);
+        assert_eq!(cpu.get_word("CX"), 320);
+        assert_eq!(cpu.get_word("DX"), 150);
+    }
+
+    #[test]
+    fn events_reach_a_loaded_program() {
+        // MOV AX, 3; INT 33h; HLT
+        let code = [0xB8, 0x03, 0x00, 0xCD, 0x33, 0xF4];
+        let mut cpu = Cpu::default();
+        cpu.load_code(0x1000, 0, &code);
+
+        assert!(cpu.mouse.feed(100, 50, LEFT_BUTTON));
+        assert!(!cpu.mouse.feed(100, 50, LEFT_BUTTON));
+
+        // the event alone runs nothing
+        assert_eq!((cpu.get_word("CS"), cpu.ip), (0x1000, 0));
+        assert_eq!(cpu.memory.read(linear(0x1000, 0), code.len()), code);
+
+        cpu.run_code(100).unwrap();
+        assert_eq!(cpu.get_word("BX"), LEFT_BUTTON);
+        assert_eq!((cpu.get_word("CX"), cpu.get_word("DX")), (100, 50));
+    }
+}
@@ -0,0 +1,436 @@
+use std::fs;
+use std::path::Path;
+
+/// OMF record types.
+const THEADR: u8 = 0x80;
+const COMENT: u8 = 0x88;
+const MODEND: u8 = 0x8A;
+const EXTDEF: u8 = 0x8C;
+const PUBDEF: u8 = 0x90;
+const LNAMES: u8 = 0x96;
+const SEGDEF: u8 = 0x98;
+const FIXUPP: u8 = 0x9C;
+const LEDATA: u8 = 0xA0;
+
+/// Paragraph aligned, public combine type.
+const ACBP_PARA_PUBLIC: u8 = 0x68;
+
+/// Largest LEDATA payload.
+const LEDATA_MAX: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub name: String,
+    pub class: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Public {
+    pub name: String,
+    pub segment: usize,
+    pub offset: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixupKind {
+    /// 16-bit displacement from the end of the field, for near CALL and JMP.
+    Relative,
+    /// 16-bit offset of the target within its segment.
+    Offset,
+    /// Paragraph of the target's segment, a load time relocation in an .EXE.
+    Base,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// An offset into one of the module's own segments.
+    Segment(usize, u16),
+    External(usize),
+}
+
+/// A 16-bit field in `segment` at `offset` the linker has to fill in. The
+/// computed value is added to what the field already holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixup {
+    pub segment: usize,
+    pub offset: u16,
+    pub kind: FixupKind,
+    pub target: Target,
+}
+
+/// One assembled module, what an OMF .OBJ file holds. Indices are 0-based.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Module {
+    pub name: String,
+    pub segments: Vec<Segment>,
+    pub publics: Vec<Public>,
+    pub externs: Vec<String>,
+    pub fixups: Vec<Fixup>,
+}
+
+fn record(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut r = vec![kind];
+    r.extend(((body.len() + 1) as u16).to_le_bytes());
+    r.extend(body);
+
+    let sum = r.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+    r.push(sum.wrapping_neg());
+    r
+}
+
+fn name(s: &str) -> Vec<u8> {
+    let mut v = vec![s.len().min(255) as u8];
+    v.extend(&s.as_bytes()[..s.len().min(255)]);
+    v
+}
+
+/// OMF index fields take one byte below 80h and two above.
+fn index(i: usize) -> Vec<u8> {
+    if i < 0x80 {
+        vec![i as u8]
+    } else {
+        vec![0x80 | (i >> 8) as u8, i as u8]
+    }
+}
+
+impl Module {
+    /// Encodes the module as OMF records.
+    pub fn to_obj(&self) -> Vec<u8> {
+        let mut out = record(THEADR, &name(&self.name));
+
+        // segment names first, then their classes
+        let mut lnames = vec![];
+        for s in &self.segments {
+            lnames.extend(name(&s.name));
+        }
+        for s in &self.segments {
+            lnames.extend(name(&s.class));
+        }
+        if !self.segments.is_empty() {
+            out.extend(record(LNAMES, &lnames));
+        }
+
+        let n = self.segments.len();
+        for (i, s) in self.segments.iter().enumerate() {
+            let mut body = vec![ACBP_PARA_PUBLIC];
+            body.extend((s.data.len() as u16).to_le_bytes());
+            body.extend(index(i + 1));
+            body.extend(index(n + i + 1));
+            body.extend(index(0));
+            out.extend(record(SEGDEF, &body));
+        }
+
+        if !self.externs.is_empty() {
+            let mut body = vec![];
+            for e in &self.externs {
+                body.extend(name(e));
+                body.extend(index(0));
+            }
+            out.extend(record(EXTDEF, &body));
+        }
+
+        for p in &self.publics {
+            let mut body = index(0);
+            body.extend(index(p.segment + 1));
+            body.extend(name(&p.name));
+            body.extend(p.offset.to_le_bytes());
+            body.extend(index(0));
+            out.extend(record(PUBDEF, &body));
+        }
+
+        for (i, s) in self.segments.iter().enumerate() {
+            for (c, chunk) in s.data.chunks(LEDATA_MAX).enumerate() {
+                let start = c * LEDATA_MAX;
+                let mut body = index(i + 1);
+                body.extend((start as u16).to_le_bytes());
+                body.extend(chunk);
+                out.extend(record(LEDATA, &body));
+
+                let fixups: Vec<&Fixup> = self
+                    .fixups
+                    .iter()
+                    .filter(|f| f.segment == i && (start..start + chunk.len()).contains(&(f.offset as usize)))
+                    .collect();
+
+                if !fixups.is_empty() {
+                    let body: Vec<u8> = fixups.iter().flat_map(|f| fixup_subrecord(f, start)).collect();
+                    out.extend(record(FIXUPP, &body));
+                }
+            }
+        }
+
+        out.extend(record(MODEND, &[0x00]));
+        out
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_obj()).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+
+        Self::from_obj(&data).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Reads the OMF records `to_obj` writes. Threads, groups and other
+    /// record types are not supported, apart from comments.
+    pub fn from_obj(data: &[u8]) -> Result<Self, String> {
+        let mut module = Module::default();
+        let mut lnames: Vec<String> = vec![];
+        let mut last_data: Option<(usize, u16)> = None;
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let header = data.get(pos..pos + 3).ok_or("truncated record")?;
+            let len = u16::from_le_bytes([header[1], header[2]]) as usize;
+            let rec = data.get(pos..pos + 3 + len).ok_or("truncated record")?;
+
+            if rec.iter().fold(0u8, |s, b| s.wrapping_add(*b)) != 0 && rec[rec.len() - 1] != 0 {
+                return Err(format!("bad checksum in record at {pos:X}h"));
+            }
+
+            let mut r = Reader {
+                body: &rec[3..rec.len() - 1],
+                pos: 0,
+            };
+
+            match header[0] {
+                THEADR => module.name = r.name()?,
+                COMENT => {}
+                LNAMES => {
+                    while !r.done() {
+                        lnames.push(r.name()?);
+                    }
+                }
+                SEGDEF => {
+                    let acbp = r.byte()?;
+                    if acbp >> 5 == 0 {
+                        return Err("absolute segments are not supported".to_string());
+                    }
+
+                    let len = r.word()? as usize;
+                    let lname = |i: usize| lnames.get(i.wrapping_sub(1)).cloned().ok_or("bad name index");
+                    let name = lname(r.index()?)?;
+                    let class = lname(r.index()?).unwrap_or_default();
+
+                    module.segments.push(Segment {
+                        name,
+                        class,
+                        data: vec![0; len],
+                    });
+                }
+                EXTDEF => {
+                    while !r.done() {
+                        module.externs.push(r.name()?);
+                        r.index()?;
+                    }
+                }
+                PUBDEF => {
+                    r.index()?;
+                    let segment = r.index()?.checked_sub(1).ok_or("absolute publics are not supported")?;
+
+                    while !r.done() {
+                        let name = r.name()?;
+                        let offset = r.word()?;
+                        r.index()?;
+                        module.publics.push(Public { name, segment, offset });
+                    }
+                }
+                LEDATA => {
+                    let segment = r.index()?.checked_sub(1).ok_or("bad segment index")?;
+                    let offset = r.word()?;
+                    let bytes = &r.body[r.pos..];
+                    let seg = module.segments.get_mut(segment).ok_or("bad segment index")?;
+                    let dst = seg
+                        .data
+                        .get_mut(offset as usize..offset as usize + bytes.len())
+                        .ok_or("LEDATA past the end of its segment")?;
+
+                    dst.copy_from_slice(bytes);
+                    last_data = Some((segment, offset));
+                }
+                FIXUPP => {
+                    let (segment, base) = last_data.ok_or("FIXUPP without LEDATA")?;
+
+                    while !r.done() {
+                        module.fixups.push(read_fixup(&mut r, segment, base)?);
+                    }
+                }
+                MODEND => break,
+                t => return Err(format!("unsupported OMF record {t:02X}h")),
+            }
+
+            pos += 3 + len;
+        }
+
+        Ok(module)
+    }
+}
+
+fn fixup_subrecord(f: &Fixup, data_start: usize) -> Vec<u8> {
+    let (segment_relative, location) = match f.kind {
+        FixupKind::Relative => (0, 1),
+        FixupKind::Offset => (1, 1),
+        FixupKind::Base => (1, 2),
+    };
+
+    let at = f.offset as usize - data_start;
+    let locat = 0x8000 | segment_relative << 14 | location << 10 | at as u16;
+    let mut v = locat.to_be_bytes().to_vec();
+
+    // frame F5 (the target's), target by segment with a displacement or by
+    // external without one
+    match f.target {
+        Target::Segment(s, disp) => {
+            v.push(0x50);
+            v.extend(index(s + 1));
+            v.extend(disp.to_le_bytes());
+        }
+        Target::External(e) => {
+            v.push(0x56);
+            v.extend(index(e + 1));
+        }
+    }
+
+    v
+}
+
+fn read_fixup(r: &mut Reader, segment: usize, base: u16) -> Result<Fixup, String> {
+    let locat = u16::from_be_bytes([r.byte()?, r.byte()?]);
+
+    if locat & 0x8000 == 0 {
+        return Err("fixup threads are not supported".to_string());
+    }
+
+    let kind = match (locat >> 14 & 1, locat >> 10 & 0xF) {
+        (0, 1) => FixupKind::Relative,
+        (1, 1) => FixupKind::Offset,
+        (1, 2) => FixupKind::Base,
+        _ => return Err("unsupported fixup location".to_string()),
+    };
+
+    let fixdat = r.byte()?;
+    if fixdat & 0x88 != 0 {
+        return Err("fixup threads are not supported".to_string());
+    }
+    if (fixdat >> 4) & 7 < 3 {
+        r.index()?;
+    }
+
+    let datum = r.index()?.checked_sub(1).ok_or("bad fixup target")?;
+    let disp = if fixdat & 4 == 0 { r.word()? } else { 0 };
+
+    let target = match fixdat & 3 {
+        0 => Target::Segment(datum, disp),
+        2 => Target::External(datum),
+        _ => return Err("unsupported fixup target".to_string()),
+    };
+
+    Ok(Fixup {
+        segment,
+        offset: base + (locat & 0x3FF),
+        kind,
+        target,
+    })
+}
+
+struct Reader<'a> {
+    body: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn done(&self) -> bool {
+        self.pos >= self.body.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let b = *self.body.get(self.pos).ok_or("truncated record")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn word(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn index(&mut self) -> Result<usize, String> {
+        let b = self.byte()? as usize;
+
+        if b & 0x80 == 0 {
+            Ok(b)
+        } else {
+            Ok((b & 0x7F) << 8 | self.byte()? as usize)
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.byte()? as usize;
+        let s = self.body.get(self.pos..self.pos + len).ok_or("truncated name")?;
+        self.pos += len;
+
+        Ok(String::from_utf8_lossy(s).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fixup, FixupKind, Module, Public, Segment, Target};
+
+    fn sample() -> Module {
+        Module {
+            name: "main".into(),
+            segments: vec![
+                Segment {
+                    name: "CODE".into(),
+                    class: "CODE".into(),
+                    data: vec![0xE8, 0, 0, 0xB8, 0, 0, 0xC3],
+                },
+                Segment {
+                    name: "DATA".into(),
+                    class: "DATA".into(),
+                    data: vec![1; 300],
+                },
+            ],
+            publics: vec![Public {
+                name: "START".into(),
+                segment: 0,
+                offset: 0,
+            }],
+            externs: vec!["PRINT".into()],
+            fixups: vec![
+                Fixup {
+                    segment: 0,
+                    offset: 1,
+                    kind: FixupKind::Relative,
+                    target: Target::External(0),
+                },
+                Fixup {
+                    segment: 0,
+                    offset: 4,
+                    kind: FixupKind::Base,
+                    target: Target::Segment(1, 0),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn records_are_checksummed() {
+        let obj = sample().to_obj();
+
+        assert_eq!(obj[0], 0x80);
+        assert_eq!(&obj[3..8], b"\x04main");
+        assert_eq!(obj[..9].iter().fold(0u8, |s, b| s.wrapping_add(*b)), 0);
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(Module::from_obj(&sample().to_obj()).unwrap(), sample());
+        assert!(Module::from_obj(&[0x80, 0x05, 0x00]).is_err());
+    }
+}
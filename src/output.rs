@@ -0,0 +1,174 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{assembler::Program, linker};
+
+/// Data bytes per Intel HEX record.
+const HEX_RECORD_LEN: usize = 16;
+
+/// Files the assembler can write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The raw image, addressed from the program's ORG.
+    Binary,
+    /// A DOS .COM program, which has to be at ORG 100h.
+    Com,
+    /// Intel HEX records for loading into a trainer board.
+    Hex,
+    /// An OMF object module for the linker.
+    Obj,
+    /// A DOS .EXE, the program linked on its own.
+    Exe,
+    /// A `.LST` listing of the source with its machine code.
+    Lst,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "bin" => Some(Format::Binary),
+            "com" => Some(Format::Com),
+            "hex" | "ihx" => Some(Format::Hex),
+            "obj" => Some(Format::Obj),
+            "exe" => Some(Format::Exe),
+            "lst" => Some(Format::Lst),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_name(path.extension()?.to_str()?)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Binary => "bin",
+            Format::Com => "com",
+            Format::Hex => "hex",
+            Format::Obj => "obj",
+            Format::Exe => "exe",
+            Format::Lst => "lst",
+        }
+    }
+}
+
+/// The file contents of `program` in `format`, `name` naming the object
+/// module. A .COM gets an INT 20h appended, so a program that runs off its
+/// end returns to DOS.
+pub fn export(program: &Program, format: Format, name: &str) -> Result<Vec<u8>, String> {
+    match format {
+        Format::Obj => return Ok(program.module(name)?.to_obj()),
+        Format::Exe => return linker::link(&[program.module(name)?], Format::Exe),
+        Format::Lst => return Ok(program.lst().into_bytes()),
+        _ => program.check_flat()?,
+    }
+
+    let mut bytes = program.bytes();
+
+    match format {
+        Format::Binary => Ok(bytes),
+        Format::Com => {
+            if program.origin != 0x100 {
+                return Err(format!(".COM programs need ORG 100h, not {:X}h", program.origin));
+            }
+
+            bytes.extend([0xCD, 0x20]);
+            Ok(bytes)
+        }
+        Format::Hex => Ok(intel_hex(&bytes, program.origin as u32).into_bytes()),
+        Format::Obj | Format::Exe | Format::Lst => unreachable!(),
+    }
+}
+
+pub fn write(program: &Program, format: Format, path: impl AsRef<Path>) -> Result<(), String> {
+    let path = path.as_ref();
+    let name = path.file_stem().map(|s| s.to_string_lossy().to_uppercase()).unwrap_or_default();
+    let data = export(program, format, &name)?;
+
+    fs::write(path, data).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+fn record(kind: u8, addr: u16, data: &[u8]) -> String {
+    let [hi, lo] = addr.to_be_bytes();
+    let mut bytes = vec![data.len() as u8, hi, lo, kind];
+    bytes.extend(data);
+
+    let sum = bytes.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+
+    let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+    format!(":{hex}\n")
+}
+
+/// Intel HEX for `data` loaded at physical `address`. Extended segment
+/// address records cover data above 64K.
+pub fn intel_hex(data: &[u8], address: u32) -> String {
+    let mut out = String::new();
+    let mut segment = 0;
+
+    for (i, chunk) in data.chunks(HEX_RECORD_LEN).enumerate() {
+        let addr = address + (i * HEX_RECORD_LEN) as u32;
+
+        if addr >> 16 != segment {
+            segment = addr >> 16;
+            out += &record(0x02, 0, &((segment << 12) as u16).to_be_bytes());
+        }
+
+        // records may not cross a 64K boundary
+        let split = (0x10000 - (addr & 0xFFFF) as usize).min(chunk.len());
+        out += &record(0x00, addr as u16, &chunk[..split]);
+
+        if split < chunk.len() {
+            segment += 1;
+            out += &record(0x02, 0, &((segment << 12) as u16).to_be_bytes());
+            out += &record(0x00, 0, &chunk[split..]);
+        }
+    }
+
+    out + &record(0x01, 0, &[])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export, intel_hex, Format};
+    use crate::assembler::assemble;
+
+    #[test]
+    fn binary_follows_org() {
+        let p = assemble("ORG 7C00h\nMOV AL, 1\nORG 7C04h\nINT 10h", 0).unwrap();
+
+        assert_eq!(p.origin, 0x7C00);
+        assert_eq!(export(&p, Format::Binary, "").unwrap(), [0xB0, 0x01, 0, 0, 0xCD, 0x10]);
+        assert!(export(&p, Format::Com, "").is_err());
+        assert!(assemble("MOV AL, 1\nORG 0", 0x100).is_err());
+    }
+
+    #[test]
+    fn com_returns_to_dos() {
+        let p = assemble("ORG 100h\nMOV AH, 2", 0).unwrap();
+        assert_eq!(export(&p, Format::Com, "").unwrap(), [0xB4, 0x02, 0xCD, 0x20]);
+    }
+
+    #[test]
+    fn hex_records() {
+        assert_eq!(
+            intel_hex(&[0xB0, 0x01, 0xF4], 0x100),
+            ":03010000B001F457\n:00000001FF\n"
+        );
+
+        let hex = intel_hex(&[0xAA; 20], 0xFFF8);
+        let lines: Vec<&str> = hex.lines().collect();
+
+        assert_eq!(
+            lines,
+            [
+                ":08FFF800AAAAAAAAAAAAAAAAB1",
+                ":020000021000EC",
+                ":08000000AAAAAAAAAAAAAAAAA8",
+                ":04000800AAAAAAAA4C",
+                ":00000001FF",
+            ]
+        );
+        assert_eq!(Format::from_name("COM"), Some(Format::Com));
+    }
+}
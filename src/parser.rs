@@ -1,16 +1,21 @@
 use std::str::FromStr;
 use std::sync::Arc;
 
-use druid::{Lens, Data};
+#[cfg(feature = "gui")]
+use druid::{Data, Lens};
 
-use crate::register::Register;
+use crate::error::{Error, Position};
+use crate::register::{self, Register};
 use crate::token::Token;
 
-#[derive(Default, Clone, Data, Debug, Lens)]
+#[derive(Default, Clone, Debug)]
+#[cfg_attr(feature = "gui", derive(Data, Lens))]
 pub struct Parser {
     pub contents: Arc<Vec<u8>>,
     pos: usize,
     peek_pos: usize,
+    /// Where the last token read starts.
+    start: usize,
 }
 
 impl Parser {
@@ -21,9 +26,23 @@ impl Parser {
             contents,
             pos: 0,
             peek_pos: 0,
+            start: 0,
         }
     }
 
+    /// Line of the last token read, counting from 1.
+    pub fn line(&self) -> usize {
+        let start = self.start.min(self.contents.len());
+        self.contents[..start].iter().filter(|c| **c == b'\n').count() + 1
+    }
+
+    /// Column of the last token read, counting from 1.
+    pub fn column(&self) -> usize {
+        let start = self.start.min(self.contents.len());
+        let line_start = self.contents[..start].iter().rposition(|c| *c == b'\n').map_or(0, |i| i + 1);
+        start - line_start + 1
+    }
+
     pub fn skip_whitespace(&mut self) {
         while let Some(c) = self.contents.get(self.pos) {
             if (*c as char).is_whitespace() {
@@ -56,7 +75,7 @@ impl Parser {
     pub fn read_int(&mut self) -> String {
         let p = self.pos;
         while let Some(c) = self.contents.get(self.pos) {
-            if !(*c as char).is_numeric() {
+            if !(*c as char).is_ascii_hexdigit() && !matches!(*c, b'h' | b'H') {
                 break;
             }
 
@@ -70,18 +89,134 @@ impl Parser {
 
         s
     }
+
+    /// True if the next character after any whitespace is a comma.
+    fn comma_follows(&self) -> bool {
+        self.contents[self.peek_pos.min(self.contents.len())..]
+            .iter()
+            .find(|c| !(**c as char).is_whitespace())
+            == Some(&b',')
+    }
+
+    /// Reads the contents of a `[...]` memory operand, leaving the parser
+    /// after the closing bracket.
+    pub fn read_memory(&mut self) -> Option<String> {
+        let start = self.pos + 1;
+        let len = self.contents[start..].iter().position(|c| *c == b']')?;
+
+        self.pos = start + len;
+        self.peek_pos = self.pos + 1;
+
+        Some(String::from_utf8_lossy(&self.contents[start..self.pos]).trim().to_string())
+    }
 }
 
 fn is_instruction(s: &str) -> bool {
     match s.to_lowercase().as_str() {
         "add" | "sub" | "mul" | "xchg" | "mov" | "div" | "and" | "or" | "xor" | "inc" | "dec"
-        | "not" => true,
+        | "not" | "in" | "out" | "int" => true,
         _ => false,
     }
 }
 
+/// Parses a decimal literal or a hex literal with an `h` suffix, saturating
+/// on overflow.
+pub fn parse_num(lit: &str) -> Option<u32> {
+    let (digits, radix) = match lit.strip_suffix(['h', 'H']) {
+        Some(d) => (d, 16),
+        None => (lit, 10),
+    };
+
+    match u32::from_str_radix(digits, radix) {
+        Ok(v) => Some(v),
+        Err(e) => match e.kind() {
+            std::num::IntErrorKind::PosOverflow => Some(u32::MAX),
+            std::num::IntErrorKind::NegOverflow => Some(0),
+            _ => None,
+        },
+    }
+}
+
+impl Parser {
+    /// Where the last token read starts.
+    pub fn location(&self) -> Position {
+        Position::new(self.line(), self.column())
+    }
+
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::syntax(message).at(self.location())
+    }
+
+    /// Reads a register, instruction or keyword.
+    fn word(&mut self) -> Option<Result<Token, Error>> {
+        let lit = self.read_literal();
+
+        if Register::from_str(&lit).is_ok() || register::is_word_register(&lit) {
+            Some(Ok(Token::Reg(lit)))
+        } else if lit.eq_ignore_ascii_case("org") {
+            // placement is the assembler's business, skip the address
+            self.next();
+            self.next()
+        } else if lit.eq_ignore_ascii_case("byte") || lit.eq_ignore_ascii_case("ptr") {
+            // memory operands are bytes already, the size is only noise
+            self.next()
+        } else if is_instruction(&lit) {
+            Some(self.instruction(lit))
+        } else {
+            let message = format!("unknown instruction {}", lit.to_uppercase());
+            Some(Err(Error::unknown_mnemonic(lit, message).at(self.location())))
+        }
+    }
+
+    /// Reads an operand of the instruction `name` at `at`.
+    fn operand(&mut self, name: &str, at: Position) -> Result<Token, Error> {
+        match self.next() {
+            Some(Err(Error::UnknownMnemonic { position, mnemonic, .. })) => {
+                Err(Error::syntax(format!("{mnemonic} is not a register, number or memory operand")).at(position))
+            }
+            Some(t) => t,
+            None => Err(Error::syntax(format!("{} is missing an operand", name.to_uppercase())).at(at)),
+        }
+    }
+
+    /// Reads the operands of the instruction `name`, which is placed where
+    /// its mnemonic is.
+    fn instruction(&mut self, name: String) -> Result<Token, Error> {
+        let start = self.start;
+        let at = self.location();
+        let left = self.operand(&name, at)?;
+
+        let single = match name.to_lowercase().as_str() {
+            "mul" | "div" => !self.comma_follows(),
+            "inc" | "dec" | "not" | "int" => true,
+            _ => false,
+        };
+
+        let right = if single {
+            None
+        } else {
+            match self.next() {
+                Some(Ok(Token::Comma)) => {}
+                Some(Ok(_)) => return Err(self.error("expected a comma")),
+                Some(Err(e)) => return Err(e),
+                None => return Err(Error::syntax(format!("{} is missing an operand", name.to_uppercase())).at(at)),
+            }
+
+            Some(Box::new(self.operand(&name, at)?))
+        };
+
+        self.start = start;
+
+        Ok(Token::Instruction {
+            name,
+            left: Box::new(left),
+            right,
+        })
+    }
+}
+
 impl Iterator for Parser {
-    type Item = Token;
+    type Item = Result<Token, Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
         use Token::*;
@@ -90,75 +225,36 @@ impl Iterator for Parser {
         self.peek_pos += 1;
 
         self.skip_whitespace();
+        self.start = self.pos;
 
-        match self.contents.get(self.pos) {
-            Some(c) => match *c as char {
-                ',' => Some(Comma),
-                c if c.is_numeric() => {
-                    let lit = self.read_int();
-
-                    let v: u8 = match lit.parse() {
-                        Ok(v) => v,
-                        Err(e) => match e.kind() {
-                            std::num::IntErrorKind::PosOverflow => 255,
-                            std::num::IntErrorKind::NegOverflow => 0,
-                            _ => unreachable!()
-                        }
-                    };
-
-                    Some(Num(v))
-                }
-                c if c.is_alphabetic() => {
-                    let lit = self.read_literal();
-
-                    if let Ok(_) = Register::from_str(&lit) {
-                        return Some(Reg(lit));
-                    } else if is_instruction(&lit) {
-                        let left = self.next();
-                        if let Some(left) = left {
-                            match lit.to_lowercase().as_str() {
-                                "inc" | "dec" | "not" => Some(Instruction {
-                                    name: lit.clone(),
-                                    left: Box::new(left),
-                                    right: None,
-                                }),
-                                _ => {
-                                    match self.next() {
-                                        Some(Comma) => {}
-                                        Some(_) => return Some(Illegal),
-                                        None => return None,
-                                    }
-                                    let right = self.next();
-
-                                    if let Some(right) = right {
-                                        Some(Instruction {
-                                            name: lit.clone(),
-                                            left: Box::new(left),
-                                            right: Some(Box::new(right)),
-                                        })
-                                    } else {
-                                        None
-                                    }
-                                }
-                            }
-                        } else {
-                            None
-                        }
-                        // this should be a comma
-                    } else {
-                        Some(Illegal)
-                    }
+        let token = match *self.contents.get(self.pos)? as char {
+            ',' => Comma,
+            c if c.is_numeric() => {
+                let lit = self.read_int();
+
+                match parse_num(&lit) {
+                    Some(v) => Num(v),
+                    None => return Some(Err(self.error(format!("{lit} is not a number")))),
                 }
-                _ => Some(Illegal),
+            }
+            '[' => match self.read_memory() {
+                Some(m) => match parse_num(&m) {
+                    Some(a) => Mem(a),
+                    None => return Some(Err(self.error(format!("[{m}] is not a memory address")))),
+                },
+                None => return Some(Err(self.error("[ without a closing ]"))),
             },
-            None => None,
-        }
+            c if c.is_alphabetic() => return self.word(),
+            c => return Some(Err(self.error(format!("unexpected character {c:?}")))),
+        };
+
+        Some(Ok(token))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::token::Token::*;
+    use crate::{error::Error, token::Token::*};
 
     use super::Parser;
 
@@ -166,26 +262,110 @@ mod tests {
     fn pos() {
         let mut p = Parser::new("AH 123 AL add DH, 199 l \n inc CH".to_string());
 
-        assert_eq!(Some(Reg("AH".into())), p.next());
-        assert_eq!(Some(Num(123)), p.next());
-        assert_eq!(Some(Reg("AL".into())), p.next());
+        assert_eq!(Some(Ok(Reg("AH".into()))), p.next());
+        assert_eq!(Some(Ok(Num(123))), p.next());
+        assert_eq!(Some(Ok(Reg("AL".into()))), p.next());
         assert_eq!(
-            Some(Instruction {
+            Some(Ok(Instruction {
                 name: "add".into(),
                 left: Box::new(Reg("DH".into())),
                 right: Some(Box::new(Num(199)))
-            }),
+            })),
             p.next()
         );
-        assert_eq!(Some(Illegal), p.next());
+        assert!(matches!(
+            p.next(),
+            Some(Err(Error::UnknownMnemonic { position, mnemonic, .. })) if (position.line, position.column) == (1, 23) && mnemonic == "l"
+        ));
         assert_eq!(
-            Some(Instruction {
+            Some(Ok(Instruction {
                 name: "inc".into(),
                 left: Box::new(Reg("CH".into())),
                 right: None
-            }),
+            })),
             p.next()
         );
         assert_eq!(None, p.next());
     }
+
+    #[test]
+    fn line_and_column() {
+        let mut p = Parser::new("MOV AL, 1\n\n  inc CH");
+
+        p.next();
+        assert_eq!((p.line(), p.column()), (1, 1));
+        p.next();
+        assert_eq!((p.line(), p.column()), (3, 3));
+    }
+
+    #[test]
+    fn hex_numbers() {
+        let mut p = Parser::new("OUT 43h, AL 0FFh 1Ah 300 1h2".to_string());
+
+        assert_eq!(
+            Some(Ok(Instruction {
+                name: "OUT".into(),
+                left: Box::new(Num(0x43)),
+                right: Some(Box::new(Reg("AL".into())))
+            })),
+            p.next()
+        );
+        assert_eq!(Some(Ok(Num(0xFF))), p.next());
+        assert_eq!(Some(Ok(Num(0x1A))), p.next());
+        assert_eq!(Some(Ok(Num(300))), p.next());
+        assert!(matches!(p.next(), Some(Err(Error::Syntax { .. }))));
+    }
+
+    #[test]
+    fn memory_operands() {
+        let mut p = Parser::new("MOV [0B8000h], AL\nINT 10h\n[12".to_string());
+
+        assert_eq!(
+            Some(Ok(Instruction {
+                name: "MOV".into(),
+                left: Box::new(Mem(0xB8000)),
+                right: Some(Box::new(Reg("AL".into())))
+            })),
+            p.next()
+        );
+        assert_eq!(
+            Some(Ok(Instruction {
+                name: "INT".into(),
+                left: Box::new(Num(0x10)),
+                right: None
+            })),
+            p.next()
+        );
+        assert_eq!(p.next().unwrap().unwrap_err().to_string(), "line 3, column 1: [ without a closing ]");
+    }
+
+    #[test]
+    fn single_operand_mul_and_byte_ptr() {
+        let mut p = Parser::new("MUL CH\nDIV AL, 3\nNOT BYTE PTR [20h]".to_string());
+
+        assert_eq!(
+            Some(Ok(Instruction {
+                name: "MUL".into(),
+                left: Box::new(Reg("CH".into())),
+                right: None
+            })),
+            p.next()
+        );
+        assert_eq!(
+            Some(Ok(Instruction {
+                name: "DIV".into(),
+                left: Box::new(Reg("AL".into())),
+                right: Some(Box::new(Num(3)))
+            })),
+            p.next()
+        );
+        assert_eq!(
+            Some(Ok(Instruction {
+                name: "NOT".into(),
+                left: Box::new(Mem(0x20)),
+                right: None
+            })),
+            p.next()
+        );
+    }
 }
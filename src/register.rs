@@ -1,8 +1,10 @@
 use std::str::FromStr;
 
+#[cfg(feature = "gui")]
 use druid::Data;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Data)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "gui", derive(Data))]
 pub enum Register {
     AH(u8),
     AL(u8),
@@ -215,6 +217,15 @@ impl FromStr for Register {
     }
 }
 
+/// 16-bit registers that can be named in source: the general purpose pairs,
+/// the pointer and index registers and the segment registers.
+pub fn is_word_register(s: &str) -> bool {
+    matches!(
+        s,
+        "AX" | "BX" | "CX" | "DX" | "SP" | "BP" | "SI" | "DI" | "ES" | "CS" | "SS" | "DS"
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::Register::{self, *};
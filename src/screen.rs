@@ -0,0 +1,99 @@
+use druid::{
+    piet::{ImageFormat, InterpolationMode},
+    widget::prelude::*,
+    Color, MouseEvent, Point, Rect,
+};
+
+use proc::{
+    cpu::Cpu,
+    mouse::{LEFT_BUTTON, MIDDLE_BUTTON, RIGHT_BUTTON, VIRTUAL_HEIGHT, VIRTUAL_WIDTH},
+};
+
+pub const SCREEN_WIDTH: f64 = 640.;
+pub const SCREEN_HEIGHT: f64 = 400.;
+
+/// Shows the CGA frame buffer of the emulated machine and feeds mouse
+/// events over it to the INT 33h driver.
+pub struct Screen;
+
+impl Screen {
+    fn feed_mouse(ctx: &mut EventCtx, mouse: &MouseEvent, data: &mut Cpu) {
+        let size = ctx.size();
+        let x = mouse.pos.x / size.width * VIRTUAL_WIDTH as f64;
+        let y = mouse.pos.y / size.height * VIRTUAL_HEIGHT as f64;
+
+        let mut buttons = 0;
+        if mouse.buttons.has_left() {
+            buttons |= LEFT_BUTTON;
+        }
+        if mouse.buttons.has_right() {
+            buttons |= RIGHT_BUTTON;
+        }
+        if mouse.buttons.has_middle() {
+            buttons |= MIDDLE_BUTTON;
+        }
+
+        // the program reads the pointer through INT 33h whenever it runs next,
+        // so only the cursor needs drawing again
+        if data.mouse.feed(x.max(0.) as u16, y.max(0.) as u16, buttons) {
+            ctx.request_paint();
+        }
+    }
+}
+
+impl Widget<Cpu> for Screen {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Cpu, _env: &Env) {
+        match event {
+            Event::MouseDown(mouse) => {
+                ctx.set_active(true);
+                Self::feed_mouse(ctx, mouse, data);
+            }
+            Event::MouseUp(mouse) => {
+                ctx.set_active(false);
+                Self::feed_mouse(ctx, mouse, data);
+            }
+            Event::MouseMove(mouse) if ctx.is_hot() || ctx.is_active() => {
+                Self::feed_mouse(ctx, mouse, data);
+            }
+            _ => {}
+        }
+    }
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &Cpu, _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Cpu, data: &Cpu, _env: &Env) {
+        if !old_data.memory.same(&data.memory)
+            || !old_data.video.same(&data.video)
+            || !old_data.mouse.same(&data.mouse)
+        {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(&mut self, _ctx: &mut LayoutCtx, bc: &BoxConstraints, _data: &Cpu, _env: &Env) -> Size {
+        bc.constrain(Size::new(SCREEN_WIDTH, SCREEN_HEIGHT))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Cpu, _env: &Env) {
+        let rect = ctx.size().to_rect();
+        ctx.fill(rect, &Color::BLACK);
+
+        if let Some(frame) = data.video.render(&data.memory) {
+            let image = ctx.make_image(frame.width, frame.height, &frame.rgb, ImageFormat::Rgb);
+
+            if let Ok(image) = image {
+                ctx.draw_image(&image, rect, InterpolationMode::NearestNeighbor);
+            }
+        }
+
+        if data.mouse.visible() {
+            let size = ctx.size();
+            let x = data.mouse.x as f64 / VIRTUAL_WIDTH as f64 * size.width;
+            let y = data.mouse.y as f64 / VIRTUAL_HEIGHT as f64 * size.height;
+            let cursor = Rect::from_origin_size(Point::new(x, y), (8., 12.));
+
+            ctx.fill(cursor, &Color::WHITE);
+            ctx.stroke(cursor, &Color::BLACK, 1.);
+        }
+    }
+}
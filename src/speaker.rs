@@ -0,0 +1,365 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+/// Input clock of the 8253 programmable interval timer.
+pub const PIT_HZ: u64 = 1_193_182;
+/// Clock of a stock 4.77 MHz PC, which is exactly four timer ticks.
+pub const CPU_HZ: u64 = PIT_HZ * 4;
+
+const AMPLITUDE: i16 = 8192;
+
+/// Snapshot of everything that shapes the speaker output, taken whenever
+/// the program touches port 42h, 43h or 61h.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct SpeakerState {
+    /// CPU cycle at which this state took effect.
+    pub cycle: u64,
+    /// Port 61h bit 0, the gate input of timer channel 2.
+    pub gate: bool,
+    /// Port 61h bit 1, connects the timer output to the speaker.
+    pub enabled: bool,
+    pub mode: u8,
+    pub reload: u16,
+    /// False until a full count has been written after a mode change.
+    pub loaded: bool,
+    /// CPU cycle at which the counter was (re)started.
+    pub start: u64,
+}
+
+impl SpeakerState {
+    fn period(&self) -> u64 {
+        if self.reload == 0 {
+            0x10000
+        } else {
+            self.reload as u64
+        }
+    }
+
+    fn ticks(&self, cycle: u64) -> u64 {
+        cycle.saturating_sub(self.start) * PIT_HZ / CPU_HZ
+    }
+
+    /// Level of the channel 2 output pin at the given cycle.
+    pub fn output(&self, cycle: u64) -> bool {
+        if !self.loaded || !self.gate {
+            return true;
+        }
+
+        let n = self.period();
+        let ticks = self.ticks(cycle);
+
+        match self.mode {
+            0 => ticks >= n,
+            2 | 6 => ticks % n != n - 1,
+            3 | 7 => ticks % n < n.div_ceil(2),
+            4 => ticks != n,
+            _ => true,
+        }
+    }
+
+    /// Current value of the down counter at the given cycle.
+    pub fn count(&self, cycle: u64) -> u16 {
+        if !self.loaded {
+            return self.reload;
+        }
+
+        let n = self.period();
+        let step = if matches!(self.mode, 3 | 7) { 2 } else { 1 };
+
+        (n - (self.ticks(cycle) * step) % n) as u16
+    }
+
+    pub fn sample(&self, cycle: u64) -> i16 {
+        match (self.enabled, self.output(cycle)) {
+            (false, _) => 0,
+            (true, true) => AMPLITUDE,
+            (true, false) => -AMPLITUDE,
+        }
+    }
+}
+
+/// PC speaker driven by channel 2 of the interval timer and port 61h.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Speaker {
+    state: SpeakerState,
+    port61: u8,
+    access: u8,
+    low_byte: Option<u8>,
+    read_high: bool,
+    latch: Option<u16>,
+    history: Arc<Vec<SpeakerState>>,
+}
+
+impl Default for Speaker {
+    fn default() -> Self {
+        Self {
+            state: SpeakerState {
+                cycle: 0,
+                gate: false,
+                enabled: false,
+                mode: 3,
+                reload: 0,
+                loaded: false,
+                start: 0,
+            },
+            port61: 0,
+            access: 3,
+            low_byte: None,
+            read_high: false,
+            latch: None,
+            history: Arc::new(Vec::new()),
+        }
+    }
+}
+
+impl Speaker {
+    pub fn handles(port: u16) -> bool {
+        matches!(port, 0x42 | 0x43 | 0x61)
+    }
+
+    pub fn state(&self) -> SpeakerState {
+        self.state
+    }
+
+    pub fn history(&self) -> &[SpeakerState] {
+        &self.history
+    }
+
+    /// Frequency of the square wave currently programmed, if any.
+    pub fn frequency(&self) -> Option<f64> {
+        if self.state.loaded {
+            Some(PIT_HZ as f64 / self.state.period() as f64)
+        } else {
+            None
+        }
+    }
+
+    pub fn write_port(&mut self, port: u16, val: u8, cycle: u64) {
+        match port {
+            0x43 => self.write_control(val, cycle),
+            0x42 => self.write_count(val, cycle),
+            0x61 => {
+                let gate = val & 1 != 0;
+
+                if gate && !self.state.gate {
+                    self.state.start = cycle;
+                }
+
+                self.port61 = val;
+                self.state.gate = gate;
+                self.state.enabled = val & 2 != 0;
+                self.record(cycle);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn read_port(&mut self, port: u16, cycle: u64) -> u8 {
+        match port {
+            0x42 => {
+                let count = match self.latch {
+                    Some(c) => c,
+                    None => self.state.count(cycle),
+                };
+
+                let byte = match self.access {
+                    1 => count as u8,
+                    2 => (count >> 8) as u8,
+                    _ => {
+                        let b = if self.read_high { (count >> 8) as u8 } else { count as u8 };
+                        self.read_high = !self.read_high;
+                        b
+                    }
+                };
+
+                if self.access != 3 || !self.read_high {
+                    self.latch = None;
+                }
+
+                byte
+            }
+            0x61 => {
+                let out = if self.state.output(cycle) { 0x20 } else { 0 };
+                (self.port61 & !0x20) | out
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write_control(&mut self, val: u8, cycle: u64) {
+        // only channel 2 is wired to the speaker
+        if val >> 6 != 2 {
+            return;
+        }
+
+        let access = (val >> 4) & 3;
+
+        if access == 0 {
+            self.latch = Some(self.state.count(cycle));
+            return;
+        }
+
+        self.access = access;
+        self.low_byte = None;
+        self.read_high = false;
+        self.state.mode = (val >> 1) & 7;
+        self.state.loaded = false;
+        self.record(cycle);
+    }
+
+    fn write_count(&mut self, val: u8, cycle: u64) {
+        let reload = match self.access {
+            1 => val as u16,
+            2 => (val as u16) << 8,
+            _ => match self.low_byte.take() {
+                Some(lo) => (val as u16) << 8 | lo as u16,
+                None => {
+                    self.low_byte = Some(val);
+                    return;
+                }
+            },
+        };
+
+        self.state.reload = reload;
+        self.state.loaded = true;
+        self.state.start = cycle;
+        self.record(cycle);
+    }
+
+    fn record(&mut self, cycle: u64) {
+        self.state.cycle = cycle;
+
+        let history = Arc::make_mut(&mut self.history);
+        match history.last_mut() {
+            Some(last) if last.cycle == cycle => *last = self.state,
+            _ => history.push(self.state),
+        }
+    }
+
+    /// Renders the speaker output from cycle 0 up to `end` as signed 16-bit
+    /// mono samples.
+    pub fn render(&self, end: u64, sample_rate: u32) -> Vec<i16> {
+        let rate = sample_rate as u64;
+        let len = end * rate / CPU_HZ;
+        let mut samples = Vec::with_capacity(len as usize);
+        let mut idx = 0;
+
+        for i in 0..len {
+            let cycle = i * CPU_HZ / rate;
+
+            while idx + 1 < self.history.len() && self.history[idx + 1].cycle <= cycle {
+                idx += 1;
+            }
+
+            let sample = match self.history.get(idx) {
+                Some(s) if s.cycle <= cycle => s.sample(cycle),
+                _ => 0,
+            };
+
+            samples.push(sample);
+        }
+
+        samples
+    }
+
+    pub fn save_wav(&self, path: impl AsRef<Path>, end: u64, sample_rate: u32) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        write_wav(file, &self.render(end, sample_rate), sample_rate)
+    }
+}
+
+/// Writes samples as a 16-bit mono PCM WAV stream.
+pub fn write_wav<W: Write>(mut w: W, samples: &[i16], sample_rate: u32) -> io::Result<()> {
+    let data_len = samples.len() as u32 * 2;
+
+    w.write_all(b"RIFF")?;
+    w.write_all(&(36 + data_len).to_le_bytes())?;
+    w.write_all(b"WAVEfmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&(sample_rate * 2).to_le_bytes())?;
+    w.write_all(&2u16.to_le_bytes())?;
+    w.write_all(&16u16.to_le_bytes())?;
+    w.write_all(b"data")?;
+    w.write_all(&data_len.to_le_bytes())?;
+
+    for s in samples {
+        w.write_all(&s.to_le_bytes())?;
+    }
+
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_wav, Speaker, CPU_HZ};
+
+    fn beep(reload: u16) -> Speaker {
+        let mut s = Speaker::default();
+
+        s.write_port(0x43, 0xB6, 0);
+        s.write_port(0x42, reload as u8, 0);
+        s.write_port(0x42, (reload >> 8) as u8, 0);
+        s.write_port(0x61, 3, 0);
+
+        s
+    }
+
+    #[test]
+    fn square_wave() {
+        let s = beep(1193);
+
+        assert_eq!(s.frequency().map(|f| f.round()), Some(1000.));
+
+        // one second at 8 kHz should contain a thousand rising edges
+        let samples = s.render(CPU_HZ, 8000);
+        let edges = samples.windows(2).filter(|w| w[0] < 0 && w[1] > 0).count();
+
+        assert_eq!(samples.len(), 8000);
+        assert!((999..=1001).contains(&edges), "{edges}");
+    }
+
+    #[test]
+    fn gate_off_is_silent() {
+        let mut s = beep(1193);
+        s.write_port(0x61, 0, CPU_HZ / 2);
+
+        let samples = s.render(CPU_HZ, 8000);
+
+        assert!(samples[..4000].iter().any(|s| *s != 0));
+        assert!(samples[4000..].iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn read_back_count() {
+        let mut s = beep(1000);
+
+        s.write_port(0x43, 0x80, 0);
+        let lo = s.read_port(0x42, 0);
+        let hi = s.read_port(0x42, 0);
+
+        assert_eq!((hi as u16) << 8 | lo as u16, 1000);
+        assert_eq!(s.read_port(0x61, 0) & 3, 3);
+    }
+
+    #[test]
+    fn wav_header() {
+        let mut buf = Vec::new();
+        write_wav(&mut buf, &[0, 1, -1], 44100).unwrap();
+
+        assert_eq!(&buf[0..4], b"RIFF");
+        assert_eq!(&buf[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(buf[24..28].try_into().unwrap()), 44100);
+        assert_eq!(u32::from_le_bytes(buf[40..44].try_into().unwrap()), 6);
+        assert_eq!(buf.len(), 44 + 6);
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{
+    decoder::{Address, Instruction, Mnemonic, Operand, Width},
+    model::Model,
+};
+
+/// Extra cycles for every word transfer at an odd address, which the 8086
+/// splits into two bus cycles.
+const ODD_WORD_PENALTY: u64 = 4;
+
+/// What happened while an instruction ran that its timing depends on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// A conditional jump or loop went to its target.
+    pub taken: bool,
+    /// Iterations of a REP string instruction.
+    pub repeats: u64,
+    /// CL for shifts and rotates by CL.
+    pub count: u8,
+    /// The memory operand is a word at an odd address.
+    pub odd: bool,
+}
+
+/// Cycles the 8086 spends computing an effective address, including two
+/// for a segment override. A displacement is taken to be present when it
+/// is non-zero, as assemblers drop zero ones, or for `[BP+d]` which always
+/// has one.
+pub fn ea_cycles(addr: &Address, segment_override: bool) -> u64 {
+    let disp = addr.disp != 0 || addr.rm == Some(6);
+
+    let base = match (addr.rm, disp) {
+        (None, _) => 6,
+        // BP+DI and BX+SI
+        (Some(0 | 3), false) => 7,
+        (Some(0 | 3), true) => 11,
+        // BP+SI and BX+DI
+        (Some(1 | 2), false) => 8,
+        (Some(1 | 2), true) => 12,
+        (Some(_), false) => 5,
+        (Some(_), true) => 9,
+    };
+
+    base + if segment_override { 2 } else { 0 }
+}
+
+/// Memory transfers of a word operand, two when it is read and written.
+fn transfers(ins: &Instruction) -> u64 {
+    use Mnemonic::*;
+
+    match (ins.mnemonic, ins.dst) {
+        (Les | Lds, _) => 2,
+        (Cmp | Test, _) => 1,
+        (Add | Or | Adc | Sbb | And | Sub | Xor | Inc | Dec | Not | Neg | Xchg, Some(Operand::Mem(_))) => 2,
+        (Rol | Ror | Rcl | Rcr | Shl | Shr | Sar, Some(Operand::Mem(_))) => 2,
+        _ => 1,
+    }
+}
+
+/// Words an instruction pushes or pops, such as the return address of a
+/// call or the flags and far address of an interrupt.
+fn stack_transfers(ins: &Instruction, t: Timing) -> u64 {
+    use Mnemonic::*;
+
+    match (ins.mnemonic, ins.src) {
+        (Push | Pop | Pushf | Popf | Call | Ret | Leave, _) => 1,
+        (CallFar | Retf, _) => 2,
+        (Int | Int3 | Iret, _) => 3,
+        (Into, _) if t.taken => 3,
+        (Pusha | Popa, _) => 8,
+        // BP, a copy of each outer frame pointer, then the new one
+        (Enter, Some(Operand::Imm(level))) if level > 0 => 2 * level as u64,
+        (Enter, _) => 1,
+        _ => 0,
+    }
+}
+
+/// Clock cycles of `ins` on `model`, following the instruction timing
+/// tables of the 8086 user's manual and the 80186 ones for its additions.
+/// Multiply and divide take their shortest time, as the real figures depend
+/// on the operands. The 8088 spends four more cycles on every word it
+/// moves, whether a memory operand or on the stack; the 80186 and 80286 are
+/// timed as an 8086.
+pub fn cycles(ins: &Instruction, t: Timing, model: Model) -> u64 {
+    use Mnemonic::*;
+    use Operand::*;
+
+    let mem = [ins.dst, ins.src].into_iter().flatten().find_map(|op| match op {
+        Mem(a) => Some(a),
+        _ => None,
+    });
+    let ea = mem.map_or(0, |a| ea_cycles(&a, ins.segment.is_some()));
+    let word = ins.width == Width::Word;
+    let taken = |yes: u64, no: u64| if t.taken { yes } else { no };
+    let by = |byte: u64, word_: u64| if word { word_ } else { byte };
+
+    let base = match (ins.mnemonic, ins.dst, ins.src) {
+        (Mov, Some(Reg8(0) | Reg16(0)), Some(Mem(Address { rm: None, .. })))
+        | (Mov, Some(Mem(Address { rm: None, .. })), Some(Reg8(0) | Reg16(0))) => 10,
+        (Mov, Some(Mem(_)), Some(Imm(_))) => 10 + ea,
+        (Mov, Some(Mem(_)), _) => 9 + ea,
+        (Mov, _, Some(Mem(_))) => 8 + ea,
+        (Mov, _, Some(Imm(_))) => 4,
+        (Mov, _, _) => 2,
+
+        (Cmp, Some(Mem(_)), Some(Imm(_))) => 10 + ea,
+        (Cmp, Some(Mem(_)), _) => 9 + ea,
+        (Test, Some(Mem(_)), Some(Imm(_))) => 11 + ea,
+        (Test, Some(Mem(_)), _) => 9 + ea,
+        (Test, _, Some(Imm(_))) if !matches!(ins.dst, Some(Reg8(0) | Reg16(0))) => 5,
+        (Add | Or | Adc | Sbb | And | Sub | Xor | Cmp | Test, dst, src) => match (dst, src) {
+            (Some(Mem(_)), Some(Imm(_))) => 17 + ea,
+            (Some(Mem(_)), _) => 16 + ea,
+            (_, Some(Mem(_))) => 9 + ea,
+            (_, Some(Imm(_))) => 4,
+            _ => 3,
+        },
+
+        (Inc | Dec, Some(Reg16(_)), _) => 2,
+        (Inc | Dec, Some(Reg8(_)), _) => 3,
+        (Inc | Dec, _, _) => 15 + ea,
+        (Not | Neg, Some(Mem(_)), _) => 16 + ea,
+        (Not | Neg, _, _) => 3,
+
+        (Rol | Ror | Rcl | Rcr | Shl | Shr | Sar, dst, src) => {
+            let by_cl = matches!(src, Some(Reg8(1)));
+            let n = t.count as u64 * 4;
+
+            match (dst, src) {
+                (Some(Mem(_)), _) if by_cl => 20 + ea + n,
+                (_, _) if by_cl => 8 + n,
+                // 80186 shifts by an immediate count
+                (Some(Mem(_)), Some(Imm(c))) if c != 1 => 17 + ea + c as u64,
+                (_, Some(Imm(c))) if c != 1 => 5 + c as u64,
+                (Some(Mem(_)), _) => 15 + ea,
+                _ => 2,
+            }
+        }
+
+        (Imul, _, Some(Mem(_))) if ins.imm.is_some() => 25 + ea,
+        (Imul, _, _) if ins.imm.is_some() => 22,
+        (Mul, Some(Mem(_)), _) => by(76, 124) + ea,
+        (Mul, _, _) => by(70, 118),
+        (Imul, Some(Mem(_)), _) => by(86, 134) + ea,
+        (Imul, _, _) => by(80, 128),
+        (Div, Some(Mem(_)), _) => by(86, 150) + ea,
+        (Div, _, _) => by(80, 144),
+        (Idiv, Some(Mem(_)), _) => by(107, 171) + ea,
+        (Idiv, _, _) => by(101, 165),
+
+        (Push, Some(Mem(_)), _) => 16 + ea,
+        (Push, Some(Seg(_) | Imm(_)), _) => 10,
+        (Push, _, _) => 11,
+        (Pop, Some(Mem(_)), _) => 17 + ea,
+        (Pop, _, _) => 8,
+        (Pushf, _, _) => 10,
+        (Popf, _, _) => 8,
+        (Pusha, _, _) => 36,
+        (Popa, _, _) => 51,
+        (Enter, _, Some(Imm(0))) => 15,
+        (Enter, _, Some(Imm(1))) => 25,
+        (Enter, _, Some(Imm(level))) => 22 + 16 * (level as u64 - 1),
+        (Enter, _, _) => 15,
+        (Leave, _, _) => 8,
+        (Bound, _, _) => 33 + ea,
+
+        (Xchg, Some(Reg16(0)), Some(Reg16(_))) => 3,
+        (Xchg, _, _) if mem.is_some() => 17 + ea,
+        (Xchg, _, _) => 4,
+        (Lea, _, _) => 2 + ea,
+        (Les | Lds, _, _) => 16 + ea,
+
+        (Daa | Das | Aaa | Aas | Lahf | Sahf, _, _) => 4,
+        (Aam, _, _) => 83,
+        (Aad, _, _) => 60,
+        (Cbw, _, _) => 2,
+        (Salc, _, _) => 3,
+        (Cwd, _, _) => 5,
+        (Xlat, _, _) => 11,
+
+        (Movs, _, _) if ins.rep.is_some() => 9 + 17 * t.repeats,
+        (Cmps, _, _) if ins.rep.is_some() => 9 + 22 * t.repeats,
+        (Stos, _, _) if ins.rep.is_some() => 9 + 10 * t.repeats,
+        (Lods, _, _) if ins.rep.is_some() => 9 + 13 * t.repeats,
+        (Scas, _, _) if ins.rep.is_some() => 9 + 15 * t.repeats,
+        (Ins | Outs, _, _) if ins.rep.is_some() => 8 + 8 * t.repeats,
+        (Ins | Outs, _, _) => 14,
+        (Movs, _, _) => 18,
+        (Cmps, _, _) => 22,
+        (Stos, _, _) => 11,
+        (Lods, _, _) => 12,
+        (Scas, _, _) => 15,
+
+        (Jcc(_), _, _) => taken(16, 4),
+        (Loop, _, _) => taken(17, 5),
+        (Loopz, _, _) => taken(18, 6),
+        (Loopnz, _, _) => taken(19, 5),
+        (Jcxz, _, _) => taken(18, 6),
+        (Jmp, Some(Reg16(_)), _) => 11,
+        (Jmp, Some(Mem(_)), _) => 18 + ea,
+        (Jmp, _, _) => 15,
+        (JmpFar, Some(Mem(_)), _) => 24 + ea,
+        (JmpFar, _, _) => 15,
+        (Call, Some(Reg16(_)), _) => 16,
+        (Call, Some(Mem(_)), _) => 21 + ea,
+        (Call, _, _) => 19,
+        (CallFar, Some(Mem(_)), _) => 37 + ea,
+        (CallFar, _, _) => 28,
+        (Ret, Some(Imm(_)), _) => 12,
+        (Ret, _, _) => 8,
+        (Retf, Some(Imm(_)), _) => 17,
+        (Retf, _, _) => 18,
+        (Int, _, _) => 51,
+        (Int3, _, _) => 52,
+        (Into, _, _) => taken(53, 4),
+        (Iret, _, _) => 24,
+
+        (In | Out, _, _) if [ins.dst, ins.src].contains(&Some(Reg16(2))) => 8,
+        (In | Out, _, _) => 10,
+
+        (Wait, _, _) => 3,
+        (Esc(_), _, _) if mem.is_some() => 8 + ea,
+        (Nop, _, _) => 3,
+        (Hlt | Cmc | Clc | Stc | Cli | Sti | Cld | Std | Esc(_), _, _) => 2,
+    };
+
+    let odd = if (t.odd || model.eight_bit_bus()) && word && mem.is_some() {
+        ODD_WORD_PENALTY * transfers(ins)
+    } else {
+        0
+    };
+    let stack = if model.eight_bit_bus() {
+        ODD_WORD_PENALTY * stack_transfers(ins, t)
+    } else {
+        0
+    };
+    let lock = if ins.lock { 2 } else { 0 };
+
+    base + odd + stack + lock
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cycles, Timing};
+    use crate::{decoder::decode, model::Model};
+
+    fn time(code: &[u8], t: Timing) -> u64 {
+        cycles(&decode(code, 0).unwrap(), t, Model::I8086)
+    }
+
+    #[test]
+    fn manual_timings() {
+        let none = Timing::default();
+
+        assert_eq!(time(&[0x89, 0xD8], none), 2); // MOV AX, BX
+        assert_eq!(time(&[0x8B, 0x46, 0xFE], none), 8 + 9); // MOV AX, [BP-2]
+        assert_eq!(time(&[0x26, 0x01, 0x07], none), 16 + 5 + 2); // ADD ES:[BX], AX
+        assert_eq!(time(&[0x03, 0x00], none), 9 + 7); // ADD AX, [BX+SI]
+        assert_eq!(time(&[0xA0, 0x00, 0x01], none), 10); // MOV AL, [100h]
+        assert_eq!(time(&[0x75, 0x00], none), 4);
+        assert_eq!(time(&[0x75, 0x00], Timing { taken: true, ..none }), 16);
+        assert_eq!(time(&[0xD3, 0xE0], Timing { count: 3, ..none }), 8 + 12); // SHL AX, CL
+        assert_eq!(time(&[0xF3, 0xA4], Timing { repeats: 10, ..none }), 9 + 170); // REP MOVSB
+    }
+
+    #[test]
+    fn odd_words_cost_extra_bus_cycles() {
+        let odd = Timing { odd: true, ..Timing::default() };
+
+        assert_eq!(time(&[0x8B, 0x07], odd), 8 + 5 + 4); // MOV AX, [BX]
+        assert_eq!(time(&[0x01, 0x07], odd), 16 + 5 + 8); // ADD [BX], AX
+        assert_eq!(time(&[0x8A, 0x07], odd), 8 + 5); // MOV AL, [BX]
+
+        let even = decode(&[0x8B, 0x07], 0).unwrap();
+        assert_eq!(cycles(&even, Timing::default(), Model::I8088), 8 + 5 + 4);
+
+        // PUSH AX, CALL and INT 21h move one, one and three words on the stack
+        let on_8088 = |code: &[u8]| cycles(&decode(code, 0).unwrap(), Timing::default(), Model::I8088);
+        assert_eq!(on_8088(&[0x50]), 11 + 4);
+        assert_eq!(on_8088(&[0xE8, 0x00, 0x00]), 19 + 4);
+        assert_eq!(on_8088(&[0xCD, 0x21]), 51 + 12);
+        assert_eq!(time(&[0x50], Timing::default()), 11);
+    }
+}
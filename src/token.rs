@@ -7,6 +7,7 @@ pub enum Token {
         left: Box<Token>,
         right: Option<Box<Token>>,
     },
-    Num(u8),
-    Illegal,
+    /// A number, checked against the operand size where it is used.
+    Num(u32),
+    Mem(u32),
 }
@@ -0,0 +1,326 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[cfg(feature = "gui")]
+use druid::Data;
+
+use crate::memory::Memory;
+
+/// Physical address of the CGA frame buffer at B800:0000.
+pub const CGA_BASE: u32 = 0xB8000;
+/// Size of the CGA frame buffer.
+pub const CGA_SIZE: usize = 0x4000;
+
+/// The 16 colours of the CGA, as RGB.
+pub const CGA_COLORS: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0xAA],
+    [0x00, 0xAA, 0x00],
+    [0x00, 0xAA, 0xAA],
+    [0xAA, 0x00, 0x00],
+    [0xAA, 0x00, 0xAA],
+    [0xAA, 0x55, 0x00],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xFF],
+    [0x55, 0xFF, 0x55],
+    [0x55, 0xFF, 0xFF],
+    [0xFF, 0x55, 0x55],
+    [0xFF, 0x55, 0xFF],
+    [0xFF, 0xFF, 0x55],
+    [0xFF, 0xFF, 0xFF],
+];
+
+/// CGA adapter state. The pixels themselves live in [`Memory`] at B800.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "gui", derive(Data))]
+pub struct Video {
+    pub mode: u8,
+    /// Colour select register, port 3D9h.
+    pub color_select: u8,
+}
+
+impl Default for Video {
+    fn default() -> Self {
+        Self {
+            mode: 3,
+            color_select: 0x30,
+        }
+    }
+}
+
+/// An RGB image of the emulated screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+impl Video {
+    pub fn handles(port: u16) -> bool {
+        port == 0x3D9
+    }
+
+    pub fn write_port(&mut self, port: u16, val: u8) {
+        if port == 0x3D9 {
+            self.color_select = val;
+        }
+    }
+
+    pub fn read_port(&self, port: u16) -> u8 {
+        if port == 0x3D9 {
+            self.color_select
+        } else {
+            0xFF
+        }
+    }
+
+    /// Resolution of the current mode if it is a graphics mode.
+    pub fn resolution(&self) -> Option<(usize, usize)> {
+        match self.mode {
+            4 | 5 => Some((320, 200)),
+            6 => Some((640, 200)),
+            _ => None,
+        }
+    }
+
+    pub fn columns(&self) -> u8 {
+        match self.mode {
+            0 | 1 | 4 | 5 => 40,
+            _ => 80,
+        }
+    }
+
+    /// INT 10h AH=00h. Bit 7 of the mode keeps the frame buffer contents.
+    pub fn set_mode(&mut self, mode: u8, mem: &mut Memory) {
+        self.mode = mode & 0x7F;
+        self.color_select = if self.mode == 6 { 0x3F } else { 0x30 };
+
+        if mode & 0x80 == 0 {
+            mem.fill(CGA_BASE, CGA_SIZE, 0);
+        }
+    }
+
+    fn bits(&self) -> usize {
+        if self.mode == 6 {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Address and shift of the pixel, with even and odd rows interleaved
+    /// 2000h bytes apart as on the real card.
+    fn locate(&self, x: usize, y: usize) -> Option<(u32, usize)> {
+        let (w, h) = self.resolution()?;
+
+        if x >= w || y >= h {
+            return None;
+        }
+
+        let bits = self.bits();
+        let per_byte = 8 / bits;
+        let offset = (y & 1) * 0x2000 + (y >> 1) * 80 + x / per_byte;
+        let shift = (per_byte - 1 - x % per_byte) * bits;
+
+        Some((CGA_BASE + offset as u32, shift))
+    }
+
+    pub fn read_pixel(&self, mem: &Memory, x: usize, y: usize) -> u8 {
+        match self.locate(x, y) {
+            Some((addr, shift)) => (mem.read_byte(addr) >> shift) & ((1 << self.bits()) - 1),
+            None => 0,
+        }
+    }
+
+    /// INT 10h AH=0Ch semantics: bit 7 of the colour XORs instead of setting.
+    pub fn write_pixel(&self, mem: &mut Memory, x: usize, y: usize, color: u8) {
+        if let Some((addr, shift)) = self.locate(x, y) {
+            let mask = ((1u8 << self.bits()) - 1) << shift;
+            let bits = (color << shift) & mask;
+            let old = mem.read_byte(addr);
+
+            let new = if color & 0x80 != 0 {
+                old ^ bits
+            } else {
+                (old & !mask) | bits
+            };
+
+            mem.write_byte(addr, new);
+        }
+    }
+
+    /// Palette index into [`CGA_COLORS`] for each pixel value of the mode.
+    pub fn palette(&self) -> Vec<usize> {
+        let cs = self.color_select as usize;
+        let fg = cs & 0x0F;
+
+        match self.mode {
+            6 => vec![0, fg],
+            _ => {
+                let bright = if cs & 0x10 != 0 { 8 } else { 0 };
+                let colors = if self.mode == 5 {
+                    [3, 4, 7]
+                } else if cs & 0x20 != 0 {
+                    [3, 5, 7]
+                } else {
+                    [2, 4, 6]
+                };
+
+                vec![fg, colors[0] + bright, colors[1] + bright, colors[2] + bright]
+            }
+        }
+    }
+
+    /// Renders the frame buffer, or `None` in text modes.
+    pub fn render(&self, mem: &Memory) -> Option<Frame> {
+        let (width, height) = self.resolution()?;
+        let palette = self.palette();
+        let mut rgb = Vec::with_capacity(width * height * 3);
+
+        for y in 0..height {
+            for x in 0..width {
+                let color = CGA_COLORS[palette[self.read_pixel(mem, x, y) as usize]];
+                rgb.extend_from_slice(&color);
+            }
+        }
+
+        Some(Frame { width, height, rgb })
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for d in data {
+        a = (a + *d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut body = kind.to_vec();
+    body.extend_from_slice(data);
+
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(&body)?;
+    w.write_all(&crc32(&body).to_be_bytes())
+}
+
+impl Frame {
+    /// Writes the frame as an uncompressed (stored deflate) RGB PNG.
+    pub fn write_png<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.rgb.chunks(self.width * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut zlib = vec![0x78, 0x01];
+        let mut blocks = raw.chunks(0xFFFF).peekable();
+        while let Some(block) = blocks.next() {
+            let len = block.len() as u16;
+            zlib.push(blocks.peek().is_none() as u8);
+            zlib.extend_from_slice(&len.to_le_bytes());
+            zlib.extend_from_slice(&(!len).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+        zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(self.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(self.height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        w.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_chunk(&mut w, b"IHDR", &ihdr)?;
+        write_chunk(&mut w, b"IDAT", &zlib)?;
+        write_chunk(&mut w, b"IEND", &[])?;
+
+        w.flush()
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, Video, CGA_BASE};
+    use crate::memory::Memory;
+
+    #[test]
+    fn interleaved_rows() {
+        let mut mem = Memory::default();
+        let mut v = Video::default();
+        v.set_mode(4, &mut mem);
+
+        v.write_pixel(&mut mem, 0, 0, 3);
+        v.write_pixel(&mut mem, 5, 1, 2);
+
+        assert_eq!(mem.read_byte(CGA_BASE), 0b1100_0000);
+        assert_eq!(mem.read_byte(CGA_BASE + 0x2001), 0b0010_0000);
+        assert_eq!(v.read_pixel(&mem, 5, 1), 2);
+
+        v.write_pixel(&mut mem, 5, 1, 0x83);
+        assert_eq!(v.read_pixel(&mem, 5, 1), 1);
+    }
+
+    #[test]
+    fn render_palette() {
+        let mut mem = Memory::default();
+        let mut v = Video::default();
+        v.set_mode(4, &mut mem);
+        v.write_pixel(&mut mem, 1, 0, 1);
+
+        let frame = v.render(&mem).unwrap();
+        assert_eq!((frame.width, frame.height), (320, 200));
+        assert_eq!(&frame.rgb[0..3], &[0, 0, 0]);
+        assert_eq!(&frame.rgb[3..6], &[0x55, 0xFF, 0xFF]);
+
+        v.set_mode(6, &mut mem);
+        mem.write_byte(CGA_BASE, 0x80);
+        let frame = v.render(&mem).unwrap();
+        assert_eq!(frame.width, 640);
+        assert_eq!(&frame.rgb[0..6], &[0xFF, 0xFF, 0xFF, 0, 0, 0]);
+    }
+
+    #[test]
+    fn text_mode_has_no_frame() {
+        assert!(Video::default().render(&Memory::default()).is_none());
+    }
+
+    #[test]
+    fn png_chunks() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+
+        let mut mem = Memory::default();
+        let mut v = Video::default();
+        v.set_mode(6, &mut mem);
+
+        let mut png = Vec::new();
+        v.render(&mem).unwrap().write_png(&mut png).unwrap();
+
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    }
+}